@@ -0,0 +1,291 @@
+//! Measures how fast the server receives a large compressed INSERT.
+//!
+//! ```sh
+//! BENCH_INSERT_MB=512 cargo bench --bench insert
+//! ```
+//!
+//! The client side is encoded by hand, the way `clickhouse-client` sends data: each
+//! block is split into compressed frames of at most 1 MiB of uncompressed data.
+//!
+//! Blocks of plain columns (`UInt64`, `String`) and of composite ones
+//! (`Array(UInt64)`, `Nullable(String)`) are sent. With 512 MiB over loopback, when the
+//! packet is parsed again from its start on each read, when only the size of plain
+//! columns is checked before decoding them, when the size of all of them is, and when
+//! these checks resume where the previous one stopped:
+//!
+//! | columns   | parsed again | plain columns checked | all columns checked | checks resumed |
+//! |-----------|--------------|-----------------------|---------------------|----------------|
+//! | plain     | 159 MiB/s    | 294 MiB/s             | 300 MiB/s           | 415 MiB/s      |
+//! | composite | 102 MiB/s    | 143 MiB/s             | 453 MiB/s           | 546 MiB/s      |
+//!
+//! The last column was measured on another machine, where checking all the columns
+//! without resuming gave 304 and 429 MiB/s.
+
+use std::env;
+use std::error::Error;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use clickhouse_rs_cityhash_sys::city_hash_128;
+use clickhouse_srv::connection::Connection;
+use clickhouse_srv::errors::Result;
+use clickhouse_srv::insert::InsertHandler;
+use clickhouse_srv::types::Block;
+use clickhouse_srv::CHContext;
+use clickhouse_srv::ClickHouseServer;
+use clickhouse_srv::ClickHouseSession;
+use futures::StreamExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+
+const CLIENT_REVISION: u64 = 54405;
+const MAX_COMPRESS_BLOCK_SIZE: usize = 1_048_576;
+const ROWS_PER_BLOCK: usize = 1_048_576;
+const WRITE_SIZE: usize = 64 * 1024;
+
+#[tokio::main]
+async fn main() -> std::result::Result<(), Box<dyn Error>> {
+    let total_mb: usize = env::var("BENCH_INSERT_MB")
+        .ok()
+        .and_then(|mb| mb.parse().ok())
+        .unwrap_or(256);
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            tokio::spawn(ClickHouseServer::run_on_stream(Arc::new(Session), stream));
+        }
+    });
+
+    // about 25 bytes per row: an UInt64 and a short String
+    insert(addr, "plain", &encode_block(ROWS_PER_BLOCK), total_mb).await?;
+    // about 45 bytes per row: two UInt64 in an array and a short String
+    insert(
+        addr,
+        "composite",
+        &encode_composite_block(ROWS_PER_BLOCK),
+        total_mb
+    )
+    .await?;
+    Ok(())
+}
+
+async fn insert(
+    addr: SocketAddr,
+    columns: &str,
+    block: &[u8],
+    total_mb: usize
+) -> std::result::Result<(), Box<dyn Error>> {
+    let blocks = (total_mb * 1024 * 1024 / block.len()).max(1);
+    let data_packet = data_packet(block);
+    let empty_packet = data_packet_of_empty_block();
+
+    let mut stream = TcpStream::connect(addr).await?;
+    let start = Instant::now();
+
+    stream.write_all(&hello_packet()).await?;
+    stream.write_all(&query_packet()).await?;
+    stream.write_all(&empty_packet).await?;
+    for _ in 0..blocks {
+        // the way the data comes from a network, not at once
+        for chunk in data_packet.chunks(WRITE_SIZE) {
+            stream.write_all(chunk).await?;
+            tokio::task::yield_now().await;
+        }
+    }
+    stream.write_all(&empty_packet).await?;
+    stream.shutdown().await?;
+
+    // the server closes the connection once the INSERT is finished
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).await?;
+
+    let elapsed = start.elapsed();
+    let mb = (blocks * block.len()) as f64 / 1024.0 / 1024.0;
+    println!(
+        "{}: inserted {} blocks, {:.0} MiB uncompressed in {:?}: {:.1} MiB/s",
+        columns,
+        blocks,
+        mb,
+        elapsed,
+        mb / elapsed.as_secs_f64()
+    );
+    Ok(())
+}
+
+struct Session;
+
+#[async_trait::async_trait]
+impl ClickHouseSession for Session {
+    async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+        Err("only INSERTs are benchmarked".into())
+    }
+
+    async fn execute_insert(&self, _ctx: &mut CHContext) -> Result<Option<InsertHandler>> {
+        let sample_block = Block::new()
+            .column("id", Vec::<u64>::new())
+            .column("name", Vec::<String>::new());
+        Ok(Some(InsertHandler::new(
+            sample_block,
+            |mut blocks| async move {
+                while blocks.next().await.is_some() {}
+                Ok(())
+            }
+        )))
+    }
+}
+
+fn uvarint(buf: &mut Vec<u8>, mut v: u64) {
+    loop {
+        let mut b = (v & 0x7f) as u8;
+        v >>= 7;
+        if v != 0 {
+            b |= 0x80;
+        }
+        buf.push(b);
+        if v == 0 {
+            break;
+        }
+    }
+}
+
+fn string(buf: &mut Vec<u8>, s: &str) {
+    uvarint(buf, s.len() as u64);
+    buf.extend_from_slice(s.as_bytes());
+}
+
+fn hello_packet() -> Vec<u8> {
+    let mut buf = Vec::new();
+    uvarint(&mut buf, 0);
+    string(&mut buf, "bench");
+    uvarint(&mut buf, 1);
+    uvarint(&mut buf, 1);
+    uvarint(&mut buf, CLIENT_REVISION);
+    string(&mut buf, "default");
+    string(&mut buf, "default");
+    string(&mut buf, "");
+    buf
+}
+
+fn query_packet() -> Vec<u8> {
+    let mut buf = Vec::new();
+    uvarint(&mut buf, 1);
+    string(&mut buf, "bench");
+    // no client info
+    buf.push(0);
+    // end of settings
+    string(&mut buf, "");
+    // stage: complete
+    uvarint(&mut buf, 2);
+    // compression: enabled
+    uvarint(&mut buf, 1);
+    string(&mut buf, "INSERT INTO bench VALUES");
+    buf
+}
+
+fn block_header(buf: &mut Vec<u8>, columns: u64, rows: u64) {
+    uvarint(buf, 1);
+    buf.push(0);
+    uvarint(buf, 2);
+    buf.extend_from_slice(&(-1_i32).to_le_bytes());
+    uvarint(buf, 0);
+
+    uvarint(buf, columns);
+    uvarint(buf, rows);
+}
+
+// Random values, so the data doesn't compress too well.
+fn random() -> impl FnMut() -> u64 {
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 7;
+        seed ^= seed << 17;
+        seed
+    }
+}
+
+fn encode_block(rows: usize) -> Vec<u8> {
+    let mut random = random();
+
+    let mut buf = Vec::new();
+    block_header(&mut buf, 2, rows as u64);
+
+    string(&mut buf, "id");
+    string(&mut buf, "UInt64");
+    for _ in 0..rows {
+        buf.extend_from_slice(&random().to_le_bytes());
+    }
+
+    string(&mut buf, "name");
+    string(&mut buf, "String");
+    for _ in 0..rows {
+        string(&mut buf, &format!("{:x}", random()));
+    }
+    buf
+}
+
+fn encode_composite_block(rows: usize) -> Vec<u8> {
+    let mut random = random();
+
+    let mut buf = Vec::new();
+    block_header(&mut buf, 2, rows as u64);
+
+    string(&mut buf, "ids");
+    string(&mut buf, "Array(UInt64)");
+    for row in 0..rows {
+        buf.extend_from_slice(&(2 * (row as u64 + 1)).to_le_bytes());
+    }
+    for _ in 0..2 * rows {
+        buf.extend_from_slice(&random().to_le_bytes());
+    }
+
+    string(&mut buf, "name");
+    string(&mut buf, "Nullable(String)");
+    let names: Vec<_> = (0..rows).map(|_| random()).collect();
+    // every 8th name is NULL
+    buf.extend(names.iter().map(|name| (name % 8 == 0) as u8));
+    for name in &names {
+        match name % 8 {
+            0 => string(&mut buf, ""),
+            _ => string(&mut buf, &format!("{:x}", name))
+        }
+    }
+    buf
+}
+
+fn data_packet(block: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    uvarint(&mut buf, 2);
+    // temporary table
+    string(&mut buf, "");
+    for chunk in block.chunks(MAX_COMPRESS_BLOCK_SIZE) {
+        compress_frame(&mut buf, chunk);
+    }
+    buf
+}
+
+fn data_packet_of_empty_block() -> Vec<u8> {
+    let mut block = Vec::new();
+    block_header(&mut block, 0, 0);
+    data_packet(&block)
+}
+
+fn compress_frame(buf: &mut Vec<u8>, data: &[u8]) {
+    let compressed = lz4::block::compress(data, None, false).unwrap();
+
+    let mut frame = vec![0x82];
+    frame.extend_from_slice(&((compressed.len() + 9) as u32).to_le_bytes());
+    frame.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&compressed);
+
+    let hash = city_hash_128(&frame);
+    buf.extend_from_slice(&hash.lo.to_le_bytes());
+    buf.extend_from_slice(&hash.hi.to_le_bytes());
+    buf.extend_from_slice(&frame);
+}
@@ -7,6 +7,8 @@ use std::time::Instant;
 
 use clickhouse_srv::connection::Connection;
 use clickhouse_srv::errors::Result;
+use clickhouse_srv::insert::InsertHandler;
+use clickhouse_srv::logs::ClientLogger;
 use clickhouse_srv::types::Block;
 use clickhouse_srv::types::Progress;
 use clickhouse_srv::CHContext;
@@ -17,41 +19,35 @@ use futures::Stream;
 use futures::StreamExt;
 use log::debug;
 use log::info;
-use tokio::net::TcpListener;
-use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
+use log::LevelFilter;
 
 extern crate clickhouse_srv;
 
 #[tokio::main]
 async fn main() -> std::result::Result<(), Box<dyn Error>> {
     env::set_var("RUST_LOG", "clickhouse_srv=debug");
-    env_logger::init();
+    // records logged while a query runs are also sent to clients with `send_logs_level`
+    ClientLogger::init(
+        env_logger::Builder::from_default_env().build(),
+        LevelFilter::Trace
+    )?;
     let host_port = "127.0.0.1:9000";
 
-    // Note that this is the Tokio TcpListener, which is fully async.
-    let listener = TcpListener::bind(host_port).await?;
+    // Every connection gets its own session, at most 100 of them are open at once.
+    let listener = ClickHouseServer::builder(|_peer| {
+        Arc::new(Session {
+            last_progress_send: Instant::now()
+        })
+    })
+    .bind(host_port)
+    .max_connections(100)
+    .listen()
+    .await?;
 
     info!("Server start at {}", host_port);
 
-    loop {
-        // Asynchronously wait for an inbound TcpStream.
-        let (stream, _) = listener.accept().await?;
-
-        // Spawn our handler to be run asynchronously.
-        tokio::spawn(async move {
-            if let Err(e) = ClickHouseServer::run_on_stream(
-                Arc::new(Session {
-                    last_progress_send: Instant::now()
-                }),
-                stream
-            )
-            .await
-            {
-                println!("Error: {:?}", e);
-            }
-        });
-    }
+    listener.run().await?;
+    Ok(())
 }
 
 struct Session {
@@ -60,25 +56,19 @@ struct Session {
 
 #[async_trait::async_trait]
 impl clickhouse_srv::ClickHouseSession for Session {
-    async fn execute_query(&self, ctx: &mut CHContext, connection: &mut Connection) -> Result<()> {
-        let query = ctx.state.query.clone();
-        debug!("Receive query {}", query);
-
-        let start = Instant::now();
-
+    async fn execute_insert(&self, ctx: &mut CHContext) -> Result<Option<InsertHandler>> {
         // simple logic for insert
-        if query.starts_with("INSERT") || query.starts_with("insert") {
-            // ctx.state.out
-            let sample_block = Block::new().column("abc", Vec::<u32>::new());
-            let (sender, rec) = mpsc::channel(4);
-            ctx.state.out = Some(sender);
-            connection.write_block(&sample_block).await?;
-
-            let sent_all_data = ctx.state.sent_all_data.clone();
-            tokio::spawn(async move {
+        let query = ctx.state.query.to_lowercase();
+        if !query.starts_with("insert") {
+            return Ok(None);
+        }
+
+        let sample_block = Block::new().column("abc", Vec::<u32>::new());
+        Ok(Some(InsertHandler::new(
+            sample_block,
+            |mut blocks| async move {
                 let mut rows = 0;
-                let mut stream = ReceiverStream::new(rec);
-                while let Some(block) = stream.next().await {
+                while let Some(block) = blocks.next().await {
                     rows += block.row_count();
                     println!(
                         "got insert block: {:?}, total_rows: {}",
@@ -86,10 +76,16 @@ impl clickhouse_srv::ClickHouseSession for Session {
                         rows
                     );
                 }
-                sent_all_data.notify_one();
-            });
-            return Ok(());
-        }
+                Ok(())
+            }
+        )))
+    }
+
+    async fn execute_query(&self, ctx: &mut CHContext, connection: &mut Connection) -> Result<()> {
+        let query = ctx.state.query.clone();
+        debug!("Receive query {}", query);
+
+        let start = Instant::now();
 
         let mut clickhouse_stream = SimpleBlockStream {
             idx: 0,
@@ -99,14 +95,20 @@ impl clickhouse_srv::ClickHouseSession for Session {
         };
 
         while let Some(block) = clickhouse_stream.next().await {
+            // the client pressed Ctrl+C
+            if ctx.state.is_cancelled() {
+                debug!("query {} is cancelled", query);
+                break;
+            }
+
             let block = block?;
             connection.write_block(&block).await?;
+            // give the server a chance to read a Cancel packet
+            tokio::task::yield_now().await;
 
             if self.last_progress_send.elapsed() >= Duration::from_millis(10) {
                 let progress = self.get_progress();
-                connection
-                    .write_progress(progress, ctx.client_revision)
-                    .await?;
+                connection.write_query_progress(progress).await?;
             }
         }
 
@@ -151,7 +153,8 @@ impl clickhouse_srv::ClickHouseSession for Session {
         Progress {
             rows: 100,
             bytes: 1000,
-            total_rows: 1000
+            total_rows: 1000,
+            ..Default::default()
         }
     }
 }
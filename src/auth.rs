@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use lazy_static::lazy_static;
+use sha2::Digest;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::error_codes;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::errors::ServerError;
+
+lazy_static! {
+    // checked instead of the password of a user which doesn't exist, its result is ignored
+    static ref UNKNOWN_USER_PASSWORD: Password = Password::Sha256Hex("0".repeat(64));
+}
+
+/// Checks the credentials sent by a client in the `Hello` packet, see
+/// [`ClickHouseSession::authenticator`](crate::ClickHouseSession::authenticator).
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, user: &str, password: &str, peer_addr: Option<SocketAddr>)
+        -> Result<()>;
+}
+
+/// The password of a user.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Password {
+    /// Any password is accepted.
+    NoPassword,
+    PlainText(String),
+    /// Hex encoded SHA256 of the password, as `password_sha256_hex` in ClickHouse `users.xml`.
+    Sha256Hex(String)
+}
+
+impl Password {
+    /// Hashes the given password, so it doesn't have to be kept in memory.
+    pub fn sha256(password: &str) -> Self {
+        Password::Sha256Hex(sha256_hex(password))
+    }
+
+    fn check(&self, password: &str) -> bool {
+        match self {
+            Password::NoPassword => true,
+            // in constant time, so the time taken doesn't tell how much of it is right
+            Password::PlainText(expected) => expected.as_bytes().ct_eq(password.as_bytes()).into(),
+            Password::Sha256Hex(expected) => {
+                let expected = expected.to_ascii_lowercase();
+                expected
+                    .as_bytes()
+                    .ct_eq(sha256_hex(password).as_bytes())
+                    .into()
+            }
+        }
+    }
+}
+
+/// An authenticator backed by a fixed table of users.
+///
+/// ```rust
+/// # use clickhouse_srv::auth::{Password, StaticUsers};
+/// let users = StaticUsers::new()
+///     .user("default", Password::NoPassword)
+///     .user("admin", Password::sha256("secret"));
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct StaticUsers {
+    users: HashMap<String, Password>
+}
+
+impl StaticUsers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a user to the table, replacing any user with the same name.
+    pub fn user(mut self, name: impl Into<String>, password: Password) -> Self {
+        self.users.insert(name.into(), password);
+        self
+    }
+}
+
+impl Authenticator for StaticUsers {
+    fn authenticate(
+        &self,
+        user: &str,
+        password: &str,
+        _peer_addr: Option<SocketAddr>
+    ) -> Result<()> {
+        // the same error and about the same time for both, so clients can't find out which
+        // users exist
+        let expected = self.users.get(user);
+        let matches = expected.unwrap_or(&UNKNOWN_USER_PASSWORD).check(password);
+        match expected {
+            Some(_) if matches => Ok(()),
+            _ => Err(authentication_failed(user))
+        }
+    }
+}
+
+pub fn authentication_failed(user: &str) -> Error {
+    Error::Server(ServerError {
+        name: "AUTHENTICATION_FAILED".to_string(),
+        code: error_codes::AUTHENTICATION_FAILED,
+        message: format!(
+            "{}: Authentication failed: password is incorrect or there is no user with such name",
+            user
+        ),
+        stack_trace: "".to_string()
+    })
+}
+
+fn sha256_hex(password: &str) -> String {
+    Sha256::digest(password.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error_code(res: Result<()>) -> u32 {
+        match res {
+            Err(Error::Server(e)) => e.code,
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(
+            sha256_hex("password"),
+            "5e884898da28047151d0e56f8dc6292773603d0d6aabbdd62a11ef721d1542d8"
+        );
+    }
+
+    #[test]
+    fn test_static_users() {
+        let users = StaticUsers::new()
+            .user("default", Password::NoPassword)
+            .user("plain", Password::PlainText("qwerty".to_string()))
+            .user(
+                "hashed",
+                Password::Sha256Hex(
+                    "5E884898DA28047151D0E56F8DC6292773603D0D6AABBDD62A11EF721D1542D8".to_string()
+                )
+            );
+
+        assert!(users.authenticate("default", "anything", None).is_ok());
+        assert!(users.authenticate("plain", "qwerty", None).is_ok());
+        assert!(users.authenticate("hashed", "password", None).is_ok());
+        assert!(users.authenticate("plain", "qwert", None).is_err());
+
+        assert_eq!(
+            error_code(users.authenticate("plain", "", None)),
+            error_codes::AUTHENTICATION_FAILED
+        );
+        assert_eq!(
+            error_code(users.authenticate("hashed", "qwerty", None)),
+            error_codes::AUTHENTICATION_FAILED
+        );
+        assert_eq!(
+            error_code(users.authenticate("nobody", "", None)),
+            error_codes::AUTHENTICATION_FAILED
+        );
+    }
+}
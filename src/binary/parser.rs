@@ -1,6 +1,7 @@
 use std::io::Read;
 
 use chrono_tz::Tz;
+use uuid::Uuid;
 
 use crate::binary::ReadEx;
 use crate::errors::DriverError;
@@ -8,6 +9,7 @@ use crate::errors::Error;
 use crate::errors::Result;
 use crate::protocols::HelloRequest;
 use crate::protocols::Packet;
+use crate::protocols::QualifiedTableName;
 use crate::protocols::QueryRequest;
 use crate::protocols::{self};
 use crate::types::Block;
@@ -32,17 +34,25 @@ impl<'a, T: Read> Parser<T> {
     pub(crate) fn parse_packet(
         &mut self,
         hello: &Option<HelloRequest>,
+        client_revision: u64,
         compress: bool
     ) -> Result<Packet> {
         let packet = self.reader.read_uvarint()?;
         match packet {
             protocols::CLIENT_PING => Ok(Packet::Ping),
             protocols::CLIENT_CANCEL => Ok(Packet::Cancel),
-            protocols::CLIENT_DATA | protocols::CLIENT_SCALAR => {
-                Ok(self.parse_data(packet == protocols::CLIENT_SCALAR, compress)?)
-            }
-            protocols::CLIENT_QUERY => Ok(self.parse_query(hello, compress)?),
+            protocols::CLIENT_KEEP_ALIVE => Ok(Packet::KeepAlive),
+            protocols::CLIENT_INGORED_PART_UUIDS => Ok(self.parse_ignored_part_uuids()?),
+            protocols::CLIENT_DATA | protocols::CLIENT_SCALAR => Ok(self.parse_data(
+                packet == protocols::CLIENT_SCALAR,
+                client_revision,
+                compress
+            )?),
+            protocols::CLIENT_QUERY => Ok(self.parse_query(hello, client_revision, compress)?),
             protocols::CLIENT_HELLO => Ok(self.parse_hello()?),
+            protocols::CLIENT_TABLES_STATUS_REQUEST => {
+                Ok(self.parse_tables_status_request(client_revision)?)
+            }
 
             _ => Err(Error::Driver(DriverError::UnknownPacket { packet }))
         }
@@ -52,19 +62,46 @@ impl<'a, T: Read> Parser<T> {
         Ok(Packet::Hello(HelloRequest::read_from(&mut self.reader)?))
     }
 
-    fn parse_query(&mut self, hello: &Option<HelloRequest>, _compress: bool) -> Result<Packet> {
+    fn parse_query(
+        &mut self,
+        hello: &Option<HelloRequest>,
+        client_revision: u64,
+        _compress: bool
+    ) -> Result<Packet> {
         match hello {
             Some(ref hello) => {
-                let query = QueryRequest::read_from(&mut self.reader, hello)?;
-                Ok(Packet::Query(query))
+                let query = QueryRequest::read_from(&mut self.reader, hello, client_revision)?;
+                Ok(Packet::Query(Box::new(query)))
             }
             _ => Err(Error::Driver(DriverError::UnexpectedPacket))
         }
     }
 
-    fn parse_data(&mut self, _scalar: bool, compress: bool) -> Result<Packet> {
-        let _temporary_table = self.reader.read_string()?;
-        let block = Block::load(&mut self.reader, self.tz, compress)?;
-        Ok(Packet::Data(block))
+    fn parse_tables_status_request(&mut self, client_revision: u64) -> Result<Packet> {
+        if client_revision < protocols::DBMS_MIN_REVISION_WITH_TABLES_STATUS {
+            return Err(Error::Driver(DriverError::UnexpectedPacket));
+        }
+        let tables = QualifiedTableName::read_list(&mut self.reader)?;
+        Ok(Packet::TablesStatusRequest(tables))
+    }
+
+    fn parse_ignored_part_uuids(&mut self) -> Result<Packet> {
+        let count = self.reader.read_uvarint()?;
+        let mut uuids = Vec::new();
+        for _ in 0..count {
+            let mut bytes = [0_u8; 16];
+            self.reader.read_bytes(&mut bytes)?;
+            // two little-endian halves, the high one first
+            bytes[..8].reverse();
+            bytes[8..].reverse();
+            uuids.push(Uuid::from_bytes(bytes));
+        }
+        Ok(Packet::IgnoredPartUUIDs(uuids))
+    }
+
+    fn parse_data(&mut self, scalar: bool, client_revision: u64, compress: bool) -> Result<Packet> {
+        let name = self.reader.read_string()?;
+        let block = Block::load(&mut self.reader, self.tz, compress, client_revision)?;
+        Ok(Packet::data(scalar, name, block))
     }
 }
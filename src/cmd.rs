@@ -1,12 +1,26 @@
+use std::sync::Arc;
+
 use log::debug;
+use log::LevelFilter;
 
 use crate::binary::Encoder;
 use crate::connection::Connection;
+use crate::connection::PacketReader;
 use crate::errors::Result;
+use crate::insert::InsertHandler;
+use crate::insert::InsertSink;
+use crate::logs::parse_send_logs_level;
+use crate::logs::LogQueue;
 use crate::protocols::HelloResponse;
 use crate::protocols::Packet;
 use crate::protocols::Stage;
+use crate::protocols::TablesStatusResponse;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM;
+use crate::protocols::DBMS_MIN_REVISION_WITH_SERVER_LOGS;
+use crate::protocols::DBMS_TCP_PROTOCOL_VERSION;
+use crate::protocols::SERVER_END_OF_STREAM;
 use crate::protocols::SERVER_PONG;
+use crate::query_stream::send_query_stream;
 use crate::CHContext;
 
 pub struct Cmd {
@@ -18,70 +32,142 @@ impl Cmd {
         Self { packet }
     }
 
-    pub async fn apply(self, connection: &mut Connection, ctx: &mut CHContext) -> Result<()> {
+    pub async fn apply(
+        self,
+        reader: &mut PacketReader,
+        connection: &mut Connection,
+        ctx: &mut CHContext
+    ) -> Result<()> {
         let mut encoder = Encoder::new();
         match self.packet {
             Packet::Ping => {
                 encoder.uvarint(SERVER_PONG);
             }
-            // todo cancel
-            Packet::Cancel => {}
-            Packet::Hello(hello) => {
+            Packet::Cancel => {
+                ctx.state.cancel_token.cancel();
+                // an INSERT is in progress, drop its sink and finish the query.
+                if let Stage::InsertStarted = ctx.state.stage {
+                    ctx.state.reset();
+                    encoder.uvarint(SERVER_END_OF_STREAM);
+                }
+            }
+            Packet::Hello(mut hello) => {
+                let session = connection.session.clone();
+                if let Err(e) = session
+                    .authenticate(&hello.user, &hello.password, ctx.peer_addr)
+                    .await
+                {
+                    debug!("authentication failed for user {}", hello.user);
+                    connection.write_error(&e).await?;
+                    return Err(e);
+                }
+
+                match session.on_hello(&hello).await {
+                    Ok(Some(scoped)) => connection.session = scoped,
+                    Ok(None) => {}
+                    Err(e) => {
+                        connection.write_error(&e).await?;
+                        return Err(e);
+                    }
+                }
+
+                // a session can't advertise a revision whose fields aren't known here
+                let revision = connection
+                    .session
+                    .dbms_tcp_protocol_version()
+                    .min(DBMS_TCP_PROTOCOL_VERSION);
                 let response = HelloResponse {
                     dbms_name: connection.session.dbms_name().to_string(),
                     dbms_version_major: connection.session.dbms_version_major(),
                     dbms_version_minor: connection.session.dbms_version_minor(),
-                    dbms_tcp_protocol_version: connection.session.dbms_tcp_protocol_version(),
+                    dbms_tcp_protocol_version: revision,
                     timezone: connection.session.timezone().to_string(),
                     server_display_name: connection.session.server_display_name().to_string(),
-                    dbms_version_patch: connection.session.dbms_version_patch()
+                    dbms_version_patch: connection.session.dbms_version_patch(),
+                    password_complexity_rules: connection.session.password_complexity_rules(),
+                    // from a CSPRNG, the client signs it for interserver secrets
+                    nonce: rand::random()
                 };
 
-                ctx.client_revision = connection
-                    .session
-                    .dbms_tcp_protocol_version()
-                    .min(hello.client_revision);
-                ctx.hello = Some(hello.clone());
+                ctx.client_revision = revision.min(hello.client_revision);
+                connection.client_revision = ctx.client_revision;
 
                 response.encode(&mut encoder, ctx.client_revision)?;
+                connection.write_bytes(encoder.get_buffer()).await?;
+
+                if ctx.client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM {
+                    hello.quota_key = reader.read_addendum().await?;
+                }
+                ctx.hello = Some(hello);
+                return Ok(());
             }
-            Packet::Query(query) => {
+            Packet::Query(mut query) => {
+                ctx.state.reset();
+                ctx.state.query_id = query.query_id.clone();
                 ctx.state.query = query.query.clone();
                 ctx.state.compression = query.compression;
+                ctx.settings = query.settings.clone();
+                if query.client_info.initial_address.is_empty() {
+                    if let Some(peer_addr) = ctx.peer_addr {
+                        query.client_info.initial_address = peer_addr.to_string();
+                    }
+                }
+                connection.compression = if query.compression > 0 {
+                    Some(connection.session.compression_method(&ctx.settings))
+                } else {
+                    None
+                };
+                ctx.query = Some(*query);
 
-                let session = connection.session.clone();
-                session.execute_query(ctx, connection).await?;
+                Self::read_external_data(reader, ctx).await?;
+                if ctx.state.is_connection_closed {
+                    return Ok(());
+                }
 
-                if let Some(_) = &ctx.state.out {
-                    ctx.state.stage = Stage::InsertPrepare;
-                } else {
-                    connection.write_end_of_stream().await?;
+                let res = Self::execute_query(reader, connection, ctx).await;
+                // they only apply to this query
+                ctx.ignored_part_uuids.clear();
+                return res;
+            }
+            Packet::TablesStatusRequest(tables) => {
+                let session = connection.session.clone();
+                match session.tables_status(ctx, &tables).await {
+                    Ok(statuses) => {
+                        TablesStatusResponse { statuses }.encode(&mut encoder, ctx.client_revision)
+                    }
+                    Err(e) => connection.write_error(&e).await?
                 }
             }
             Packet::Data(block) => {
-                if block.is_empty() {
-                    match ctx.state.stage {
-                        Stage::InsertPrepare => {
-                            ctx.state.stage = Stage::InsertStarted;
+                if let Stage::InsertStarted = ctx.state.stage {
+                    if block.is_empty() {
+                        // all the data was sent, the sink may have returned before
+                        ctx.state.stage = Stage::Default;
+                        let res = match ctx.state.insert.take() {
+                            Some(sink) => sink.finish().await,
+                            None => Ok(())
+                        };
+                        match res {
+                            Ok(()) => connection.write_end_of_stream().await?,
+                            Err(e) => connection.write_error(&e).await?
                         }
-                        Stage::InsertStarted => {
-                            // reset will reset the out, so the outer stream will break
-                            ctx.state.reset();
-
-                            ctx.state.sent_all_data.notified().await;
-                            // wait stream finished
-                            connection.write_end_of_stream().await?;
+                    } else if let Some(mut sink) = ctx.state.insert.take() {
+                        if sink.send(block).await {
+                            ctx.state.insert = Some(sink);
+                        } else if let Err(e) = sink.finish().await {
+                            // the client stops sending on the exception, the rest is ignored
                             ctx.state.stage = Stage::Default;
+                            connection.write_error(&e).await?;
                         }
-                        _ => {}
-                    }
-                } else {
-                    if let Some(out) = &ctx.state.out {
-                        // out.block_stream.
-                        out.send(block).await.unwrap();
                     }
                 }
             }
+            Packet::KeepAlive => {}
+            Packet::IgnoredPartUUIDs(uuids) => {
+                ctx.ignored_part_uuids = uuids;
+            }
+            // only expected right after a query, see `read_external_data`
+            Packet::ExternalTable(..) | Packet::Scalar(..) => {}
         };
 
         let bytes = encoder.get_buffer();
@@ -90,4 +176,137 @@ impl Cmd {
         }
         Ok(())
     }
+
+    /// Collects the scalars and the external tables the client sends after the query into
+    /// `CHContext::scalars` and `CHContext::external_tables`, up to the empty block which
+    /// ends them.
+    async fn read_external_data(reader: &mut PacketReader, ctx: &mut CHContext) -> Result<()> {
+        ctx.scalars.clear();
+        ctx.external_tables.clear();
+        let hello = ctx.hello.clone();
+
+        loop {
+            match reader.read_packet(&hello, ctx.client_revision).await? {
+                Some(Packet::Scalar(name, block)) => {
+                    ctx.scalars.insert(name, block);
+                }
+                Some(Packet::ExternalTable(table, block)) => {
+                    ctx.external_tables.entry(table).or_default().push(block);
+                }
+                Some(Packet::Data(block)) if block.is_empty() => return Ok(()),
+                // e.g. `Cancel`, handled while the query runs
+                Some(packet) => {
+                    reader.defer(packet);
+                    return Ok(());
+                }
+                None => {
+                    ctx.state.is_connection_closed = true;
+                    return Ok(());
+                }
+            }
+        }
+    }
+
+    /// Runs the query on the session while still reading packets from the client,
+    /// so a `Cancel` (or a closed socket) fires `QueryState::cancel_token`. The header of
+    /// an INSERT is sent here, its data is received by `apply`. An error of the session is
+    /// sent to the client and the connection is kept, only errors of the connection itself
+    /// are returned.
+    async fn execute_query(
+        reader: &mut PacketReader,
+        connection: &mut Connection,
+        ctx: &mut CHContext
+    ) -> Result<()> {
+        let session = connection.session.clone();
+        let hello = ctx.hello.clone();
+        let client_revision = ctx.client_revision;
+        let cancel_token = ctx.state.cancel_token.clone();
+
+        // forward the records of the `log` crate to the client, see `logs::ClientLogger`
+        let level = match ctx.settings.get_str("send_logs_level") {
+            Some(level) if ctx.client_revision >= DBMS_MIN_REVISION_WITH_SERVER_LOGS => {
+                parse_send_logs_level(level)
+            }
+            _ => LevelFilter::Off
+        };
+        let logs = Arc::new(LogQueue::new(level, ctx.state.query_id.clone()));
+        connection.logs = Some(logs.clone());
+
+        let mut read_error = None;
+        let mut closed = false;
+
+        let res: Result<Option<InsertHandler>> = {
+            let query = logs.scope(async {
+                match session.execute_insert(ctx).await? {
+                    Some(handler) => {
+                        connection.write_block(handler.header()).await?;
+                        Ok(Some(handler))
+                    }
+                    None => {
+                        match session.query_stream(ctx).await? {
+                            Some(stream) => send_query_stream(stream, ctx, connection).await?,
+                            None => session.execute_query(ctx, connection).await?
+                        }
+                        Ok(None)
+                    }
+                }
+            });
+            tokio::pin!(query);
+
+            loop {
+                tokio::select! {
+                    res = &mut query => break res,
+                    packet = reader.read_packet(&hello, client_revision), if !closed => {
+                        match packet {
+                            Ok(Some(Packet::Cancel)) => {
+                                debug!("query is cancelled by client");
+                                cancel_token.cancel();
+                            }
+                            Ok(Some(Packet::KeepAlive)) => {}
+                            // handled once the query is finished
+                            Ok(Some(packet)) => reader.defer(packet),
+                            Ok(None) => {
+                                closed = true;
+                                cancel_token.cancel();
+                            }
+                            Err(e) => {
+                                closed = true;
+                                read_error = Some(e);
+                                cancel_token.cancel();
+                            }
+                        }
+                    }
+                }
+            }
+        };
+
+        if !closed {
+            connection.write_pending_logs().await?;
+        }
+        connection.logs = None;
+
+        if let Some(e) = read_error {
+            // the packets can't be told apart anymore
+            connection.write_error(&e).await?;
+            return Err(e);
+        }
+        ctx.state.is_connection_closed = closed;
+        if closed {
+            return Ok(());
+        }
+
+        match res {
+            // the data of the INSERT follows
+            Ok(Some(handler)) if !ctx.state.is_cancelled() => {
+                ctx.state.insert = Some(InsertSink::start(handler));
+                ctx.state.stage = Stage::InsertStarted;
+                Ok(())
+            }
+            Ok(_) => connection.write_end_of_stream().await,
+            Err(e) => {
+                debug!("query failed: {}", e);
+                connection.write_error(&e).await
+            }
+        }
+    }
 }
@@ -1,88 +1,111 @@
+use std::borrow::Cow;
+use std::collections::VecDeque;
 use std::io::Cursor;
 use std::sync::Arc;
 
 use bytes::Buf;
 use bytes::BytesMut;
 use chrono_tz::Tz;
+use tokio::io::AsyncRead;
 use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWrite;
 use tokio::io::AsyncWriteExt;
 use tokio::io::BufWriter;
-use tokio::net::TcpStream;
 
 use crate::binary::Encoder;
 use crate::binary::Parser;
+use crate::binary::ReadEx;
 use crate::errors::Error;
 use crate::errors::Result;
+use crate::logs::LogQueue;
+use crate::logs::ServerLog;
 use crate::protocols::ExceptionResponse;
+use crate::protocols::HelloRequest;
 use crate::protocols::Packet;
+use crate::protocols::CLIENT_DATA;
+use crate::protocols::CLIENT_SCALAR;
+use crate::protocols::DBMS_MIN_REVISION_WITH_LOW_CARDINALITY_TYPE;
+use crate::protocols::DBMS_MIN_REVISION_WITH_SERVER_LOGS;
 use crate::protocols::SERVER_END_OF_STREAM;
+use crate::protocols::SERVER_EXTREMES;
+use crate::protocols::SERVER_LOG;
+use crate::protocols::SERVER_TOTALS;
 use crate::types::Block;
+use crate::types::BlockDecoder;
+use crate::types::CompressionMethod;
+use crate::types::ProfileInfo;
 use crate::types::Progress;
-use crate::CHContext;
 use crate::ClickHouseSession;
 
-/// Send and receive `Packet` values from a remote peer.
+/// Receive `Packet` values from a remote peer.
 ///
 /// When implementing networking protocols, a message on that protocol is
 /// often composed of several smaller messages known as frames. The purpose of
-/// `Connection` is to read and write frames on the underlying `TcpStream`.
+/// `PacketReader` is to read frames from the read half of the transport.
 ///
-/// To read frames, the `Connection` uses an internal buffer, which is filled
+/// To read frames, the `PacketReader` uses an internal buffer, which is filled
 /// up until there are enough bytes to create a full frame. Once this happens,
-/// the `Connection` creates the frame and returns it to the caller.
+/// the `PacketReader` creates the frame and returns it to the caller.
 ///
-/// When sending frames, the frame is first encoded into the write buffer.
-/// The contents of the write buffer are then written to the socket.
-pub struct Connection {
-    // The `TcpStream`. It is decorated with a `BufWriter`, which provides write
-    // level buffering. The `BufWriter` implementation provided by Tokio is
-    // sufficient for our needs.
-    pub buffer: BytesMut,
-
-    stream: BufWriter<TcpStream>,
-    pub session: Arc<dyn ClickHouseSession>,
+/// The reader is kept apart from [`Connection`], so that packets (e.g. `Cancel`)
+/// can still be received while a session is writing the result of a query.
+pub struct PacketReader {
+    stream: Box<dyn AsyncRead + Send + Unpin>,
 
     // The buffer for reading frames.
+    buffer: BytesMut,
+    // Packets which were received while a query was running and which must be
+    // handled once it finished.
+    deferred: VecDeque<Packet>,
+    // The block of the `Data` or `Scalar` packet being received, with the type of the
+    // packet and the name of the external table or of the scalar.
+    pending: Option<(u64, String, BlockDecoder)>,
+
     tz: Tz,
-    with_stack_trace: bool,
     compress: bool
 }
 
-impl Connection {
-    /// Create a new `Connection`, backed by `socket`. Read and write buffers
-    /// are initialized.
-    pub fn new(
-        stream: TcpStream,
-        session: Arc<dyn ClickHouseSession>,
-        timezone: String
-    ) -> Result<Connection> {
-        let tz: Tz = timezone.parse()?;
-        Ok(Connection {
-            stream: BufWriter::new(stream),
+impl PacketReader {
+    pub(crate) fn new<R>(stream: R, tz: Tz) -> PacketReader
+    where R: AsyncRead + Send + Unpin + 'static {
+        PacketReader {
+            stream: Box::new(stream),
             buffer: BytesMut::with_capacity(4 * 1024),
-            session,
+            deferred: VecDeque::new(),
+            pending: None,
             tz,
-            with_stack_trace: false,
             compress: true
-        })
+        }
     }
 
     /// Read a single `Packet` value from the underlying stream.
     ///
     /// The function waits until it has retrieved enough data to parse a frame.
     /// Any data remaining in the read buffer after the frame has been parsed is
-    /// kept there for the next call to `read_packet`.
+    /// kept there for the next call to `read_packet`. Deferred packets are
+    /// returned first.
+    ///
+    /// This method is cancel safe: if it is used in `tokio::select!` and another
+    /// branch completes first, no data is lost.
     ///
     /// # Returns
     ///
-    /// On success, the received frame is returned. If the `TcpStream`
+    /// On success, the received frame is returned. If the transport
     /// is closed in a way that doesn't break a frame in half, it returns
     /// `None`. Otherwise, an error is returned.
-    pub async fn read_packet(&mut self, ctx: &mut CHContext) -> crate::Result<Option<Packet>> {
+    pub async fn read_packet(
+        &mut self,
+        hello: &Option<HelloRequest>,
+        client_revision: u64
+    ) -> crate::Result<Option<Packet>> {
+        if let Some(packet) = self.deferred.pop_front() {
+            return Ok(Some(packet));
+        }
+
         loop {
             // Attempt to parse a frame from the buffered data. If enough data
             // has been buffered, the frame is returned.
-            if let Some(frame) = self.parse_packet(ctx)? {
+            if let Some(frame) = self.parse_packet(hello, client_revision)? {
                 return Ok(Some(frame));
             }
 
@@ -105,11 +128,55 @@ impl Connection {
         }
     }
 
+    /// Reads the addendum the client sends once it received the `Hello` of the server,
+    /// since `DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM`. It's made of the quota key.
+    pub(crate) async fn read_addendum(&mut self) -> crate::Result<String> {
+        loop {
+            let mut buf = Cursor::new(&self.buffer[..]);
+            match buf.read_string() {
+                Ok(quota_key) => {
+                    let len = buf.position() as usize;
+                    self.buffer.advance(len);
+                    return Ok(quota_key);
+                }
+                Err(err) if err.is_would_block() => {}
+                Err(e) => return Err(e)
+            }
+
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return Err("connection reset by peer".into());
+            }
+        }
+    }
+
+    /// Keep a packet which can't be handled right now, it will be returned by
+    /// the next call to `read_packet`.
+    pub(crate) fn defer(&mut self, packet: Packet) {
+        self.deferred.push_back(packet);
+    }
+
     /// Tries to parse a frame from the buffer. If the buffer contains enough
     /// data, the frame is returned and the data removed from the buffer. If not
     /// enough data has been buffered yet, `Ok(None)` is returned. If the
     /// buffered data does not represent a valid frame, `Err` is returned.
-    fn parse_packet(&mut self, ctx: &mut CHContext) -> crate::Result<Option<Packet>> {
+    fn parse_packet(
+        &mut self,
+        hello: &Option<HelloRequest>,
+        client_revision: u64
+    ) -> crate::Result<Option<Packet>> {
+        // Blocks can be large, they are decoded while they are received instead of
+        // parsing the whole packet again each time more data arrives.
+        if self.pending.is_some() || self.start_data_packet(client_revision)? {
+            let (_, _, decoder) = self.pending.as_mut().unwrap();
+            return match decoder.decode(&mut self.buffer)? {
+                Some(block) => {
+                    let (packet, name, _) = self.pending.take().unwrap();
+                    Ok(Some(Packet::data(packet == CLIENT_SCALAR, name, block)))
+                }
+                None => Ok(None)
+            };
+        }
+
         // Cursor is used to track the "current" location in the
         // buffer. Cursor also implements `Buf` from the `bytes` crate
         // which provides a number of helpful utilities for working
@@ -117,14 +184,12 @@ impl Connection {
         let mut buf = Cursor::new(&self.buffer[..]);
         let mut parser = Parser::new(&mut buf, self.tz);
 
-        let hello = ctx.hello.clone();
-        let packet = parser.parse_packet(&hello, self.compress);
+        let packet = parser.parse_packet(hello, client_revision, self.compress);
 
         match packet {
             Ok(packet) => {
-                match &packet {
-                    Packet::Query(ref query) => self.compress = query.compression > 0,
-                    _ => {}
+                if let Packet::Query(ref query) = &packet {
+                    self.compress = query.compression > 0;
                 }
                 // The `check` function will have advanced the cursor until the
                 // end of the frame. Since the cursor had position set to zero
@@ -147,24 +212,135 @@ impl Connection {
             // An error was encountered while parsing the frame. The connection
             // is now in an invalid state. Returning `Err` from here will result
             // in the connection being closed.
-            Err(e) => Err(e.into())
+            Err(e) => Err(e)
+        }
+    }
+
+    /// Consumes the header of a `Data` packet and starts decoding its block. Returns
+    /// `false` if the buffer holds another packet, or not the whole header yet, these
+    /// are left to the parser.
+    fn start_data_packet(&mut self, client_revision: u64) -> crate::Result<bool> {
+        let mut buf = Cursor::new(&self.buffer[..]);
+        let header = buf.read_uvarint().and_then(|packet| {
+            if packet == CLIENT_DATA || packet == CLIENT_SCALAR {
+                // the name of the external table or of the scalar
+                buf.read_string().map(|name| Some((packet, name)))
+            } else {
+                Ok(None)
+            }
+        });
+
+        match header {
+            Ok(Some((packet, name))) => {
+                let len = buf.position() as usize;
+                self.buffer.advance(len);
+                let decoder = BlockDecoder::new(self.tz, self.compress, client_revision);
+                self.pending = Some((packet, name, decoder));
+                Ok(true)
+            }
+            Ok(None) => Ok(false),
+            Err(err) if err.is_would_block() => Ok(false),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+/// Send `Packet` values to a remote peer.
+///
+/// When sending frames, the frame is first encoded into the write buffer.
+/// The contents of the write buffer are then written to the socket.
+pub struct Connection {
+    // The write half of the transport. It is decorated with a `BufWriter`,
+    // which provides write level buffering. The `BufWriter` implementation
+    // provided by Tokio is sufficient for our needs.
+    stream: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    pub session: Arc<dyn ClickHouseSession>,
+
+    // The revision negotiated in the handshake.
+    pub(crate) client_revision: u64,
+    // The codec of outgoing blocks, `None` if the client disabled compression.
+    pub(crate) compression: Option<CompressionMethod>,
+    // The messages logged by the running query, they are sent before any other packet.
+    pub(crate) logs: Option<Arc<LogQueue>>
+}
+
+impl Connection {
+    /// Create a new `Connection`, backed by `stream`. The write buffer is
+    /// initialized.
+    pub fn new<W>(stream: W, session: Arc<dyn ClickHouseSession>) -> Connection
+    where W: AsyncWrite + Send + Unpin + 'static {
+        Connection {
+            stream: BufWriter::new(Box::new(stream)),
+            session,
+            client_revision: 0,
+            compression: Some(CompressionMethod::default()),
+            logs: None
         }
     }
 
     pub async fn write_block(&mut self, block: &Block) -> Result<()> {
+        let block = self.compatible_block(block)?;
         let mut encoder = Encoder::new();
-        block.send_server_data(&mut encoder, self.compress);
-        self.stream.write_all(&encoder.get_buffer()).await?;
-        self.stream.flush().await?;
-        Ok(())
+        block.send_server_data(&mut encoder, self.compression, self.client_revision)?;
+        self.write_bytes(encoder.get_buffer()).await
     }
 
-    pub async fn write_progress(&mut self, progress: Progress, client_revision: u64) -> Result<()> {
+    /// Sends the progress of the query, encoded for the revision negotiated in the handshake.
+    pub async fn write_query_progress(&mut self, progress: Progress) -> Result<()> {
         let mut encoder = Encoder::new();
-        progress.write(&mut encoder, client_revision);
-        self.stream.write_all(&encoder.get_buffer()).await?;
-        self.stream.flush().await?;
-        Ok(())
+        progress.write(&mut encoder, self.client_revision);
+        self.write_bytes(encoder.get_buffer()).await
+    }
+
+    /// `client_revision` is ignored, the progress is encoded for the revision negotiated in
+    /// the handshake, which may be lower than the one of the client.
+    #[deprecated(note = "use `write_query_progress`")]
+    pub async fn write_progress(
+        &mut self,
+        progress: Progress,
+        _client_revision: u64
+    ) -> Result<()> {
+        self.write_query_progress(progress).await
+    }
+
+    pub async fn write_profile_info(&mut self, profile_info: ProfileInfo) -> Result<()> {
+        let mut encoder = Encoder::new();
+        profile_info.write(&mut encoder, self.client_revision);
+        self.write_bytes(encoder.get_buffer()).await
+    }
+
+    /// Sends the `WITH TOTALS` row of the query.
+    pub async fn write_totals(&mut self, block: &Block) -> Result<()> {
+        let block = self.compatible_block(block)?;
+        let mut encoder = Encoder::new();
+        block.send_server_packet(
+            &mut encoder,
+            SERVER_TOTALS,
+            self.compression,
+            self.client_revision
+        )?;
+        self.write_bytes(encoder.get_buffer()).await
+    }
+
+    /// Sends the min and max rows of the result, when the client asked for `extremes`.
+    pub async fn write_extremes(&mut self, block: &Block) -> Result<()> {
+        let block = self.compatible_block(block)?;
+        let mut encoder = Encoder::new();
+        block.send_server_packet(
+            &mut encoder,
+            SERVER_EXTREMES,
+            self.compression,
+            self.client_revision
+        )?;
+        self.write_bytes(encoder.get_buffer()).await
+    }
+
+    // Clients before `LowCardinality` receive the columns with their full type.
+    fn compatible_block<'a>(&self, block: &'a Block) -> Result<Cow<'a, Block>> {
+        if self.client_revision >= DBMS_MIN_REVISION_WITH_LOW_CARDINALITY_TYPE {
+            return Ok(Cow::Borrowed(block));
+        }
+        Ok(Cow::Owned(block.remove_low_cardinality()?))
     }
 
     pub async fn write_end_of_stream(&mut self) -> Result<()> {
@@ -176,16 +352,133 @@ impl Connection {
 
     pub async fn write_error(&mut self, err: &Error) -> Result<()> {
         let mut encoder = Encoder::new();
-        ExceptionResponse::write(&mut encoder, &err, self.with_stack_trace);
+        ExceptionResponse::write(&mut encoder, err, self.session.with_stack_trace());
+        self.write_bytes(encoder.get_buffer()).await
+    }
 
-        self.stream.write_all(&encoder.get_buffer()).await?;
-        self.stream.flush().await?;
+    /// Sends server log messages, they are dropped if the client doesn't support them.
+    pub async fn write_log(&mut self, logs: &[ServerLog]) -> Result<()> {
+        if logs.is_empty() || self.client_revision < DBMS_MIN_REVISION_WITH_SERVER_LOGS {
+            return Ok(());
+        }
+
+        let mut encoder = Encoder::new();
+        // logs are never compressed
+        ServerLog::to_block(logs).send_server_packet(
+            &mut encoder,
+            SERVER_LOG,
+            None,
+            self.client_revision
+        )?;
+        self.write_bytes(encoder.get_buffer()).await
+    }
+
+    /// Sends the messages logged by the running query so far.
+    pub(crate) async fn write_pending_logs(&mut self) -> Result<()> {
+        let logs = match &self.logs {
+            Some(queue) => queue.take(),
+            None => return Ok(())
+        };
+
+        if !logs.is_empty() {
+            let mut encoder = Encoder::new();
+            ServerLog::to_block(&logs).send_server_packet(
+                &mut encoder,
+                SERVER_LOG,
+                None,
+                self.client_revision
+            )?;
+            self.stream.write_all(encoder.get_buffer_ref()).await?;
+        }
         Ok(())
     }
 
     pub async fn write_bytes(&mut self, bytes: Vec<u8>) -> Result<()> {
+        self.write_pending_logs().await?;
         self.stream.write_all(&bytes).await?;
         self.stream.flush().await?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::protocols::DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION;
+    use crate::protocols::DBMS_MIN_REVISION_WITH_SERVER_TIMEZONE;
+    use crate::protocols::DBMS_TCP_PROTOCOL_VERSION;
+    use crate::testing::ServerPacket;
+    use crate::testing::TestClient;
+    use crate::CHContext;
+
+    struct Session;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for Session {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    // Writes the packets sent at the end of a query, then decodes them as a client of
+    // the given revision.
+    async fn test_end_of_query_packets(revision: u64) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let mut connection = Connection::new(server, Arc::new(Session));
+        connection.client_revision = revision;
+        connection.compression = None;
+
+        let totals = Block::new().column("count", vec![42_u64]);
+        let extremes = Block::new()
+            .column("min", vec![1_u64, 2])
+            .column("max", vec!["a", "b"]);
+        let info = ProfileInfo {
+            rows: 10,
+            bytes: 100,
+            blocks: 2,
+            applied_limit: true,
+            rows_before_limit: 20,
+            calculated_rows_before_limit: true,
+            applied_aggregation: true,
+            rows_before_aggregation: 30
+        };
+        connection.write_totals(&totals).await.unwrap();
+        connection.write_extremes(&extremes).await.unwrap();
+        connection.write_profile_info(info).await.unwrap();
+        drop(connection);
+
+        let mut client = TestClient::new(client, None).with_revision(revision);
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Totals(block) => assert_eq!(block, totals),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Extremes(block) => assert_eq!(block, extremes),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        let expected = if revision >= DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION {
+            info
+        } else {
+            ProfileInfo {
+                applied_aggregation: false,
+                rows_before_aggregation: 0,
+                ..info
+            }
+        };
+        match client.read_packet().await.unwrap() {
+            ServerPacket::ProfileInfo(actual) => assert_eq!(actual, expected),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(client.read_packet().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_end_of_query_packets_old_revision() {
+        test_end_of_query_packets(DBMS_MIN_REVISION_WITH_SERVER_TIMEZONE).await;
+    }
+
+    #[tokio::test]
+    async fn test_end_of_query_packets_current_revision() {
+        test_end_of_query_packets(DBMS_TCP_PROTOCOL_VERSION).await;
+    }
+}
@@ -464,6 +464,7 @@ pub const LIMIT_BY_WITH_TIES_IS_NOT_SUPPORTED: u32 = 498;
 pub const S3_ERROR: u32 = 499;
 pub const CANNOT_CREATE_DICTIONARY_FROM_METADATA: u32 = 500;
 pub const CANNOT_CREATE_DATABASE: u32 = 501;
+pub const AUTHENTICATION_FAILED: u32 = 516;
 
 pub const KEEPER_EXCEPTION: u32 = 999;
 pub const POCO_EXCEPTION: u32 = 1000;
@@ -9,6 +9,8 @@ use thiserror::Error;
 use tokio::time::error::Elapsed;
 use url::ParseError;
 
+use crate::error_codes;
+
 /// Result type alias for this library.
 pub type Result<T> = result::Result<T, Error>;
 
@@ -221,7 +223,48 @@ impl Error {
             Error::Other(_) => "OtherException",
             Error::Server(e) => e.name.as_str(),
             Error::Url(_) => "URLException",
-            Error::FromSql(_) => "SQLException",
+            Error::FromSql(_) => "SQLException"
+        }
+    }
+
+    /// The ClickHouse error code sent to the client, see [`error_codes`].
+    pub fn code(&self) -> u32 {
+        match self {
+            Error::Driver(e) => e.code(),
+            Error::IO(e) => match e.kind() {
+                io::ErrorKind::TimedOut => error_codes::SOCKET_TIMEOUT,
+                io::ErrorKind::UnexpectedEof => error_codes::ATTEMPT_TO_READ_AFTER_EOF,
+                io::ErrorKind::NotFound => error_codes::FILE_DOESNT_EXIST,
+                _ => error_codes::STD_EXCEPTION
+            },
+            Error::Connection(_) => error_codes::NETWORK_ERROR,
+            Error::Other(_) => error_codes::UNKNOWN_EXCEPTION,
+            Error::Server(e) => e.code,
+            Error::Url(_) => error_codes::BAD_ARGUMENTS,
+            Error::FromSql(e) => e.code()
+        }
+    }
+}
+
+impl DriverError {
+    pub fn code(&self) -> u32 {
+        match self {
+            DriverError::Overflow => error_codes::INCORRECT_DATA,
+            DriverError::UnknownPacket { .. } => error_codes::UNKNOWN_PACKET_FROM_CLIENT,
+            DriverError::UnexpectedPacket => error_codes::UNEXPECTED_PACKET_FROM_CLIENT,
+            DriverError::Timeout => error_codes::TIMEOUT_EXCEEDED,
+            DriverError::Utf8Error(_) => error_codes::CANNOT_PARSE_TEXT,
+            DriverError::UnknownSetting { .. } => error_codes::UNKNOWN_SETTING
+        }
+    }
+}
+
+impl FromSqlError {
+    pub fn code(&self) -> u32 {
+        match self {
+            FromSqlError::InvalidType { .. } => error_codes::TYPE_MISMATCH,
+            FromSqlError::OutOfRange => error_codes::VALUE_IS_OUT_OF_RANGE_OF_DATA_TYPE,
+            FromSqlError::UnsupportedOperation => error_codes::NOT_IMPLEMENTED
         }
     }
 }
@@ -241,4 +284,24 @@ mod tests {
         let dst_err: std::io::Error = src_err.into();
         assert_eq!(dst_err.to_string(), "Other error: `Somth went wrong.`");
     }
+
+    #[test]
+    fn test_code() {
+        use super::*;
+
+        assert_eq!(
+            Error::from(DriverError::UnexpectedPacket).code(),
+            error_codes::UNEXPECTED_PACKET_FROM_CLIENT
+        );
+        assert_eq!(
+            Error::FromSql(FromSqlError::OutOfRange).code(),
+            error_codes::VALUE_IS_OUT_OF_RANGE_OF_DATA_TYPE
+        );
+        let timeout = io::Error::new(io::ErrorKind::TimedOut, "timeout");
+        assert_eq!(Error::from(timeout).code(), error_codes::SOCKET_TIMEOUT);
+        assert_eq!(
+            Error::from("unknown").code(),
+            error_codes::UNKNOWN_EXCEPTION
+        );
+    }
 }
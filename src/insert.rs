@@ -0,0 +1,167 @@
+//! The data of an INSERT, see
+//! [`ClickHouseSession::execute_insert`](crate::ClickHouseSession::execute_insert).
+
+use std::future::Future;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use futures::Stream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::errors::Result;
+use crate::types::Block;
+
+// How many blocks are buffered while the session consumes them.
+const BUFFERED_BLOCKS: usize = 4;
+
+type Sink = Box<dyn FnOnce(InsertStream) -> BoxFuture<'static, Result<()>> + Send>;
+
+/// How a session runs an INSERT: the header is sent to the client as the structure of the
+/// table, then the sink consumes the blocks the client sends.
+pub struct InsertHandler {
+    header: Block,
+    sink: Sink
+}
+
+impl InsertHandler {
+    /// `header` has the columns of the table and no rows. The future returned by `sink`
+    /// runs on its own task, it's done once the stream ended and the data is stored. If it
+    /// fails, the error is sent to the client as an exception.
+    pub fn new<F, Fut>(header: Block, sink: F) -> Self
+    where
+        F: FnOnce(InsertStream) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static
+    {
+        Self {
+            header,
+            sink: Box::new(move |stream| sink(stream).boxed())
+        }
+    }
+
+    pub fn header(&self) -> &Block {
+        &self.header
+    }
+}
+
+/// The blocks of an INSERT, until the client sent all of them. When the INSERT is cancelled
+/// or the connection is closed, the sink is dropped instead, so a stream which ended always
+/// has all the data.
+pub struct InsertStream {
+    receiver: ReceiverStream<Block>
+}
+
+impl Stream for InsertStream {
+    type Item = Block;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Block>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+/// The running sink of an INSERT, the task is aborted when it's dropped unfinished.
+pub(crate) struct InsertSink {
+    sender: Option<mpsc::Sender<Block>>,
+    task: JoinHandle<Result<()>>
+}
+
+impl InsertSink {
+    pub(crate) fn start(handler: InsertHandler) -> Self {
+        let (sender, receiver) = mpsc::channel(BUFFERED_BLOCKS);
+        let stream = InsertStream {
+            receiver: ReceiverStream::new(receiver)
+        };
+        Self {
+            sender: Some(sender),
+            task: tokio::spawn((handler.sink)(stream))
+        }
+    }
+
+    /// Hands a block to the sink. Returns false if the sink doesn't take blocks anymore,
+    /// `finish` tells why.
+    pub(crate) async fn send(&mut self, block: Block) -> bool {
+        match &self.sender {
+            Some(sender) => sender.send(block).await.is_ok(),
+            None => false
+        }
+    }
+
+    /// Ends the stream and waits for the sink.
+    pub(crate) async fn finish(mut self) -> Result<()> {
+        self.sender = None;
+        self.wait().await
+    }
+
+    async fn wait(&mut self) -> Result<()> {
+        match (&mut self.task).await {
+            Ok(res) => res,
+            Err(e) if e.is_panic() => Err("the INSERT panicked".into()),
+            Err(e) => Err(e.to_string().into())
+        }
+    }
+}
+
+impl Drop for InsertSink {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Resolves when the sink of the running INSERT returned, the sink is taken then. Never
+/// resolves without an INSERT.
+pub(crate) async fn returned(insert: &mut Option<InsertSink>) -> Result<()> {
+    let res = match insert {
+        Some(sink) => sink.wait().await,
+        None => return futures::future::pending().await
+    };
+    *insert = None;
+    res
+}
+
+#[cfg(test)]
+mod test {
+    use futures::StreamExt;
+
+    use super::*;
+
+    fn ids(ids: Vec<u32>) -> Block {
+        Block::new().column("id", ids)
+    }
+
+    #[tokio::test]
+    async fn test_finish() {
+        let handler = InsertHandler::new(ids(vec![]), |mut stream: InsertStream| async move {
+            let mut rows = 0;
+            while let Some(block) = stream.next().await {
+                rows += block.row_count();
+            }
+            assert_eq!(rows, 3);
+            Ok(())
+        });
+        assert_eq!(handler.header().row_count(), 0);
+
+        let mut sink = InsertSink::start(handler);
+        assert!(sink.send(ids(vec![1, 2])).await);
+        assert!(sink.send(ids(vec![3])).await);
+        sink.finish().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let handler = InsertHandler::new(ids(vec![]), |_| async { Err("read only".into()) });
+        let mut sink = InsertSink::start(handler);
+        // the block is buffered, or the sink already returned
+        sink.send(ids(vec![1])).await;
+        let err = sink.finish().await.unwrap_err();
+        assert!(err.to_string().contains("read only"));
+
+        let handler = InsertHandler::new(ids(vec![]), |_| async { panic!("corrupted") });
+        let sink = InsertSink::start(handler);
+        let err = sink.finish().await.unwrap_err();
+        assert!(err.to_string().contains("panicked"));
+    }
+}
@@ -1,30 +1,133 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
+use chrono_tz::Tz;
 use errors::Result;
 use log::debug;
 use protocols::Stage;
 use tokio::net::TcpStream;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Notify;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
+use crate::auth::Authenticator;
 use crate::cmd::Cmd;
 use crate::connection::Connection;
+use crate::connection::PacketReader;
+use crate::insert::InsertHandler;
+use crate::insert::InsertSink;
 use crate::protocols::HelloRequest;
+use crate::protocols::Packet;
+use crate::protocols::QualifiedTableName;
+use crate::protocols::QueryRequest;
+use crate::protocols::TableStatus;
+use crate::query_stream::QueryStream;
+use crate::server::PeerInfo;
+use crate::server::ServerBuilder;
+use crate::stream::Transport;
 use crate::types::Block;
+use crate::types::CompressionMethod;
 use crate::types::Progress;
+use crate::types::Settings;
 
+pub mod auth;
 mod binary;
 pub mod cmd;
 pub mod connection;
 pub mod error_codes;
 pub mod errors;
+pub mod insert;
+pub mod logs;
 pub mod protocols;
+pub mod query_stream;
+pub mod server;
+pub mod stream;
+#[cfg(test)]
+mod testing;
+#[cfg(feature = "tls")]
+pub mod tls;
 pub mod types;
 
 #[async_trait::async_trait]
 pub trait ClickHouseSession: Send + Sync {
+    /// Runs a query, writing its result to `connection`, or sending a stream of events with
+    /// [`query_stream::send_query_stream`]. On error the exception is sent to the client,
+    /// the connection is kept for the next query.
     async fn execute_query(&self, ctx: &mut CHContext, connection: &mut Connection) -> Result<()>;
 
+    /// Runs a query as a stream of events, which the server sends to the client like
+    /// [`query_stream::send_query_stream`] does. Called before
+    /// [`execute_query`](ClickHouseSession::execute_query) which runs when `None` is
+    /// returned, as by default.
+    async fn query_stream(&self, _ctx: &mut CHContext) -> Result<Option<QueryStream>> {
+        Ok(None)
+    }
+
+    /// Starts an INSERT whose data is sent by the client after the query, called before
+    /// [`execute_query`](ClickHouseSession::execute_query) which runs when `None` is
+    /// returned, as by default.
+    async fn execute_insert(&self, _ctx: &mut CHContext) -> Result<Option<InsertHandler>> {
+        Ok(None)
+    }
+
+    /// Checks the credentials sent in the `Hello` packet. On error the exception is sent to
+    /// the client and the connection is closed. By default they are checked by
+    /// [`authenticator`](ClickHouseSession::authenticator), everyone is accepted without one.
+    async fn authenticate(
+        &self,
+        user: &str,
+        password: &str,
+        peer_addr: Option<SocketAddr>
+    ) -> Result<()> {
+        match self.authenticator() {
+            Some(authenticator) => authenticator.authenticate(user, password, peer_addr),
+            None => Ok(())
+        }
+    }
+
+    /// The authenticator used by the default [`authenticate`](ClickHouseSession::authenticate),
+    /// e.g. an [`auth::StaticUsers`] table. None by default.
+    fn authenticator(&self) -> Option<&dyn Authenticator> {
+        None
+    }
+
+    /// Called when a client connected, before anything is received. On error the
+    /// exception is sent to the client and the connection is closed.
+    async fn on_connect(&self, _peer_addr: Option<SocketAddr>) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called once the client is authenticated. The returned session serves the rest of
+    /// the connection instead of this one, so it can keep the state of the connection,
+    /// e.g. the current database or temporary tables. `None` keeps this session.
+    async fn on_hello(&self, _hello: &HelloRequest) -> Result<Option<Arc<dyn ClickHouseSession>>> {
+        Ok(None)
+    }
+
+    /// Called on the session of the connection when it's closed, whatever the reason,
+    /// except when [`on_connect`](ClickHouseSession::on_connect) rejected it or the
+    /// connection is dropped at the shutdown timeout of a [`server::Listener`].
+    async fn on_disconnect(&self, _ctx: &CHContext) {}
+
+    /// Answers a `TablesStatusRequest`, sent by clients of `Distributed` tables to check
+    /// the delay of replicas. Tables which don't exist are left out. On error the exception
+    /// is sent to the client, the connection is kept. By default every table is reported as
+    /// not replicated.
+    async fn tables_status(
+        &self,
+        _ctx: &CHContext,
+        tables: &[QualifiedTableName]
+    ) -> Result<Vec<(QualifiedTableName, TableStatus)>> {
+        Ok(tables
+            .iter()
+            .map(|table| (table.clone(), TableStatus::default()))
+            .collect())
+    }
+
+    /// Whether the stack traces of [`errors::ServerError`]s are sent to the client.
     fn with_stack_trace(&self) -> bool {
         false
     }
@@ -42,8 +145,11 @@ pub trait ClickHouseSession: Send + Sync {
         17
     }
 
+    /// The revision of the native protocol advertised to clients, the negotiated one is
+    /// the lowest of this, the revision of the client and
+    /// [`protocols::DBMS_TCP_PROTOCOL_VERSION`].
     fn dbms_tcp_protocol_version(&self) -> u64 {
-        54428
+        protocols::DBMS_TCP_PROTOCOL_VERSION
     }
 
     fn timezone(&self) -> &str {
@@ -58,9 +164,24 @@ pub trait ClickHouseSession: Send + Sync {
         1
     }
 
+    /// The rules new passwords must match, sent to clients in the handshake as pairs of
+    /// a regular expression and the message shown when it doesn't match.
+    fn password_complexity_rules(&self) -> Vec<(String, String)> {
+        Vec::new()
+    }
+
     fn get_progress(&self) -> Progress {
         Progress::default()
     }
+
+    /// The codec of the blocks sent to the client when it enabled compression.
+    /// By default the `network_compression_method` setting of the client is used, or LZ4.
+    fn compression_method(&self, settings: &Settings) -> CompressionMethod {
+        settings
+            .get_str("network_compression_method")
+            .and_then(CompressionMethod::from_name)
+            .unwrap_or_default()
+    }
 }
 
 #[derive(Default)]
@@ -69,23 +190,29 @@ pub struct QueryState {
     pub stage: Stage,
     pub compression: u64,
     pub query: String,
-    pub is_cancelled: bool,
+    /// Cancelled when the client sends `Cancel` or goes away while the query is running.
+    /// Sessions should stop producing blocks once it fires.
+    pub cancel_token: CancellationToken,
     pub is_connection_closed: bool,
     /// empty or not
     pub is_empty: bool,
 
-    /// Data was sent.
-    pub sent_all_data: Arc<Notify>,
-    pub out: Option<Sender<Block>>
+    // The INSERT receiving the data of the client.
+    pub(crate) insert: Option<InsertSink>
 }
 
 impl QueryState {
     fn reset(&mut self) {
         self.stage = Stage::Default;
-        self.is_cancelled = false;
+        self.cancel_token = CancellationToken::new();
         self.is_connection_closed = false;
         self.is_empty = false;
-        self.out = None;
+        self.insert = None;
+    }
+
+    /// Returns true if the client asked to cancel the running query.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_token.is_cancelled()
     }
 }
 
@@ -93,7 +220,20 @@ pub struct CHContext {
     pub state: QueryState,
 
     pub client_revision: u64,
-    pub hello: Option<HelloRequest>
+    pub hello: Option<HelloRequest>,
+    /// The address of the client, if it's known.
+    pub peer_addr: Option<SocketAddr>,
+    /// Settings sent by the client with the current query.
+    pub settings: Settings,
+    /// The external tables sent with the current query (`--external` of
+    /// `clickhouse-client`), by name.
+    pub external_tables: HashMap<String, Vec<Block>>,
+    /// The results of scalar subqueries computed by the client for the current query,
+    /// by name. They are sent by servers forwarding a distributed query.
+    pub scalars: HashMap<String, Block>,
+    /// The parts the current query must skip, sent by replicas reading in parallel.
+    pub ignored_part_uuids: Vec<Uuid>,
+    pub(crate) query: Option<QueryRequest>
 }
 
 impl CHContext {
@@ -101,41 +241,147 @@ impl CHContext {
         Self {
             state,
             client_revision: 0,
-            hello: None
+            hello: None,
+            peer_addr: None,
+            settings: Settings::default(),
+            external_tables: HashMap::new(),
+            scalars: HashMap::new(),
+            ignored_part_uuids: Vec::new(),
+            query: None
         }
     }
+
+    /// The current query, with the information about the client which sent it.
+    pub fn query(&self) -> Option<&QueryRequest> {
+        self.query.as_ref()
+    }
 }
 
 /// A server that speaks the ClickHouseprotocol, and can delegate client commands to a backend
 /// that implements [`ClickHouseSession`]
-pub struct ClickHouseServer {}
+#[derive(Default)]
+pub struct ClickHouseServer {
+    // Connections waiting for a query longer than this are closed.
+    idle_timeout: Option<Duration>,
+    // Fired when the listener shuts down, connections are closed once their query finished.
+    shutdown: CancellationToken
+}
 
 impl ClickHouseServer {
+    /// Creates a listener running a session from `factory` for every accepted connection.
+    ///
+    /// ```rust,no_run
+    /// # use std::sync::Arc;
+    /// # use clickhouse_srv::{ClickHouseServer, ClickHouseSession};
+    /// # async fn run(session: Arc<dyn ClickHouseSession>) -> clickhouse_srv::errors::Result<()> {
+    /// let listener = ClickHouseServer::builder(move |_peer| session.clone())
+    ///     .bind("127.0.0.1:9000")
+    ///     .max_connections(100)
+    ///     .listen()
+    ///     .await?;
+    /// // `shutdown()` stops the listener, `run` returns once the connections are closed
+    /// let shutdown = listener.shutdown_handle();
+    /// listener.run().await
+    /// # }
+    /// ```
+    pub fn builder<F>(factory: F) -> ServerBuilder
+    where F: Fn(&PeerInfo) -> Arc<dyn ClickHouseSession> + Send + Sync + 'static {
+        ServerBuilder::new(Arc::new(factory))
+    }
+
     pub async fn run_on_stream(
         session: Arc<dyn ClickHouseSession>,
         stream: TcpStream
     ) -> Result<()> {
-        ClickHouseServer::run_on(session, stream.into()).await
+        ClickHouseServer::run_on_transport(session, stream).await
     }
-}
 
-impl ClickHouseServer {
-    async fn run_on(session: Arc<dyn ClickHouseSession>, stream: TcpStream) -> Result<()> {
-        let mut srv = ClickHouseServer {};
+    /// Runs the protocol over a Unix domain socket.
+    #[cfg(unix)]
+    pub async fn run_on_unix_stream(
+        session: Arc<dyn ClickHouseSession>,
+        stream: UnixStream
+    ) -> Result<()> {
+        ClickHouseServer::run_on_transport(session, stream).await
+    }
+
+    /// Runs the protocol over TLS, the handshake is done on the accepted socket first.
+    #[cfg(feature = "tls")]
+    pub async fn run_on_tls_stream(
+        session: Arc<dyn ClickHouseSession>,
+        acceptor: &tls::TlsAcceptor,
+        stream: TcpStream
+    ) -> Result<()> {
+        let stream = acceptor.accept(stream).await?;
+        ClickHouseServer::run_on_transport(session, stream).await
+    }
+
+    /// Runs the protocol over any transport, e.g. a `tokio::io::duplex` pipe.
+    pub async fn run_on_transport<T: Transport>(
+        session: Arc<dyn ClickHouseSession>,
+        stream: T
+    ) -> Result<()> {
+        let mut srv = ClickHouseServer::default();
         srv.run(session, stream).await?;
         Ok(())
     }
+}
 
-    async fn run(&mut self, session: Arc<dyn ClickHouseSession>, stream: TcpStream) -> Result<()> {
+impl ClickHouseServer {
+    async fn run<T: Transport>(
+        &mut self,
+        session: Arc<dyn ClickHouseSession>,
+        stream: T
+    ) -> Result<()> {
         debug!("Handle New session");
-        let tz = session.timezone().to_string();
+        let tz: Tz = session.timezone().parse()?;
         let mut ctx = CHContext::new(QueryState::default());
-        let mut connection = Connection::new(stream, session, tz)?;
+        ctx.peer_addr = stream.peer_addr();
+        let (read_half, write_half) = tokio::io::split(stream);
+        let mut reader = PacketReader::new(read_half, tz);
+        let mut connection = Connection::new(write_half, session.clone());
+
+        if let Err(e) = session.on_connect(ctx.peer_addr).await {
+            // the connection never started, `on_disconnect` isn't called
+            debug!("connection from {:?} is rejected", ctx.peer_addr);
+            connection.write_error(&e).await?;
+            return Err(e);
+        }
 
+        let res = self
+            .handle_packets(&mut reader, &mut connection, &mut ctx)
+            .await;
+        // the session which was returned by `on_hello`, if any
+        connection.session.clone().on_disconnect(&ctx).await;
+        res
+    }
+
+    async fn handle_packets(
+        &mut self,
+        reader: &mut PacketReader,
+        connection: &mut Connection,
+        ctx: &mut CHContext
+    ) -> Result<()> {
         loop {
-            // signal.
+            // an INSERT is still running while its data is received
+            let inserting = matches!(ctx.state.stage, Stage::InsertStarted);
+            // the client isn't idle while it sends the data of an INSERT
+            let idle_timeout = if inserting { None } else { self.idle_timeout };
             let maybe_packet = tokio::select! {
-               res = connection.read_packet(&mut ctx) => res,
+                biased;
+                _ = self.shutdown.cancelled(), if !inserting => {
+                    debug!("server is shutting down, closing the connection");
+                    return Ok(());
+                }
+                // the rest of the data is ignored after an error, the client stops sending it
+                res = insert::returned(&mut ctx.state.insert) => {
+                    if let Err(e) = res {
+                        ctx.state.stage = Stage::Default;
+                        connection.write_error(&e).await?;
+                    }
+                    continue;
+                }
+                packet = Self::read_packet(reader, &ctx.hello, ctx.client_revision, idle_timeout) => packet
             };
 
             let packet = match maybe_packet {
@@ -152,7 +398,31 @@ impl ClickHouseServer {
                 }
             };
             let cmd = Cmd::create(packet);
-            cmd.apply(&mut connection, &mut ctx).await?;
+            cmd.apply(reader, connection, ctx).await?;
+
+            if ctx.state.is_connection_closed {
+                debug!("client closed the connection during query");
+                return Ok(());
+            }
+        }
+    }
+
+    async fn read_packet(
+        reader: &mut PacketReader,
+        hello: &Option<HelloRequest>,
+        client_revision: u64,
+        idle_timeout: Option<Duration>
+    ) -> Result<Option<Packet>> {
+        let read = reader.read_packet(hello, client_revision);
+        match idle_timeout {
+            Some(idle_timeout) => match tokio::time::timeout(idle_timeout, read).await {
+                Ok(packet) => packet,
+                Err(_) => {
+                    debug!("closing the connection idle for {:?}", idle_timeout);
+                    Ok(None)
+                }
+            },
+            None => read.await
         }
     }
 }
@@ -184,8 +454,670 @@ macro_rules! row {
 
 #[cfg(test)]
 mod tests {
+    use futures::StreamExt;
+
+    use super::*;
+    use crate::binary::Encoder;
+    use crate::errors::Error;
+    use crate::protocols::SERVER_END_OF_STREAM;
+    use crate::testing::ServerPacket;
+    use crate::testing::TestClient;
+
     #[test]
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    struct Session;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for Session {
+        async fn execute_query(
+            &self,
+            ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            let query = ctx.query().unwrap();
+            assert_eq!(query.query(), ctx.state.query);
+            assert_eq!(query.client_info().client_name, "test");
+            assert_eq!(query.client_info().initial_user, "default");
+
+            if ctx.state.query == "SELECT sleep" {
+                // until the client cancels the query
+                ctx.state.cancel_token.cancelled().await;
+                return Ok(());
+            }
+            let block = Block::new().column("query", vec![ctx.state.query.clone()]);
+            connection.write_block(&block).await?;
+            if ctx.settings.get_u64("fail").is_some() {
+                return Err("the query failed".into());
+            }
+            Ok(())
+        }
+
+        async fn authenticate(
+            &self,
+            user: &str,
+            _password: &str,
+            _peer_addr: Option<SocketAddr>
+        ) -> Result<()> {
+            match user {
+                "default" => Ok(()),
+                _ => Err("unknown user".into())
+            }
+        }
+
+        fn dbms_name(&self) -> &str {
+            "test-server"
+        }
+    }
+
+    async fn assert_query(client: &mut TestClient, query: &str) {
+        client.query(query).await;
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Data(block) => {
+                assert_eq!(block.get::<String, _>(0, "query").unwrap(), query)
+            }
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_on_duplex() {
+        let mut client = TestClient::connect(Arc::new(Session));
+        assert_eq!(client.hello("default").await.unwrap(), "test-server");
+        assert_query(&mut client, "SELECT 1").await;
+        assert_query(&mut client, "SELECT 2").await;
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel_query() {
+        let mut client = TestClient::connect(Arc::new(Session));
+        client.hello("default").await.unwrap();
+        client.query("SELECT sleep").await;
+
+        let mut encoder = Encoder::new();
+        encoder.uvarint(protocols::CLIENT_CANCEL);
+        client.send(encoder).await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+
+        // the connection is still usable
+        assert_query(&mut client, "SELECT 1").await;
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_query_error() {
+        let mut client = TestClient::connect(Arc::new(Session))
+            .with_revision(protocols::DBMS_TCP_PROTOCOL_VERSION);
+        client.hello("default").await.unwrap();
+        client
+            .query_with("SELECT 1", &[("fail", "1")], &[], &[])
+            .await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Data(_)
+        ));
+        match client.read_packet().await {
+            Err(Error::Server(e)) => assert!(e.message.contains("the query failed")),
+            res => panic!("unexpected result {:?}", res)
+        }
+
+        // the connection is kept for the next query
+        assert_query(&mut client, "SELECT 2").await;
+        client.close().await.unwrap();
+    }
+
+    struct LoggingSession;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for LoggingSession {
+        async fn execute_query(
+            &self,
+            _ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            log::info!(target: "session", "reading");
+            log::debug!(target: "session", "reading part 1");
+            connection
+                .write_block(&Block::new().column("x", vec![1_u8]))
+                .await
+        }
+    }
+
+    // The messages of the test session in the `SERVER_LOG` packet sent before the result.
+    async fn query_logs(client: &mut TestClient, send_logs_level: &str) -> Vec<(i8, String)> {
+        client
+            .query_with("SELECT 1", &[("send_logs_level", send_logs_level)], &[], &[
+            ])
+            .await;
+        let mut logs = Vec::new();
+        loop {
+            match client.read_packet().await.unwrap() {
+                ServerPacket::Log(block) => {
+                    for i in 0..block.row_count() {
+                        if block.get::<String, _>(i, "source").unwrap() == "session" {
+                            let priority = block.get::<i8, _>(i, "priority").unwrap();
+                            logs.push((priority, block.get(i, "text").unwrap()));
+                        }
+                    }
+                }
+                ServerPacket::Data(_) => {}
+                ServerPacket::Other(SERVER_END_OF_STREAM) => return logs,
+                packet => panic!("unexpected packet {:?}", packet)
+            }
+        }
+    }
+
+    struct Discard;
+
+    impl log::Log for Discard {
+        fn enabled(&self, _: &log::Metadata) -> bool {
+            false
+        }
+
+        fn log(&self, _: &log::Record) {}
+
+        fn flush(&self) {}
+    }
+
+    #[tokio::test]
+    async fn test_server_logs() {
+        // the global logger can only be installed once, no other test installs one
+        logs::ClientLogger::init(Discard, log::LevelFilter::Trace).unwrap();
+
+        let mut client = TestClient::connect(Arc::new(LoggingSession))
+            .with_revision(protocols::DBMS_TCP_PROTOCOL_VERSION);
+        client.hello("default").await.unwrap();
+
+        assert_eq!(query_logs(&mut client, "debug").await, vec![
+            (logs::LogPriority::Information as i8, "reading".to_string()),
+            (logs::LogPriority::Debug as i8, "reading part 1".to_string())
+        ]);
+        assert_eq!(query_logs(&mut client, "information").await, vec![(
+            logs::LogPriority::Information as i8,
+            "reading".to_string()
+        )]);
+        assert_eq!(query_logs(&mut client, "none").await, vec![]);
+        client.close().await.unwrap();
+    }
+
+    struct StackTraceSession;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for StackTraceSession {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Err(Error::Server(errors::ServerError {
+                code: error_codes::UNKNOWN_TABLE,
+                name: "DB::Exception".to_string(),
+                message: "no table t".to_string(),
+                stack_trace: "at execute_query".to_string()
+            }))
+        }
+
+        fn with_stack_trace(&self) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stack_trace() {
+        let mut client = TestClient::connect(Arc::new(StackTraceSession));
+        client.hello("default").await.unwrap();
+        client.query("SELECT * FROM t").await;
+        match client.read_packet().await {
+            Err(Error::Server(e)) => {
+                assert_eq!(e.code, error_codes::UNKNOWN_TABLE);
+                assert_eq!(e.stack_trace, "at execute_query");
+            }
+            res => panic!("unexpected result {:?}", res)
+        }
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_negotiate_revision() {
+        for revision in &[54405, protocols::DBMS_TCP_PROTOCOL_VERSION, u64::MAX] {
+            let mut client = TestClient::connect(Arc::new(Session)).with_revision(*revision);
+            client.hello("default").await.unwrap();
+            assert_eq!(
+                client.revision(),
+                (*revision).min(protocols::DBMS_TCP_PROTOCOL_VERSION)
+            );
+            assert_query(&mut client, "SELECT 1").await;
+            client.close().await.unwrap();
+        }
+    }
+
+    struct ReplicatedSession;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for ReplicatedSession {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Ok(())
+        }
+
+        async fn tables_status(
+            &self,
+            _ctx: &CHContext,
+            tables: &[QualifiedTableName]
+        ) -> Result<Vec<(QualifiedTableName, TableStatus)>> {
+            if tables.iter().any(|table| table.database.is_empty()) {
+                return Err("no database".into());
+            }
+            // only `t` exists
+            Ok(tables
+                .iter()
+                .filter(|table| table.table == "t")
+                .map(|table| {
+                    (table.clone(), TableStatus {
+                        is_replicated: true,
+                        absolute_delay: 5,
+                        is_readonly: false
+                    })
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tables_status() {
+        let table = |database: &str, table: &str| QualifiedTableName {
+            database: database.to_string(),
+            table: table.to_string()
+        };
+
+        let mut client = TestClient::connect(Arc::new(Session));
+        client.hello("default").await.unwrap();
+        let statuses = client.tables_status(&[table("db", "t")]).await.unwrap();
+        assert_eq!(statuses, vec![(table("db", "t"), false)]);
+        client.close().await.unwrap();
+
+        let mut client = TestClient::connect(Arc::new(ReplicatedSession))
+            .with_revision(protocols::DBMS_TCP_PROTOCOL_VERSION);
+        client.hello("default").await.unwrap();
+        let statuses = client
+            .tables_status(&[table("db", "t"), table("db", "u")])
+            .await
+            .unwrap();
+        assert_eq!(statuses, vec![(table("db", "t"), true)]);
+
+        // the connection is kept after an error
+        assert!(client.tables_status(&[table("", "t")]).await.is_err());
+        let statuses = client.tables_status(&[table("db", "t")]).await.unwrap();
+        assert_eq!(statuses.len(), 1);
+        client.close().await.unwrap();
+    }
+
+    struct ExternalTablesSession;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for ExternalTablesSession {
+        async fn execute_query(
+            &self,
+            ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            let mut tables: Vec<_> = ctx
+                .external_tables
+                .iter()
+                .map(|(name, blocks)| {
+                    let rows: usize = blocks.iter().map(|block| block.row_count()).sum();
+                    format!("{}:{}", name, rows)
+                })
+                .collect();
+            tables.sort();
+
+            let block = Block::new().column("tables", tables);
+            connection.write_block(&block).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_external_tables() {
+        let mut client = TestClient::connect(Arc::new(ExternalTablesSession));
+        client.hello("default").await.unwrap();
+
+        let ids = || Block::new().column("id", vec![1_u32, 2]);
+        let tables = [("a", ids()), ("b", ids()), ("a", ids())];
+        client.query_with("SELECT 1", &[], &[], &tables).await;
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Data(block) => {
+                assert_eq!(block.get::<String, _>(0, "tables").unwrap(), "a:4");
+                assert_eq!(block.get::<String, _>(1, "tables").unwrap(), "b:2");
+            }
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+
+        // they only belong to their query
+        client.query("SELECT 2").await;
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Data(block) => assert_eq!(block.row_count(), 0),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        client.close().await.unwrap();
+    }
+
+    #[derive(Default)]
+    struct InsertSession {
+        scalars: std::sync::Mutex<Vec<String>>,
+        inserted: Arc<std::sync::Mutex<Vec<Block>>>
+    }
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for InsertSession {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Ok(())
+        }
+
+        async fn execute_insert(&self, ctx: &mut CHContext) -> Result<Option<InsertHandler>> {
+            if !ctx.state.query.starts_with("INSERT") {
+                return Ok(None);
+            }
+            let scalar = &ctx.scalars["s"];
+            assert_eq!(scalar.get::<u64, _>(0, "x").unwrap(), 42);
+            self.scalars
+                .lock()
+                .unwrap()
+                .extend(ctx.scalars.keys().cloned());
+
+            let header = Block::new().column("id", Vec::<u32>::new());
+            let inserted = self.inserted.clone();
+            let failing = ctx.state.query.contains("read_only");
+            Ok(Some(InsertHandler::new(
+                header,
+                move |mut blocks| async move {
+                    while let Some(block) = blocks.next().await {
+                        if failing {
+                            return Err("the table is read only".into());
+                        }
+                        inserted.lock().unwrap().push(block);
+                    }
+                    Ok(())
+                }
+            )))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scalars() {
+        let session = Arc::new(InsertSession::default());
+        let mut client = TestClient::connect(session.clone());
+        client.hello("default").await.unwrap();
+
+        let scalar = Block::new().column("x", vec![42_u64]);
+        let ids = Block::new().column("id", vec![1_u32, 2, 3]);
+        client
+            .query_with("INSERT INTO t VALUES", &[], &[("s", scalar)], &[])
+            .await;
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Data(header) => assert_eq!(header.row_count(), 0),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        client.send_data(&ids).await;
+        client.send_data(&Block::new()).await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+        client.close().await.unwrap();
+
+        assert_eq!(*session.scalars.lock().unwrap(), vec!["s".to_string()]);
+        // only the data of the INSERT is received by the session
+        assert_eq!(*session.inserted.lock().unwrap(), vec![ids]);
+    }
+
+    #[tokio::test]
+    async fn test_insert_errors() {
+        let session = Arc::new(InsertSession::default());
+        let mut client = TestClient::connect(session.clone());
+        client.hello("default").await.unwrap();
+        let scalars = || [("s", Block::new().column("x", vec![42_u64]))];
+        let ids = Block::new().column("id", vec![1_u32, 2, 3]);
+
+        client
+            .query_with("INSERT INTO read_only VALUES", &[], &scalars(), &[])
+            .await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Data(_)
+        ));
+        client.send_data(&ids).await;
+        match client.read_packet().await {
+            Err(Error::Server(e)) => assert!(e.message.contains("read only")),
+            res => panic!("unexpected result {:?}", res)
+        }
+        // the rest of the data is ignored
+        client.send_data(&ids).await;
+        client.send_data(&Block::new()).await;
+
+        // the connection is still usable
+        client
+            .query_with("INSERT INTO t VALUES", &[], &scalars(), &[])
+            .await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Data(_)
+        ));
+        client.send_data(&ids).await;
+        client.send_data(&Block::new()).await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+        client.close().await.unwrap();
+        assert_eq!(*session.inserted.lock().unwrap(), vec![ids]);
+    }
+
+    #[derive(Default)]
+    struct PartsSession {
+        ignored: std::sync::Mutex<Vec<Vec<Uuid>>>
+    }
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for PartsSession {
+        async fn execute_query(
+            &self,
+            ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            self.ignored
+                .lock()
+                .unwrap()
+                .push(ctx.ignored_part_uuids.clone());
+            let block = Block::new().column("query", vec![ctx.state.query.clone()]);
+            connection.write_block(&block).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_and_ignored_part_uuids() {
+        let session = Arc::new(PartsSession::default());
+        let mut client = TestClient::connect(session.clone());
+        client.hello("default").await.unwrap();
+
+        let mut encoder = Encoder::new();
+        encoder.uvarint(protocols::CLIENT_KEEP_ALIVE);
+        encoder.uvarint(protocols::CLIENT_INGORED_PART_UUIDS);
+        encoder.uvarint(1);
+        encoder.write(0x936d_a01f_9abd_4d9d_u64);
+        encoder.write(0x80c7_02af_85c8_22a8_u64);
+        client.send(encoder).await;
+        assert_query(&mut client, "SELECT 1").await;
+
+        let mut encoder = Encoder::new();
+        encoder.uvarint(protocols::CLIENT_KEEP_ALIVE);
+        client.send(encoder).await;
+        assert_query(&mut client, "SELECT 2").await;
+        client.close().await.unwrap();
+
+        let uuid = Uuid::parse_str("936da01f-9abd-4d9d-80c7-02af85c822a8").unwrap();
+        // they only apply to the next query
+        assert_eq!(*session.ignored.lock().unwrap(), vec![vec![uuid], vec![]]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_run_on_unix_stream() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let server = tokio::spawn(ClickHouseServer::run_on_unix_stream(
+            Arc::new(Session),
+            server
+        ));
+
+        let mut client = TestClient::new(client, Some(server));
+        client.hello("default").await.unwrap();
+        assert_query(&mut client, "SELECT 1").await;
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authentication_failure() {
+        let (client, server) = tokio::io::duplex(1024);
+        let server = tokio::spawn(ClickHouseServer::run_on_transport(
+            Arc::new(Session),
+            server
+        ));
+        let mut client = TestClient::new(client, Some(server));
+
+        match client.hello("nobody").await {
+            Err(Error::Server(e)) => assert!(e.message.contains("unknown user")),
+            res => panic!("unexpected result {:?}", res)
+        }
+        assert!(client.close().await.is_err());
+    }
+
+    struct UsersSession {
+        users: auth::StaticUsers
+    }
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for UsersSession {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Ok(())
+        }
+
+        fn authenticator(&self) -> Option<&dyn Authenticator> {
+            Some(&self.users)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_authenticator() {
+        let session = Arc::new(UsersSession {
+            users: auth::StaticUsers::new().user("admin", auth::Password::sha256("secret"))
+        });
+
+        let mut client = TestClient::connect(session.clone());
+        client.hello_with_password("admin", "secret").await.unwrap();
+        client.close().await.unwrap();
+
+        for (user, password) in &[("admin", "wrong"), ("nobody", "secret")] {
+            let mut client = TestClient::connect(session.clone());
+            match client.hello_with_password(user, password).await {
+                Err(Error::Server(e)) => assert_eq!(e.code, error_codes::AUTHENTICATION_FAILED),
+                res => panic!("unexpected result {:?}", res)
+            }
+            assert!(client.close().await.is_err());
+        }
+    }
+
+    // Records the lifecycle of the connections, the queries run on a session per connection.
+    struct Lifecycle {
+        events: Arc<std::sync::Mutex<Vec<String>>>
+    }
+
+    struct ScopedSession {
+        user: String,
+        events: Arc<std::sync::Mutex<Vec<String>>>
+    }
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for Lifecycle {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            unreachable!("queries are run by the scoped session")
+        }
+
+        async fn on_connect(&self, _peer_addr: Option<SocketAddr>) -> Result<()> {
+            let mut events = self.events.lock().unwrap();
+            events.push("connect".to_string());
+            match events.len() {
+                1 => Ok(()),
+                _ => Err("too many connections".into())
+            }
+        }
+
+        async fn on_hello(
+            &self,
+            hello: &HelloRequest
+        ) -> Result<Option<Arc<dyn ClickHouseSession>>> {
+            Ok(Some(Arc::new(ScopedSession {
+                user: hello.user.clone(),
+                events: self.events.clone()
+            })))
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for ScopedSession {
+        async fn execute_query(
+            &self,
+            _ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            let block = Block::new().column("user", vec![self.user.clone()]);
+            connection.write_block(&block).await
+        }
+
+        async fn on_disconnect(&self, _ctx: &CHContext) {
+            let event = format!("disconnect {}", self.user);
+            self.events.lock().unwrap().push(event);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_session_lifecycle() {
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let session = Arc::new(Lifecycle {
+            events: events.clone()
+        });
+
+        let mut client = TestClient::connect(session.clone());
+        client.hello("default").await.unwrap();
+        client.query("SELECT currentUser()").await;
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Data(block) => {
+                assert_eq!(block.get::<String, _>(0, "user").unwrap(), "default")
+            }
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        client.read_packet().await.unwrap();
+        client.close().await.unwrap();
+
+        // the second connection is rejected by `on_connect`
+        let mut client = TestClient::connect(session);
+        match client.hello("default").await {
+            Err(Error::Server(e)) => assert!(e.message.contains("too many connections")),
+            res => panic!("unexpected result {:?}", res)
+        }
+        assert!(client.close().await.is_err());
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            "connect",
+            "disconnect default",
+            "connect"
+        ]);
+    }
 }
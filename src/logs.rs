@@ -0,0 +1,261 @@
+//! Server logs sent to the client with `SERVER_LOG` packets, e.g. for `send_logs_level`.
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use chrono::TimeZone;
+use chrono_tz::Tz;
+use lazy_static::lazy_static;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+use log::SetLoggerError;
+
+use crate::types::Block;
+
+tokio::task_local! {
+    static QUERY_LOGS: Arc<LogQueue>;
+}
+
+/// The priority of a server log message, the same as `Poco::Message::Priority`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogPriority {
+    Fatal = 1,
+    Critical = 2,
+    Error = 3,
+    Warning = 4,
+    Notice = 5,
+    Information = 6,
+    Debug = 7,
+    Trace = 8
+}
+
+impl From<Level> for LogPriority {
+    fn from(level: Level) -> Self {
+        match level {
+            Level::Error => LogPriority::Error,
+            Level::Warn => LogPriority::Warning,
+            Level::Info => LogPriority::Information,
+            Level::Debug => LogPriority::Debug,
+            Level::Trace => LogPriority::Trace
+        }
+    }
+}
+
+/// A row of the `system.text_log` shaped block sent to the client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerLog {
+    /// Seconds since the unix epoch.
+    pub event_time: u32,
+    pub event_time_microseconds: u32,
+    pub host_name: String,
+    pub query_id: String,
+    pub thread_id: u64,
+    pub priority: LogPriority,
+    pub source: String,
+    pub text: String
+}
+
+impl ServerLog {
+    /// Creates a message of the current time and host.
+    pub fn new(
+        query_id: impl Into<String>,
+        priority: LogPriority,
+        source: impl Into<String>,
+        text: impl Into<String>
+    ) -> Self {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        Self {
+            event_time: now.as_secs() as u32,
+            event_time_microseconds: now.subsec_micros(),
+            host_name: HOSTNAME.clone(),
+            query_id: query_id.into(),
+            thread_id: 0,
+            priority,
+            source: source.into(),
+            text: text.into()
+        }
+    }
+
+    pub(crate) fn to_block(logs: &[ServerLog]) -> Block {
+        Block::new()
+            .column(
+                "event_time",
+                logs.iter()
+                    .map(|log| Tz::UTC.timestamp_opt(log.event_time as i64, 0).unwrap())
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "event_time_microseconds",
+                logs.iter()
+                    .map(|log| log.event_time_microseconds)
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "host_name",
+                logs.iter()
+                    .map(|log| log.host_name.as_str())
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "query_id",
+                logs.iter()
+                    .map(|log| log.query_id.as_str())
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "thread_id",
+                logs.iter().map(|log| log.thread_id).collect::<Vec<_>>()
+            )
+            .column(
+                "priority",
+                logs.iter()
+                    .map(|log| log.priority as i8)
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "source",
+                logs.iter()
+                    .map(|log| log.source.as_str())
+                    .collect::<Vec<_>>()
+            )
+            .column(
+                "text",
+                logs.iter().map(|log| log.text.as_str()).collect::<Vec<_>>()
+            )
+    }
+}
+
+/// Converts the `send_logs_level` setting of the client into a level filter.
+pub fn parse_send_logs_level(level: &str) -> LevelFilter {
+    match level.to_ascii_lowercase().as_str() {
+        // the `log` crate has no fatal level, errors are its most severe records
+        "fatal" | "error" => LevelFilter::Error,
+        "warning" => LevelFilter::Warn,
+        "information" => LevelFilter::Info,
+        "debug" => LevelFilter::Debug,
+        "trace" | "test" => LevelFilter::Trace,
+        _ => LevelFilter::Off
+    }
+}
+
+/// Log messages of a running query, waiting to be sent to the client.
+pub(crate) struct LogQueue {
+    level: LevelFilter,
+    query_id: String,
+    logs: Mutex<Vec<ServerLog>>
+}
+
+impl LogQueue {
+    pub(crate) fn new(level: LevelFilter, query_id: String) -> Self {
+        Self {
+            level,
+            query_id,
+            logs: Mutex::new(Vec::new())
+        }
+    }
+
+    pub(crate) fn take(&self) -> Vec<ServerLog> {
+        std::mem::take(&mut *self.logs.lock().unwrap())
+    }
+
+    fn push(&self, record: &Record) {
+        if record.level() > self.level {
+            return;
+        }
+
+        let log = ServerLog::new(
+            self.query_id.as_str(),
+            record.level().into(),
+            record.target(),
+            record.args().to_string()
+        );
+        self.logs.lock().unwrap().push(log);
+    }
+
+    /// Runs the query, records logged from it are collected in this queue.
+    pub(crate) async fn scope<F: std::future::Future>(self: Arc<Self>, f: F) -> F::Output {
+        QUERY_LOGS.scope(self, f).await
+    }
+}
+
+/// A `log` crate logger which forwards records emitted while a query is running to the
+/// client that issued it, and passes every record to the inner logger.
+///
+/// Only the records logged from the task running `ClickHouseSession::execute_query` are
+/// forwarded, not the ones of the tasks it spawns.
+pub struct ClientLogger<L: Log> {
+    inner: L
+}
+
+impl<L: Log + 'static> ClientLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self { inner }
+    }
+
+    /// Installs the logger as the global `log` logger. `max_level` should be at least the
+    /// most verbose `send_logs_level` clients will ask for.
+    pub fn init(inner: L, max_level: LevelFilter) -> Result<(), SetLoggerError> {
+        log::set_boxed_logger(Box::new(Self::new(inner)))?;
+        log::set_max_level(max_level);
+        Ok(())
+    }
+}
+
+impl<L: Log> Log for ClientLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+            || QUERY_LOGS
+                .try_with(|queue| metadata.level() <= queue.level)
+                .unwrap_or(false)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.inner.enabled(record.metadata()) {
+            self.inner.log(record);
+        }
+        let _ = QUERY_LOGS.try_with(|queue| queue.push(record));
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+lazy_static! {
+    static ref HOSTNAME: String = hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .unwrap_or_default();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_send_logs_level() {
+        assert_eq!(parse_send_logs_level("none"), LevelFilter::Off);
+        assert_eq!(parse_send_logs_level("warning"), LevelFilter::Warn);
+        assert_eq!(parse_send_logs_level("information"), LevelFilter::Info);
+        assert_eq!(parse_send_logs_level("trace"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn test_to_block() {
+        let log = ServerLog::new("q1", LogPriority::Debug, "source", "hello");
+        let block = ServerLog::to_block(&[log.clone(), log]);
+
+        assert_eq!(block.row_count(), 2);
+        assert_eq!(block.column_count(), 8);
+        assert_eq!(block.get::<i8, _>(0, "priority").unwrap(), 7);
+        assert_eq!(block.get::<String, _>(1, "text").unwrap(), "hello");
+    }
+}
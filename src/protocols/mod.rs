@@ -1,12 +1,15 @@
 mod protocol_exception;
 mod protocol_hello;
 mod protocol_query;
+mod protocol_tables_status;
 mod protocol_type;
 
 pub use protocol_exception::*;
 pub use protocol_hello::*;
 pub use protocol_query::*;
+pub use protocol_tables_status::*;
 pub use protocol_type::*;
+use uuid::Uuid;
 
 use crate::types::Block;
 
@@ -15,16 +18,35 @@ pub enum Packet {
     Ping,
     Cancel,
     Hello(HelloRequest),
-    Query(QueryRequest),
-    Data(Block)
+    Query(Box<QueryRequest>),
+    Data(Block),
+    /// A block of an external table, sent right after the query.
+    ExternalTable(String, Block),
+    /// The result of a scalar subquery, sent right after the query.
+    Scalar(String, Block),
+    TablesStatusRequest(Vec<QualifiedTableName>),
+    KeepAlive,
+    /// The parts the next query must skip, sent by replicas reading in parallel.
+    IgnoredPartUUIDs(Vec<Uuid>)
+}
+
+impl Packet {
+    // The packet of a block received with `CLIENT_DATA` or `CLIENT_SCALAR`.
+    pub(crate) fn data(scalar: bool, name: String, block: Block) -> Packet {
+        if scalar {
+            Packet::Scalar(name, block)
+        } else if name.is_empty() {
+            Packet::Data(block)
+        } else {
+            Packet::ExternalTable(name, block)
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum Stage {
     Default = 0,
-    InsertPrepare,
-    InsertStarted,
-    EOS
+    InsertStarted
 }
 impl Default for Stage {
     fn default() -> Self {
@@ -61,3 +83,28 @@ pub const DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET: u64 = 54441;
 
 pub const DBMS_MIN_REVISION_WITH_X_FORWARDED_FOR_IN_CLIENT_INFO: u64 = 54443;
 pub const DBMS_MIN_REVISION_WITH_REFERER_IN_CLIENT_INFO: u64 = 54447;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_DISTRIBUTED_DEPTH: u64 = 54448;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_INITIAL_QUERY_START_TIME: u64 = 54449;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_INCREMENTAL_PROFILE_EVENTS: u64 = 54451;
+pub const DBMS_MIN_REVISION_WITH_PARALLEL_REPLICAS: u64 = 54453;
+// A byte telling whether the column has a custom serialization follows the type of
+// each column in the Native format.
+pub const DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION: u64 = 54454;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_PROFILE_EVENTS_IN_INSERT: u64 = 54456;
+// The client sends an addendum after the `Hello` of the server.
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM: u64 = 54458;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_QUOTA_KEY: u64 = 54458;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_PARAMETERS: u64 = 54459;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS: u64 = 54460;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_PASSWORD_COMPLEXITY_RULES: u64 = 54461;
+pub const DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET_V2: u64 = 54462;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS: u64 = 54463;
+pub const DBMS_MIN_PROTOCOL_VERSION_WITH_TIMEZONE_UPDATES: u64 = 54464;
+pub const DBMS_MIN_REVISION_WITH_SPARSE_SERIALIZATION: u64 = 54465;
+pub const DBMS_MIN_REVISION_WITH_SSH_AUTHENTICATION: u64 = 54466;
+pub const DBMS_MIN_REVISION_WITH_TABLE_READ_ONLY_CHECK: u64 = 54467;
+pub const DBMS_MIN_REVISION_WITH_SYSTEM_KEYWORDS_TABLE: u64 = 54468;
+pub const DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION: u64 = 54469;
+
+// The latest revision this crate speaks, the negotiated revision never exceeds it.
+pub const DBMS_TCP_PROTOCOL_VERSION: u64 = 54469;
@@ -1,34 +1,108 @@
+use std::error::Error as StdError;
+
 use crate::binary::Encoder;
 use crate::error_codes::UNKNOWN_EXCEPTION;
+use crate::errors::DriverError;
 use crate::errors::Error;
+use crate::errors::FromSqlError;
+use crate::errors::ServerError;
 use crate::protocols::*;
 
 pub struct ExceptionResponse {}
 
 impl ExceptionResponse {
+    /// Writes `error`, its chain of sources follows as nested exceptions.
     pub fn write(encoder: &mut Encoder, error: &Error, with_stack_trace: bool) {
-        let mut code = UNKNOWN_EXCEPTION;
-        let name = error.exception_name();
-        let mut stack_trace = "".to_string();
-        let mut message = error.to_string();
-
-        if let Error::Server(e) = error {
-            code = e.code;
-            if with_stack_trace {
-                stack_trace = e.stack_trace.clone();
-            }
-            message = e.message.clone();
-        }
         encoder.uvarint(SERVER_EXCEPTION);
 
-        encoder.write(code);
-        //Name
-        encoder.string(name);
-        // Message
-        encoder.string(message);
-        // StackTrace
-        encoder.string(stack_trace);
-        // Nested.
-        encoder.write(false);
+        let mut exception: Option<&(dyn StdError + 'static)> = Some(error);
+        while let Some(e) = exception {
+            let (code, name, message, stack_trace) = describe(e);
+            encoder.write(code);
+            encoder.string(name);
+            encoder.string(message);
+            encoder.string(if with_stack_trace { stack_trace } else { "" });
+
+            exception = source(e);
+            // Nested.
+            encoder.write(exception.is_some());
+        }
+    }
+}
+
+// The code, name, message and stack trace of an exception.
+fn describe<'a>(error: &'a (dyn StdError + 'static)) -> (u32, &'a str, String, &'a str) {
+    if let Some(e) = error.downcast_ref::<Error>() {
+        match e {
+            Error::Server(e) => (e.code, &e.name, e.message.clone(), &e.stack_trace),
+            e => (e.code(), e.exception_name(), e.to_string(), "")
+        }
+    } else if let Some(e) = error.downcast_ref::<ServerError>() {
+        (e.code, &e.name, e.message.clone(), &e.stack_trace)
+    } else if let Some(e) = error.downcast_ref::<DriverError>() {
+        (e.code(), "DriverException", e.to_string(), "")
+    } else if let Some(e) = error.downcast_ref::<FromSqlError>() {
+        (e.code(), "SQLException", e.to_string(), "")
+    } else {
+        (UNKNOWN_EXCEPTION, "OtherException", error.to_string(), "")
+    }
+}
+
+// The cause of `error`. The variants of `Error` wrap another error which is already
+// described by the variant, its own source follows.
+fn source<'a>(error: &'a (dyn StdError + 'static)) -> Option<&'a (dyn StdError + 'static)> {
+    match error.downcast_ref::<Error>() {
+        Some(e) => e.source().and_then(|wrapped| wrapped.source()),
+        None => error.source()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::errors::ServerError;
+
+    #[test]
+    fn test_write() {
+        let error = Error::Server(ServerError {
+            code: 60,
+            name: "UNKNOWN_TABLE".to_string(),
+            message: "no t".to_string(),
+            stack_trace: "at".to_string()
+        });
+
+        // the format doesn't depend on the revision
+        let mut encoder = Encoder::new();
+        ExceptionResponse::write(&mut encoder, &error, true);
+        let mut expected = vec![2_u8, 60, 0, 0, 0, 13];
+        expected.extend_from_slice(b"UNKNOWN_TABLE\x04no t\x02at\x00");
+        assert_eq!(encoder.get_buffer(), expected);
+
+        let mut encoder = Encoder::new();
+        ExceptionResponse::write(&mut encoder, &error, false);
+        let mut expected = vec![2_u8, 60, 0, 0, 0, 13];
+        expected.extend_from_slice(b"UNKNOWN_TABLE\x04no t\x00\x00");
+        assert_eq!(encoder.get_buffer(), expected);
+    }
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("cannot read t")]
+    struct ReadError(#[source] Error);
+
+    #[test]
+    fn test_write_nested() {
+        let cause = Error::from(DriverError::UnexpectedPacket);
+        let error = Error::from(std::io::Error::other(ReadError(cause)));
+
+        let mut encoder = Encoder::new();
+        ExceptionResponse::write(&mut encoder, &error, true);
+        let mut expected = vec![2_u8];
+        expected.extend_from_slice(&1001_u32.to_le_bytes());
+        expected
+            .extend_from_slice(b"\x0bIOException\x23Input/output error: `cannot read t`\x00\x01");
+        expected.extend_from_slice(&101_u32.to_le_bytes());
+        expected.extend_from_slice(b"\x0fDriverException");
+        expected.extend_from_slice(b"\x22Driver error: `Unexpected packet.`\x00\x00");
+        assert_eq!(encoder.get_buffer(), expected);
     }
 }
@@ -20,7 +20,10 @@ pub struct HelloRequest {
     pub password: String,
 
     // Not set currently
-    pub client_version_patch: u64
+    pub client_version_patch: u64,
+    /// Sent in the addendum which follows the handshake, it's still empty when
+    /// [`ClickHouseSession::on_hello`](crate::ClickHouseSession::on_hello) is called.
+    pub quota_key: String
 }
 
 impl HelloRequest {
@@ -34,7 +37,8 @@ impl HelloRequest {
             user: reader.read_string()?,
             password: reader.read_string()?,
 
-            client_version_patch: 0
+            client_version_patch: 0,
+            quota_key: String::new()
         };
 
         if request.user.is_empty() {
@@ -61,7 +65,12 @@ pub struct HelloResponse {
     pub dbms_tcp_protocol_version: u64,
     pub timezone: String,
     pub server_display_name: String,
-    pub dbms_version_patch: u64
+    pub dbms_version_patch: u64,
+    /// The rules new passwords must match, as pairs of a regular expression and the
+    /// message shown when it doesn't match.
+    pub password_complexity_rules: Vec<(String, String)>,
+    /// Salts the hash of the interserver secret sent with queries.
+    pub nonce: u64
 }
 
 impl HelloResponse {
@@ -85,6 +94,95 @@ impl HelloResponse {
             encoder.uvarint(self.dbms_version_patch);
         }
 
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_PASSWORD_COMPLEXITY_RULES {
+            encoder.uvarint(self.password_complexity_rules.len() as u64);
+            for (pattern, message) in &self.password_complexity_rules {
+                encoder.string(pattern);
+                encoder.string(message);
+            }
+        }
+
+        if client_revision >= DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET_V2 {
+            encoder.write(self.nonce);
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_read_hello() {
+        let bytes = [
+            4, b't', b'e', b's', b't', 24, 8, 0xc5, 0xa9, 0x03, 0, 7, b'd', b'e', b'f', b'a', b'u',
+            b'l', b't', 3, b'p', b'w', b'd'
+        ];
+        let mut reader = Cursor::new(&bytes[..]);
+        let hello = HelloRequest::read_from(&mut reader).unwrap();
+
+        assert_eq!(hello.client_name, "test");
+        assert_eq!(hello.client_version_major, 24);
+        assert_eq!(hello.client_version_minor, 8);
+        assert_eq!(hello.client_revision, DBMS_TCP_PROTOCOL_VERSION);
+        assert_eq!(hello.default_database, "");
+        assert_eq!(hello.user, "default");
+        assert_eq!(hello.password, "pwd");
+        assert_eq!(reader.position() as usize, bytes.len());
+    }
+
+    #[test]
+    fn test_encode_response() {
+        let response = HelloResponse {
+            dbms_name: "ch".to_string(),
+            dbms_version_major: 24,
+            dbms_version_minor: 8,
+            dbms_tcp_protocol_version: DBMS_TCP_PROTOCOL_VERSION,
+            timezone: "UTC".to_string(),
+            server_display_name: "x".to_string(),
+            dbms_version_patch: 1,
+            password_complexity_rules: vec![(".{8}".to_string(), "8 chars".to_string())],
+            nonce: 0x0102_0304_0506_0708
+        };
+
+        let header = [0_u8, 2, b'c', b'h', 24, 8, 0xc5, 0xa9, 0x03];
+        let rules = [
+            1_u8, 4, b'.', b'{', b'8', b'}', 7, b'8', b' ', b'c', b'h', b'a', b'r', b's'
+        ];
+        let nonce = [8_u8, 7, 6, 5, 4, 3, 2, 1];
+        let cases: [(u64, Vec<&[u8]>); 6] = [
+            (54057, vec![&header]),
+            (DBMS_MIN_REVISION_WITH_SERVER_TIMEZONE, vec![
+                &header, b"\x03UTC",
+            ]),
+            (DBMS_MIN_REVISION_WITH_SERVER_DISPLAY_NAME, vec![
+                &header, b"\x03UTC", b"\x01x",
+            ]),
+            (DBMS_MIN_REVISION_WITH_VERSION_PATCH, vec![
+                &header, b"\x03UTC", b"\x01x", b"\x01",
+            ]),
+            (
+                DBMS_MIN_PROTOCOL_VERSION_WITH_PASSWORD_COMPLEXITY_RULES,
+                vec![&header, b"\x03UTC", b"\x01x", b"\x01", &rules]
+            ),
+            (DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET_V2, vec![
+                &header, b"\x03UTC", b"\x01x", b"\x01", &rules, &nonce,
+            ])
+        ];
+
+        for (revision, expected) in cases.iter() {
+            let mut encoder = Encoder::new();
+            response.encode(&mut encoder, *revision).unwrap();
+            assert_eq!(
+                encoder.get_buffer(),
+                expected.concat(),
+                "revision {}",
+                revision
+            );
+        }
+    }
+}
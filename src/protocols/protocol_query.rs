@@ -2,20 +2,80 @@ use std::io::Read;
 
 use super::*;
 use crate::binary::ReadEx;
-use crate::errors::DriverError::UnknownSetting;
-use crate::errors::Error;
 use crate::errors::Result;
+use crate::types::Settings;
 
-const TCP: u8 = 1;
-const HTTP: u8 = 2;
+pub const INTERFACE_TCP: u8 = 1;
+pub const INTERFACE_HTTP: u8 = 2;
 
-#[derive(Default, Debug)]
+/// The stage up to which the client asked to process the query.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ProcessingStage {
+    /// Only read the columns.
+    FetchColumns,
+    /// Until the states of the aggregate functions, for distributed queries.
+    WithMergeableState,
+    /// The final result.
+    Complete,
+    WithMergeableStateAfterAggregation
+}
+
+impl ProcessingStage {
+    pub fn from_u64(stage: u64) -> Option<Self> {
+        match stage {
+            0 => Some(ProcessingStage::FetchColumns),
+            1 => Some(ProcessingStage::WithMergeableState),
+            2 => Some(ProcessingStage::Complete),
+            3 => Some(ProcessingStage::WithMergeableStateAfterAggregation),
+            _ => None
+        }
+    }
+}
+
+/// The W3C trace context of the span which sent the query, to continue the trace.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TraceContext {
+    pub trace_id: u128,
+    pub span_id: u64,
+    pub tracestate: String,
+    pub trace_flags: u8
+}
+
+impl TraceContext {
+    fn read_from<R: Read>(reader: &mut R) -> Result<TraceContext> {
+        // sent as an UUID: the high half first
+        let high: u64 = reader.read_scalar()?;
+        let low: u64 = reader.read_scalar()?;
+
+        Ok(TraceContext {
+            trace_id: (u128::from(high) << 64) | u128::from(low),
+            span_id: reader.read_scalar()?,
+            tracestate: reader.read_string()?,
+            trace_flags: reader.read_scalar()?
+        })
+    }
+
+    /// The value of a `traceparent` header for this span.
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id, self.span_id, self.trace_flags
+        )
+    }
+}
+
+/// Who sent the query and how, see `system.query_log` of ClickHouse.
+#[derive(Default, Debug, Clone)]
 pub struct QueryClientInfo {
+    /// `INITIAL_QUERY`, or `SECONDARY_QUERY` for the parts of distributed queries.
     pub query_kind: u8,
     pub initial_user: String,
     pub initial_query_id: String,
 
     pub initial_address: String,
+    /// When the initial query started, in microseconds since the epoch.
+    pub initial_query_start_time_microseconds: u64,
+    /// `INTERFACE_TCP` or `INTERFACE_HTTP`.
     pub interface: u8,
 
     // TCP
@@ -31,12 +91,27 @@ pub struct QueryClientInfo {
     // HTTP
     pub http_method: u8,
     pub http_user_agent: String,
+    /// The `X-Forwarded-For` header.
+    pub forwarded_for: String,
+    /// The `Referer` header.
+    pub http_referer: String,
+
+    pub quota_key: String,
+    /// The number of servers the query went through, for distributed queries.
+    pub distributed_depth: u64,
+    /// Set if the query is a part of a distributed trace.
+    pub trace_context: Option<TraceContext>,
 
-    pub quota_key: String
+    // Parallel replicas
+    pub collaborate_with_initiator: bool,
+    pub count_participating_replicas: u64,
+    pub number_of_current_replica: u64
 }
 
 impl QueryClientInfo {
-    pub fn read_from<R: Read>(reader: &mut R) -> Result<QueryClientInfo> {
+    /// Reads the client info of a query, `client_revision` is the revision negotiated
+    /// in the handshake.
+    pub fn read_from<R: Read>(reader: &mut R, client_revision: u64) -> Result<QueryClientInfo> {
         let mut client_info = QueryClientInfo {
             query_kind: reader.read_scalar()?,
             ..Default::default()
@@ -49,68 +124,94 @@ impl QueryClientInfo {
         client_info.initial_user = reader.read_string()?;
         client_info.initial_query_id = reader.read_string()?;
         client_info.initial_address = reader.read_string()?;
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_INITIAL_QUERY_START_TIME {
+            client_info.initial_query_start_time_microseconds = reader.read_scalar()?;
+        }
         client_info.interface = reader.read_scalar()?;
 
         match client_info.interface {
-            TCP => {
+            INTERFACE_TCP => {
                 client_info.os_user = reader.read_string()?;
                 client_info.client_hostname = reader.read_string()?;
                 client_info.client_name = reader.read_string()?;
 
                 client_info.client_version_major = reader.read_uvarint()?;
                 client_info.client_version_minor = reader.read_uvarint()?;
-                let client_revision = reader.read_uvarint()?;
+                let revision = reader.read_uvarint()?;
 
-                client_info.client_revision = client_revision;
-                client_info.client_version_patch = client_revision;
+                client_info.client_revision = revision;
+                client_info.client_version_patch = revision;
             }
-            HTTP => {
+            INTERFACE_HTTP => {
                 client_info.http_method = reader.read_scalar()?;
                 client_info.http_user_agent = reader.read_string()?;
+
+                if client_revision >= DBMS_MIN_REVISION_WITH_X_FORWARDED_FOR_IN_CLIENT_INFO {
+                    client_info.forwarded_for = reader.read_string()?;
+                }
+                if client_revision >= DBMS_MIN_REVISION_WITH_REFERER_IN_CLIENT_INFO {
+                    client_info.http_referer = reader.read_string()?;
+                }
             }
             _ => {}
         }
 
-        if client_info.client_revision >= DBMS_MIN_REVISION_WITH_QUOTA_KEY_IN_CLIENT_INFO {
+        // the fields below depend on the negotiated revision, not on the one of the
+        // client which started the query
+        if client_revision >= DBMS_MIN_REVISION_WITH_QUOTA_KEY_IN_CLIENT_INFO {
             client_info.quota_key = reader.read_string()?;
         }
 
-        if client_info.interface == TCP
-            && client_info.client_revision >= DBMS_MIN_REVISION_WITH_VERSION_PATCH
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_DISTRIBUTED_DEPTH {
+            client_info.distributed_depth = reader.read_uvarint()?;
+        }
+
+        if client_info.interface == INTERFACE_TCP
+            && client_revision >= DBMS_MIN_REVISION_WITH_VERSION_PATCH
         {
             client_info.client_version_patch = reader.read_uvarint()?;
         }
 
-        // TODO
-        // if client_info.client_revision >= DBMS_MIN_REVISION_WITH_OPENTELEMETRY {
-        //     let trace_id: u8 = reader.read_scalar()?;
-        //     if trace_id > 0 {
-        //     }
-        // }
+        if client_revision >= DBMS_MIN_REVISION_WITH_OPENTELEMETRY {
+            let have_trace_id: u8 = reader.read_scalar()?;
+            if have_trace_id > 0 {
+                client_info.trace_context = Some(TraceContext::read_from(reader)?);
+            }
+        }
+
+        if client_revision >= DBMS_MIN_REVISION_WITH_PARALLEL_REPLICAS {
+            client_info.collaborate_with_initiator = reader.read_uvarint()? != 0;
+            client_info.count_participating_replicas = reader.read_uvarint()?;
+            client_info.number_of_current_replica = reader.read_uvarint()?;
+        }
 
         Ok(client_info)
     }
 }
 
+/// A query sent by the client, see [`CHContext::query`](crate::CHContext::query).
 #[derive(Default, Debug)]
 pub struct QueryRequest {
     pub(crate) query_id: String,
     pub(crate) client_info: QueryClientInfo,
+    pub(crate) settings: Settings,
     pub(crate) stage: u64,
     pub(crate) compression: u64,
-    pub(crate) query: String
+    pub(crate) query: String,
+    pub(crate) parameters: Settings
 }
 
 impl QueryRequest {
     pub fn read_from<R: Read>(
         reader: &mut R,
-        hello_request: &HelloRequest
+        hello_request: &HelloRequest,
+        client_revision: u64
     ) -> Result<QueryRequest> {
         let query_id = reader.read_string()?;
 
         let mut client_info = Default::default();
-        if hello_request.client_revision >= DBMS_MIN_REVISION_WITH_CLIENT_INFO {
-            client_info = QueryClientInfo::read_from(reader)?;
+        if client_revision >= DBMS_MIN_REVISION_WITH_CLIENT_INFO {
+            client_info = QueryClientInfo::read_from(reader, client_revision)?;
         }
 
         if client_info.query_kind == 0 {
@@ -122,33 +223,299 @@ impl QueryRequest {
             client_info.client_revision = hello_request.client_revision;
         }
 
-        client_info.interface = TCP;
-
-        loop {
-            let name = reader.read_string()?;
+        client_info.interface = INTERFACE_TCP;
 
-            if name.is_empty() {
-                break;
+        // the client itself started the query
+        if client_info.query_kind == INITIAL_QUERY {
+            if client_info.initial_user.is_empty() {
+                client_info.initial_user = hello_request.user.clone();
             }
-
-            match name.as_str() {
-                "max_block_size" | "max_threads" => {
-                    let _ = reader.read_uvarint()?;
-                }
-                _ => {
-                    return Err(Error::Driver(UnknownSetting { name }));
-                }
+            if client_info.initial_query_id.is_empty() {
+                client_info.initial_query_id = query_id.clone();
             }
         }
 
-        let query_protocol = QueryRequest {
+        let settings = Settings::read_from(reader, client_revision)?;
+
+        // only checked by servers of a cluster sharing the secret
+        if client_revision >= DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET {
+            let _interserver_secret_hash = reader.read_string()?;
+        }
+
+        let mut query_protocol = QueryRequest {
             query_id,
             client_info,
+            settings,
             stage: reader.read_uvarint()?,
             compression: reader.read_uvarint()?,
-            query: reader.read_string()?
+            query: reader.read_string()?,
+            parameters: Settings::default()
         };
 
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_PARAMETERS {
+            query_protocol.parameters = Settings::read_from(reader, client_revision)?;
+        }
+
         Ok(query_protocol)
     }
+
+    pub fn query_id(&self) -> &str {
+        &self.query_id
+    }
+
+    pub fn client_info(&self) -> &QueryClientInfo {
+        &self.client_info
+    }
+
+    /// The trace context sent by the client, if the query is traced.
+    pub fn trace_context(&self) -> Option<&TraceContext> {
+        self.client_info.trace_context.as_ref()
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// The stage sent by the client, `None` if it's unknown.
+    pub fn stage(&self) -> Option<ProcessingStage> {
+        ProcessingStage::from_u64(self.stage)
+    }
+
+    pub fn compression(&self) -> bool {
+        self.compression > 0
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    /// The values of the `{name:Type}` placeholders of the query, as sent by the client.
+    pub fn parameters(&self) -> &Settings {
+        &self.parameters
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::binary::Encoder;
+
+    const REVISION: u64 = 54405;
+
+    fn hello() -> HelloRequest {
+        HelloRequest {
+            client_name: "test".to_string(),
+            client_revision: REVISION,
+            user: "default".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_read_client_info() {
+        let mut encoder = Encoder::new();
+        encoder.string("q1");
+        encoder.write(SECONDARY_QUERY);
+        encoder.string("alice");
+        encoder.string("q0");
+        encoder.string("10.0.0.1:9000");
+        encoder.write(INTERFACE_TCP);
+        encoder.string("bob");
+        encoder.string("host");
+        encoder.string("ClickHouse client");
+        encoder.uvarint(21);
+        encoder.uvarint(8);
+        encoder.uvarint(REVISION);
+        encoder.string("key");
+        encoder.uvarint(3);
+        // end of settings
+        encoder.string("");
+        encoder.uvarint(1);
+        encoder.uvarint(0);
+        encoder.string("SELECT 1");
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let query = QueryRequest::read_from(&mut reader, &hello(), REVISION).unwrap();
+
+        assert_eq!(query.query_id(), "q1");
+        assert_eq!(query.query(), "SELECT 1");
+        assert_eq!(query.stage(), Some(ProcessingStage::WithMergeableState));
+        assert!(!query.compression());
+
+        let info = query.client_info();
+        assert_eq!(info.query_kind, SECONDARY_QUERY);
+        assert_eq!(info.initial_user, "alice");
+        assert_eq!(info.initial_query_id, "q0");
+        assert_eq!(info.initial_address, "10.0.0.1:9000");
+        assert_eq!(info.os_user, "bob");
+        assert_eq!(info.client_hostname, "host");
+        assert_eq!(info.client_name, "ClickHouse client");
+        assert_eq!(info.client_version_major, 21);
+        assert_eq!(info.client_version_patch, 3);
+        assert_eq!(info.quota_key, "key");
+    }
+
+    #[test]
+    fn test_read_without_client_info() {
+        let mut encoder = Encoder::new();
+        encoder.string("q1");
+        encoder.write(NO_QUERY);
+        encoder.string("");
+        encoder.uvarint(2);
+        encoder.uvarint(1);
+        encoder.string("SELECT 1");
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let query = QueryRequest::read_from(&mut reader, &hello(), REVISION).unwrap();
+
+        // an initial query, as described by `Hello`
+        let info = query.client_info();
+        assert_eq!(info.query_kind, INITIAL_QUERY);
+        assert_eq!(info.initial_user, "default");
+        assert_eq!(info.initial_query_id, "q1");
+        assert_eq!(info.client_name, "test");
+        assert_eq!(info.interface, INTERFACE_TCP);
+        assert_eq!(query.stage(), Some(ProcessingStage::Complete));
+        assert!(query.compression());
+    }
+
+    fn client_info_header(encoder: &mut Encoder, interface: u8) {
+        encoder.write(INITIAL_QUERY);
+        encoder.string("default");
+        encoder.string("q1");
+        encoder.string("127.0.0.1:9000");
+        encoder.write(interface);
+    }
+
+    #[test]
+    fn test_read_trace_context() {
+        let revision = DBMS_MIN_REVISION_WITH_REFERER_IN_CLIENT_INFO;
+        let mut encoder = Encoder::new();
+        client_info_header(&mut encoder, INTERFACE_TCP);
+        encoder.string("bob");
+        encoder.string("host");
+        encoder.string("ClickHouse client");
+        encoder.uvarint(22);
+        encoder.uvarint(3);
+        encoder.uvarint(revision);
+        encoder.string("");
+        encoder.uvarint(1);
+        // OpenTelemetry
+        encoder.write(1_u8);
+        encoder.write(0x0af7_6519_16cd_43dd_u64);
+        encoder.write(0x8448_eb21_1c80_319c_u64);
+        encoder.write(0xb7ad_6b71_6920_3331_u64);
+        encoder.string("congo=t61rcWkgMzE");
+        encoder.write(1_u8);
+        encoder.write(42_u8);
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let info = QueryClientInfo::read_from(&mut reader, revision).unwrap();
+        // nothing more is read
+        assert_eq!(reader.read_scalar::<u8>().unwrap(), 42);
+
+        assert_eq!(info.client_version_patch, 1);
+        let trace_context = info.trace_context.unwrap();
+        assert_eq!(trace_context.tracestate, "congo=t61rcWkgMzE");
+        assert_eq!(
+            trace_context.traceparent(),
+            "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01"
+        );
+    }
+
+    #[test]
+    fn test_read_http_headers() {
+        let revision = DBMS_MIN_REVISION_WITH_REFERER_IN_CLIENT_INFO;
+        let mut encoder = Encoder::new();
+        client_info_header(&mut encoder, INTERFACE_HTTP);
+        encoder.write(2_u8);
+        encoder.string("curl/7.68.0");
+        encoder.string("10.0.0.1");
+        encoder.string("http://example.com/");
+        encoder.string("quota");
+        // no trace
+        encoder.write(0_u8);
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let info = QueryClientInfo::read_from(&mut reader, revision).unwrap();
+
+        assert_eq!(info.http_user_agent, "curl/7.68.0");
+        assert_eq!(info.forwarded_for, "10.0.0.1");
+        assert_eq!(info.http_referer, "http://example.com/");
+        assert_eq!(info.quota_key, "quota");
+        assert_eq!(info.trace_context, None);
+        assert!(reader.read_scalar::<u8>().is_err());
+    }
+
+    #[test]
+    fn test_read_latest_revision() {
+        let revision = DBMS_TCP_PROTOCOL_VERSION;
+        let mut encoder = Encoder::new();
+        encoder.string("q1");
+        encoder.write(INITIAL_QUERY);
+        encoder.string("default");
+        encoder.string("q1");
+        encoder.string("127.0.0.1:9000");
+        // the start time of the initial query
+        encoder.write(1_600_000_000_000_000_u64);
+        encoder.write(INTERFACE_TCP);
+        encoder.string("bob");
+        encoder.string("host");
+        encoder.string("ClickHouse client");
+        encoder.uvarint(24);
+        encoder.uvarint(8);
+        encoder.uvarint(revision);
+        encoder.string("quota");
+        // distributed depth
+        encoder.uvarint(2);
+        encoder.uvarint(4);
+        // no trace
+        encoder.write(0_u8);
+        // parallel replicas
+        encoder.uvarint(1);
+        encoder.uvarint(3);
+        encoder.uvarint(1);
+        // settings
+        encoder.string("max_threads");
+        encoder.uvarint(1);
+        encoder.string("8");
+        encoder.string("");
+        // interserver secret
+        encoder.string("");
+        encoder.uvarint(2);
+        encoder.uvarint(1);
+        encoder.string("SELECT {x:String}");
+        // parameters
+        encoder.string("x");
+        encoder.uvarint(2);
+        encoder.string("'abc'");
+        encoder.string("");
+        encoder.write(42_u8);
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let query = QueryRequest::read_from(&mut reader, &hello(), revision).unwrap();
+        // nothing more is read
+        assert_eq!(reader.read_scalar::<u8>().unwrap(), 42);
+
+        assert_eq!(query.query(), "SELECT {x:String}");
+        assert_eq!(query.stage(), Some(ProcessingStage::Complete));
+        assert!(query.compression());
+        assert_eq!(query.settings().get_u64("max_threads"), Some(8));
+        assert_eq!(query.parameters().get_str("x"), Some("abc"));
+
+        let info = query.client_info();
+        assert_eq!(
+            info.initial_query_start_time_microseconds,
+            1_600_000_000_000_000
+        );
+        assert_eq!(info.client_name, "ClickHouse client");
+        assert_eq!(info.quota_key, "quota");
+        assert_eq!(info.distributed_depth, 2);
+        assert_eq!(info.client_version_patch, 4);
+        assert!(info.collaborate_with_initiator);
+        assert_eq!(info.count_participating_replicas, 3);
+        assert_eq!(info.number_of_current_replica, 1);
+    }
 }
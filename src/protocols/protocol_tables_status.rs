@@ -0,0 +1,118 @@
+use std::io::Read;
+
+use crate::binary::Encoder;
+use crate::binary::ReadEx;
+use crate::errors::Result;
+use crate::protocols::*;
+
+/// A table the client asked the status of, see
+/// [`ClickHouseSession::tables_status`](crate::ClickHouseSession::tables_status).
+#[derive(Default, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct QualifiedTableName {
+    pub database: String,
+    pub table: String
+}
+
+impl QualifiedTableName {
+    /// Reads the tables of a `TablesStatusRequest` packet.
+    pub fn read_list<R: Read>(reader: &mut R) -> Result<Vec<QualifiedTableName>> {
+        let count = reader.read_uvarint()?;
+        // the count isn't trusted to allocate
+        let mut tables = Vec::new();
+        for _ in 0..count {
+            tables.push(QualifiedTableName {
+                database: reader.read_string()?,
+                table: reader.read_string()?
+            });
+        }
+        Ok(tables)
+    }
+}
+
+/// The status of a table, used by clients of `Distributed` tables to choose replicas.
+#[derive(Default, Debug, Clone, PartialEq)]
+pub struct TableStatus {
+    pub is_replicated: bool,
+    /// How far the replica is behind, in seconds. Only sent for replicated tables.
+    pub absolute_delay: u32,
+    /// Only sent for replicated tables.
+    pub is_readonly: bool
+}
+
+pub struct TablesStatusResponse {
+    pub statuses: Vec<(QualifiedTableName, TableStatus)>
+}
+
+impl TablesStatusResponse {
+    pub fn encode(&self, encoder: &mut Encoder, client_revision: u64) {
+        encoder.uvarint(SERVER_TABLES_STATUS_RESPONSE);
+        encoder.uvarint(self.statuses.len() as u64);
+
+        for (name, status) in &self.statuses {
+            encoder.string(&name.database);
+            encoder.string(&name.table);
+
+            encoder.write(status.is_replicated);
+            if status.is_replicated {
+                encoder.uvarint(u64::from(status.absolute_delay));
+                if client_revision >= DBMS_MIN_REVISION_WITH_TABLE_READ_ONLY_CHECK {
+                    encoder.uvarint(status.is_readonly as u64);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn name(database: &str, table: &str) -> QualifiedTableName {
+        QualifiedTableName {
+            database: database.to_string(),
+            table: table.to_string()
+        }
+    }
+
+    #[test]
+    fn test_read_list() {
+        let bytes = b"\x02\x02db\x01a\x00\x01b";
+        let mut reader = Cursor::new(&bytes[..]);
+        let tables = QualifiedTableName::read_list(&mut reader).unwrap();
+        assert_eq!(tables, vec![name("db", "a"), name("", "b")]);
+
+        // a truncated packet
+        let mut reader = Cursor::new(&bytes[..5]);
+        assert!(QualifiedTableName::read_list(&mut reader).is_err());
+    }
+
+    #[test]
+    fn test_encode() {
+        let response = TablesStatusResponse {
+            statuses: vec![
+                (name("db", "a"), TableStatus::default()),
+                (name("db", "b"), TableStatus {
+                    is_replicated: true,
+                    absolute_delay: 300,
+                    is_readonly: true
+                }),
+            ]
+        };
+
+        let mut encoder = Encoder::new();
+        response.encode(&mut encoder, DBMS_MIN_REVISION_WITH_TABLES_STATUS);
+        assert_eq!(
+            encoder.get_buffer(),
+            b"\x09\x02\x02db\x01a\x00\x02db\x01b\x01\xac\x02".to_vec()
+        );
+
+        let mut encoder = Encoder::new();
+        response.encode(&mut encoder, DBMS_MIN_REVISION_WITH_TABLE_READ_ONLY_CHECK);
+        assert_eq!(
+            encoder.get_buffer(),
+            b"\x09\x02\x02db\x01a\x00\x02db\x01b\x01\xac\x02\x01".to_vec()
+        );
+    }
+}
@@ -0,0 +1,217 @@
+//! The result of a query as a stream of events, returned by
+//! [`ClickHouseSession::query_stream`](crate::ClickHouseSession::query_stream), or sent from
+//! [`ClickHouseSession::execute_query`](crate::ClickHouseSession::execute_query) by
+//! [`send_query_stream`].
+
+use futures::stream::BoxStream;
+use futures::StreamExt;
+
+use crate::connection::Connection;
+use crate::errors::Result;
+use crate::logs::ServerLog;
+use crate::types::Block;
+use crate::types::ProfileInfo;
+use crate::types::Progress;
+use crate::CHContext;
+
+// The `max_block_size` of ClickHouse when the client didn't set it.
+const DEFAULT_MAX_BLOCK_SIZE: u64 = 65409;
+
+/// What a query sends to the client.
+#[derive(Debug)]
+pub enum QueryEvent {
+    /// Rows of the result, split to the `max_block_size` of the client.
+    Data(Block),
+    Progress(Progress),
+    /// The `WITH TOTALS` row.
+    Totals(Block),
+    /// The min and max rows of the result, when the client asked for `extremes`.
+    Extremes(Block),
+    ProfileInfo(ProfileInfo),
+    Log(ServerLog)
+}
+
+pub type QueryStream = BoxStream<'static, Result<QueryEvent>>;
+
+/// Sends the events of `stream` until it ends, or until the query is cancelled in which
+/// case the stream is dropped. The blocks are split to the `max_block_size` of the client.
+/// The end of the stream is sent by the server once `execute_query` returned.
+pub async fn send_query_stream(
+    mut stream: QueryStream,
+    ctx: &CHContext,
+    connection: &mut Connection
+) -> Result<()> {
+    let cancel_token = ctx.state.cancel_token.clone();
+    let max_block_size = ctx
+        .settings
+        .get_u64("max_block_size")
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_MAX_BLOCK_SIZE) as usize;
+
+    loop {
+        let event = tokio::select! {
+            biased;
+            _ = cancel_token.cancelled() => return Ok(()),
+            event = stream.next() => match event {
+                Some(event) => event?,
+                None => return Ok(())
+            }
+        };
+
+        match event {
+            QueryEvent::Data(block) => {
+                for chunk in block.chunks(max_block_size) {
+                    connection.write_block(&chunk).await?;
+                }
+            }
+            QueryEvent::Progress(progress) => connection.write_query_progress(progress).await?,
+            QueryEvent::Totals(block) => connection.write_totals(&block).await?,
+            QueryEvent::Extremes(block) => connection.write_extremes(&block).await?,
+            QueryEvent::ProfileInfo(info) => connection.write_profile_info(info).await?,
+            QueryEvent::Log(log) => connection.write_log(&[log]).await?
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use futures::stream;
+
+    use super::*;
+    use crate::binary::Encoder;
+    use crate::protocols::CLIENT_CANCEL;
+    use crate::protocols::DBMS_TCP_PROTOCOL_VERSION;
+    use crate::protocols::SERVER_END_OF_STREAM;
+    use crate::testing::ServerPacket;
+    use crate::testing::TestClient;
+    use crate::ClickHouseSession;
+
+    struct Session;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for Session {
+        async fn execute_query(
+            &self,
+            ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            send_query_stream(events(ctx), ctx, connection).await
+        }
+    }
+
+    // The same events, through the trait method.
+    struct StreamSession;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for StreamSession {
+        async fn execute_query(&self, _: &mut CHContext, _: &mut Connection) -> Result<()> {
+            Err("execute_query isn't called when there is a stream".into())
+        }
+
+        async fn query_stream(&self, ctx: &mut CHContext) -> Result<Option<QueryStream>> {
+            Ok(Some(events(ctx)))
+        }
+    }
+
+    fn events(ctx: &CHContext) -> QueryStream {
+        let ids = Block::new().column("id", (0..5_u32).collect::<Vec<_>>());
+        let events = vec![
+            Ok(QueryEvent::Progress(Progress {
+                rows: 5,
+                ..Progress::default()
+            })),
+            Ok(QueryEvent::Data(ids)),
+            Ok(QueryEvent::Totals(Block::new().column("id", vec![10_u32]))),
+            Ok(QueryEvent::Extremes(
+                Block::new().column("id", vec![0_u32, 4])
+            )),
+        ];
+
+        if ctx.state.query == "SELECT forever" {
+            // the first block, then nothing until the query is cancelled
+            return stream::iter(events)
+                .take(2)
+                .chain(stream::pending())
+                .boxed();
+        }
+        stream::iter(events).boxed()
+    }
+
+    fn rows(packet: ServerPacket) -> usize {
+        match packet {
+            ServerPacket::Data(block) => block.row_count(),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_stream() {
+        check_query_stream(Arc::new(Session)).await;
+    }
+
+    #[tokio::test]
+    async fn test_session_query_stream() {
+        check_query_stream(Arc::new(StreamSession)).await;
+    }
+
+    async fn check_query_stream(session: Arc<dyn ClickHouseSession>) {
+        let mut client = TestClient::connect(session).with_revision(DBMS_TCP_PROTOCOL_VERSION);
+        client.hello("default").await.unwrap();
+        client
+            .query_with("SELECT id", &[("max_block_size", "2")], &[], &[])
+            .await;
+
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Progress(progress) => assert_eq!(progress.rows, 5),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        // split to the `max_block_size` of the client
+        for expected in &[2, 2, 1] {
+            assert_eq!(rows(client.read_packet().await.unwrap()), *expected);
+        }
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Totals(block) => assert_eq!(block.get::<u32, _>(0, "id").unwrap(), 10),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match client.read_packet().await.unwrap() {
+            ServerPacket::Extremes(block) => assert_eq!(block.row_count(), 2),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+        client.close().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let mut client = TestClient::connect(Arc::new(Session));
+        client.hello("default").await.unwrap();
+        client.query("SELECT forever").await;
+
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Progress(_)
+        ));
+        assert_eq!(rows(client.read_packet().await.unwrap()), 5);
+
+        let mut encoder = Encoder::new();
+        encoder.uvarint(CLIENT_CANCEL);
+        client.send(encoder).await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+
+        // the connection is still usable
+        client.query("SELECT id").await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Progress(_)
+        ));
+        client.close().await.unwrap();
+    }
+}
@@ -0,0 +1,524 @@
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::join_all;
+use log::debug;
+use log::warn;
+use tokio::net::TcpListener;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::sync::Semaphore;
+use tokio_util::sync::CancellationToken;
+
+use crate::errors::Result;
+#[cfg(feature = "tls")]
+use crate::tls::TlsAcceptor;
+use crate::ClickHouseServer;
+use crate::ClickHouseSession;
+
+const DEFAULT_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+#[cfg(feature = "tls")]
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type SessionFactory = Arc<dyn Fn(&PeerInfo) -> Arc<dyn ClickHouseSession> + Send + Sync>;
+
+/// The addresses of an accepted connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PeerInfo {
+    /// The address of the client.
+    pub addr: SocketAddr,
+    /// The address the connection was accepted on.
+    pub local_addr: SocketAddr
+}
+
+/// Configures a [`Listener`], see [`ClickHouseServer::builder`].
+pub struct ServerBuilder {
+    factory: SessionFactory,
+    addrs: Vec<String>,
+    max_connections: Option<usize>,
+    idle_timeout: Option<Duration>,
+    shutdown_timeout: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    #[cfg(feature = "tls")]
+    handshake_timeout: Duration
+}
+
+impl ServerBuilder {
+    pub(crate) fn new(factory: SessionFactory) -> Self {
+        Self {
+            factory,
+            addrs: Vec::new(),
+            max_connections: None,
+            idle_timeout: None,
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT,
+            #[cfg(feature = "tls")]
+            tls: None,
+            #[cfg(feature = "tls")]
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT
+        }
+    }
+
+    /// Listens on `addr`, e.g. `127.0.0.1:9000`. Can be called several times.
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.addrs.push(addr.into());
+        self
+    }
+
+    /// No more connections are accepted while this many are open (unlimited by default).
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = Some(max_connections);
+        self
+    }
+
+    /// Closes connections which don't send a query for this long (never by default).
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// How long running queries may take to finish on shutdown, the remaining
+    /// connections are closed then (defaults to 30 seconds).
+    pub fn shutdown_timeout(mut self, shutdown_timeout: Duration) -> Self {
+        self.shutdown_timeout = shutdown_timeout;
+        self
+    }
+
+    /// Accepts TLS connections only, for the secure native protocol.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, acceptor: TlsAcceptor) -> Self {
+        self.tls = Some(acceptor);
+        self
+    }
+
+    /// Closes connections which don't complete the TLS handshake in time (defaults to
+    /// 10 seconds).
+    #[cfg(feature = "tls")]
+    pub fn handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Binds the addresses, the connections are accepted by [`Listener::run`].
+    pub async fn listen(self) -> Result<Listener> {
+        if self.addrs.is_empty() {
+            return Err("No address to listen on.".into());
+        }
+
+        let mut listeners = Vec::with_capacity(self.addrs.len());
+        for addr in &self.addrs {
+            listeners.push(TcpListener::bind(addr.as_str()).await?);
+        }
+
+        Ok(Listener {
+            listeners,
+            factory: self.factory,
+            limit_connections: self
+                .max_connections
+                .map(|max_connections| Arc::new(Semaphore::new(max_connections))),
+            idle_timeout: self.idle_timeout,
+            shutdown_timeout: self.shutdown_timeout,
+            shutdown: CancellationToken::new(),
+            #[cfg(feature = "tls")]
+            tls: self.tls,
+            #[cfg(feature = "tls")]
+            handshake_timeout: self.handshake_timeout
+        })
+    }
+}
+
+/// Stops a [`Listener`].
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle(CancellationToken);
+
+impl ShutdownHandle {
+    /// Stops accepting connections. Idle connections are closed right away, the others
+    /// once their query finished or the shutdown timeout elapsed.
+    pub fn shutdown(&self) {
+        self.0.cancel();
+    }
+}
+
+/// Accepts connections and runs the protocol on each of them in its own task.
+pub struct Listener {
+    listeners: Vec<TcpListener>,
+    factory: SessionFactory,
+
+    // Limits the number of open connections. A permit is acquired before a
+    // connection is accepted and released when it's closed, so clients wait
+    // in the backlog of the socket meanwhile.
+    limit_connections: Option<Arc<Semaphore>>,
+    idle_timeout: Option<Duration>,
+    shutdown_timeout: Duration,
+    shutdown: CancellationToken,
+    #[cfg(feature = "tls")]
+    tls: Option<TlsAcceptor>,
+    #[cfg(feature = "tls")]
+    handshake_timeout: Duration
+}
+
+impl Listener {
+    /// The bound addresses, to find out the ports when binding to port 0.
+    pub fn local_addrs(&self) -> Result<Vec<SocketAddr>> {
+        let mut addrs = Vec::with_capacity(self.listeners.len());
+        for listener in &self.listeners {
+            addrs.push(listener.local_addr()?);
+        }
+        Ok(addrs)
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle(self.shutdown.clone())
+    }
+
+    /// Accepts connections until the shutdown, returns once all of them are closed.
+    pub async fn run(self) -> Result<()> {
+        // Every connection task holds a sender, `recv` returns `None` once all of
+        // them are dropped.
+        let (done_tx, mut done_rx) = mpsc::channel::<()>(1);
+        // Fired when the shutdown timeout elapsed, the remaining connections are dropped.
+        let force_close = CancellationToken::new();
+
+        let accept_loops = self
+            .listeners
+            .iter()
+            .map(|listener| self.accept_loop(listener, &done_tx, &force_close));
+        join_all(accept_loops).await;
+
+        // nothing is accepted anymore, the clients waiting in the backlog get an error
+        drop(self.listeners);
+        drop(done_tx);
+        if tokio::time::timeout(self.shutdown_timeout, done_rx.recv())
+            .await
+            .is_err()
+        {
+            warn!("closing the connections still running after the shutdown timeout");
+            force_close.cancel();
+            done_rx.recv().await;
+        }
+        Ok(())
+    }
+
+    async fn accept_loop(
+        &self,
+        listener: &TcpListener,
+        done_tx: &mpsc::Sender<()>,
+        force_close: &CancellationToken
+    ) {
+        loop {
+            let permit = tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                permit = self.acquire_permit() => permit
+            };
+
+            let accepted = tokio::select! {
+                _ = self.shutdown.cancelled() => return,
+                accepted = listener.accept() => accepted
+            };
+
+            let (stream, addr) = match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // e.g. too many open files, try again a bit later
+                    warn!("failed to accept a connection: {}", e);
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            };
+
+            let peer = PeerInfo {
+                addr,
+                local_addr: stream.local_addr().unwrap_or(addr)
+            };
+            debug!("accepted connection from {}", peer.addr);
+
+            let session = (self.factory)(&peer);
+            let server = ClickHouseServer {
+                idle_timeout: self.idle_timeout,
+                shutdown: self.shutdown.clone()
+            };
+            #[cfg(feature = "tls")]
+            let tls = self.tls.clone();
+            #[cfg(feature = "tls")]
+            let handshake_timeout = self.handshake_timeout;
+            let done_tx = done_tx.clone();
+            let force_close = force_close.clone();
+
+            tokio::spawn(async move {
+                #[cfg(feature = "tls")]
+                let serve = server.serve(session, stream, tls, handshake_timeout);
+                #[cfg(not(feature = "tls"))]
+                let serve = server.serve(session, stream);
+
+                tokio::select! {
+                    res = serve => {
+                        if let Err(e) = res {
+                            debug!("connection from {} failed: {}", peer.addr, e);
+                        }
+                    }
+                    _ = force_close.cancelled() => {
+                        debug!("connection from {} closed on shutdown", peer.addr);
+                    }
+                }
+                drop(permit);
+                drop(done_tx);
+            });
+        }
+    }
+
+    async fn acquire_permit(&self) -> Option<OwnedSemaphorePermit> {
+        match &self.limit_connections {
+            Some(limit) => Some(limit.clone().acquire_owned().await.unwrap()),
+            None => None
+        }
+    }
+}
+
+impl ClickHouseServer {
+    #[cfg(not(feature = "tls"))]
+    async fn serve(mut self, session: Arc<dyn ClickHouseSession>, stream: TcpStream) -> Result<()> {
+        self.run(session, stream).await
+    }
+
+    #[cfg(feature = "tls")]
+    async fn serve(
+        mut self,
+        session: Arc<dyn ClickHouseSession>,
+        stream: TcpStream,
+        tls: Option<TlsAcceptor>,
+        handshake_timeout: Duration
+    ) -> Result<()> {
+        match tls {
+            Some(acceptor) => {
+                let handshake = tokio::time::timeout(handshake_timeout, acceptor.accept(stream));
+                let stream = tokio::select! {
+                    _ = self.shutdown.cancelled() => {
+                        debug!("server is shutting down, aborting the TLS handshake");
+                        return Ok(());
+                    }
+                    stream = handshake => stream??
+                };
+                self.run(session, stream).await
+            }
+            None => self.run(session, stream).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use futures::StreamExt;
+    #[cfg(feature = "tls")]
+    use tokio::io::AsyncReadExt;
+    use tokio::task::JoinHandle;
+
+    use super::*;
+    use crate::connection::Connection;
+    use crate::protocols::SERVER_END_OF_STREAM;
+    use crate::testing::ServerPacket;
+    use crate::testing::TestClient;
+    use crate::types::Block;
+    use crate::CHContext;
+    use crate::InsertHandler;
+
+    struct Session;
+
+    #[async_trait::async_trait]
+    impl ClickHouseSession for Session {
+        async fn execute_query(
+            &self,
+            ctx: &mut CHContext,
+            connection: &mut Connection
+        ) -> Result<()> {
+            if let Some(millis) = ctx.state.query.strip_prefix("SLEEP ") {
+                let millis = millis.parse().unwrap();
+                tokio::time::sleep(Duration::from_millis(millis)).await;
+            }
+            connection
+                .write_block(&Block::new().column("x", vec![1_u8]))
+                .await
+        }
+
+        async fn execute_insert(&self, ctx: &mut CHContext) -> Result<Option<InsertHandler>> {
+            if !ctx.state.query.starts_with("INSERT") {
+                return Ok(None);
+            }
+            let header = Block::new().column("x", Vec::<u8>::new());
+            Ok(Some(InsertHandler::new(header, |stream| async move {
+                stream.for_each(|_| async {}).await;
+                Ok(())
+            })))
+        }
+    }
+
+    async fn listen(
+        builder: ServerBuilder
+    ) -> (SocketAddr, ShutdownHandle, JoinHandle<Result<()>>) {
+        let listener = builder.bind("127.0.0.1:0").listen().await.unwrap();
+        let addr = listener.local_addrs().unwrap()[0];
+        let shutdown = listener.shutdown_handle();
+        (addr, shutdown, tokio::spawn(listener.run()))
+    }
+
+    fn builder() -> ServerBuilder {
+        ClickHouseServer::builder(|_| Arc::new(Session))
+    }
+
+    async fn connect(addr: SocketAddr) -> TestClient {
+        TestClient::new(TcpStream::connect(addr).await.unwrap(), None)
+    }
+
+    async fn assert_result(client: &mut TestClient) {
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Data(_)
+        ));
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let (addr, shutdown, server) = listen(builder()).await;
+
+        let mut idle = connect(addr).await;
+        idle.hello("default").await.unwrap();
+        let mut busy = connect(addr).await;
+        busy.hello("default").await.unwrap();
+
+        busy.query("SLEEP 100").await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        shutdown.shutdown();
+
+        // the running query finishes, then both connections are closed
+        assert_result(&mut busy).await;
+        assert!(busy.read_packet().await.is_err());
+        assert!(idle.read_packet().await.is_err());
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_timeout() {
+        let (addr, shutdown, server) =
+            listen(builder().shutdown_timeout(Duration::from_millis(50))).await;
+
+        let mut client = connect(addr).await;
+        client.hello("default").await.unwrap();
+        client.query("SLEEP 10000").await;
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let start = Instant::now();
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(client.read_packet().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_max_connections() {
+        let (addr, shutdown, server) = listen(builder().max_connections(1)).await;
+
+        let mut first = connect(addr).await;
+        first.hello("default").await.unwrap();
+
+        // waits in the backlog until the first connection is closed
+        let mut second = connect(addr).await;
+        let hello = tokio::spawn(async move {
+            second.hello("default").await.unwrap();
+            second
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!hello.is_finished());
+
+        first.close().await.unwrap();
+        let mut second = hello.await.unwrap();
+        second.query("SELECT 1").await;
+        assert_result(&mut second).await;
+
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout() {
+        let (addr, shutdown, server) =
+            listen(builder().idle_timeout(Duration::from_millis(50))).await;
+
+        let mut client = connect(addr).await;
+        client.hello("default").await.unwrap();
+        // the timeout doesn't apply to the query itself
+        client.query("SLEEP 100").await;
+        assert_result(&mut client).await;
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(client.read_packet().await.is_err());
+
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_idle_timeout_insert() {
+        let (addr, shutdown, server) =
+            listen(builder().idle_timeout(Duration::from_millis(50))).await;
+
+        let mut client = connect(addr).await;
+        client.hello("default").await.unwrap();
+        client.query("INSERT INTO t VALUES").await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Data(_)
+        ));
+
+        // the timeout doesn't apply while the data of the INSERT is sent
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client
+            .send_data(&Block::new().column("x", vec![1_u8]))
+            .await;
+        client.send_data(&Block::new()).await;
+        assert!(matches!(
+            client.read_packet().await.unwrap(),
+            ServerPacket::Other(SERVER_END_OF_STREAM)
+        ));
+
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn test_handshake_timeout() {
+        let acceptor = TlsAcceptor::from_pem(
+            include_bytes!("../tests/certs/server.crt"),
+            include_bytes!("../tests/certs/server.key")
+        )
+        .unwrap();
+
+        let tls = builder()
+            .tls(acceptor.clone())
+            .handshake_timeout(Duration::from_millis(50));
+        let (addr, shutdown, server) = listen(tls).await;
+        // never starts the handshake, the connection is closed
+        let mut stalled = TcpStream::connect(addr).await.unwrap();
+        let mut buf = [0; 1];
+        let read = tokio::time::timeout(Duration::from_secs(5), stalled.read(&mut buf)).await;
+        assert!(matches!(read, Ok(Ok(0)) | Ok(Err(_))));
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+
+        // a pending handshake doesn't delay the shutdown
+        let (addr, shutdown, server) = listen(builder().tls(acceptor)).await;
+        let _stalled = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let start = Instant::now();
+        shutdown.shutdown();
+        server.await.unwrap().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
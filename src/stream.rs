@@ -0,0 +1,124 @@
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+use pin_project::pin_project;
+use tokio::io::AsyncRead;
+use tokio::io::AsyncWrite;
+use tokio::io::DuplexStream;
+use tokio::io::ReadBuf;
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+#[cfg(feature = "tls")]
+use tokio_native_tls::TlsStream;
+
+/// A bidirectional byte stream the native protocol can run over, see
+/// [`ClickHouseServer::run_on_transport`](crate::ClickHouseServer::run_on_transport).
+pub trait Transport: AsyncRead + AsyncWrite + Send + Unpin + 'static {
+    /// The address of the client, if the transport has one.
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl Transport for TcpStream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl Transport for UnixStream {}
+
+impl Transport for DuplexStream {}
+
+#[cfg(feature = "tls")]
+impl<S: Transport> Transport for TlsStream<S> {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        self.get_ref().get_ref().get_ref().peer_addr()
+    }
+}
+
+/// An accepted client socket, either plain or wrapped in TLS.
+#[pin_project(project = StreamProj)]
+pub enum Stream {
+    Plain(#[pin] TcpStream),
+    #[cfg(feature = "tls")]
+    Secure(#[pin] TlsStream<TcpStream>)
+}
+
+impl Stream {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Plain(stream) => stream.peer_addr(),
+            #[cfg(feature = "tls")]
+            Stream::Secure(stream) => stream.get_ref().get_ref().get_ref().peer_addr()
+        }
+    }
+}
+
+impl Transport for Stream {
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        Stream::peer_addr(self).ok()
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Plain(stream)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl From<TlsStream<TcpStream>> for Stream {
+    fn from(stream: TlsStream<TcpStream>) -> Self {
+        Stream::Secure(stream)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>
+    ) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Plain(stream) => stream.poll_read(cx, buf),
+            #[cfg(feature = "tls")]
+            StreamProj::Secure(stream) => stream.poll_read(cx, buf)
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8]
+    ) -> Poll<io::Result<usize>> {
+        match self.project() {
+            StreamProj::Plain(stream) => stream.poll_write(cx, buf),
+            #[cfg(feature = "tls")]
+            StreamProj::Secure(stream) => stream.poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Plain(stream) => stream.poll_flush(cx),
+            #[cfg(feature = "tls")]
+            StreamProj::Secure(stream) => stream.poll_flush(cx)
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.project() {
+            StreamProj::Plain(stream) => stream.poll_shutdown(cx),
+            #[cfg(feature = "tls")]
+            StreamProj::Secure(stream) => stream.poll_shutdown(cx)
+        }
+    }
+}
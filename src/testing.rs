@@ -0,0 +1,406 @@
+//! A client speaking the native protocol over an in-memory pipe, to test sessions
+//! without binding ports.
+
+use std::io::Cursor;
+use std::sync::Arc;
+
+use bytes::Buf;
+use bytes::BytesMut;
+use chrono_tz::Tz;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
+use tokio::task::JoinHandle;
+
+use crate::binary::Encoder;
+use crate::binary::ReadEx;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::errors::ServerError;
+use crate::protocols::QualifiedTableName;
+use crate::protocols::CLIENT_DATA;
+use crate::protocols::CLIENT_HELLO;
+use crate::protocols::CLIENT_QUERY;
+use crate::protocols::CLIENT_SCALAR;
+use crate::protocols::CLIENT_TABLES_STATUS_REQUEST;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_PARAMETERS;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_PASSWORD_COMPLEXITY_RULES;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS;
+use crate::protocols::DBMS_MIN_REVISION_WITH_CLIENT_WRITE_INFO;
+use crate::protocols::DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET;
+use crate::protocols::DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET_V2;
+use crate::protocols::DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION;
+use crate::protocols::DBMS_MIN_REVISION_WITH_SETTINGS_SERIALIZED_AS_STRINGS;
+use crate::protocols::DBMS_MIN_REVISION_WITH_TABLE_READ_ONLY_CHECK;
+use crate::protocols::SERVER_DATA;
+use crate::protocols::SERVER_EXCEPTION;
+use crate::protocols::SERVER_EXTREMES;
+use crate::protocols::SERVER_HELLO;
+use crate::protocols::SERVER_LOG;
+use crate::protocols::SERVER_PROFILE_INFO;
+use crate::protocols::SERVER_PROGRESS;
+use crate::protocols::SERVER_TABLES_STATUS_RESPONSE;
+use crate::protocols::SERVER_TOTALS;
+use crate::stream::Transport;
+use crate::types::Block;
+use crate::types::BlockDecoder;
+use crate::types::ProfileInfo;
+use crate::types::Progress;
+use crate::ClickHouseServer;
+use crate::ClickHouseSession;
+
+pub(crate) const CLIENT_REVISION: u64 = 54405;
+
+/// A packet received from the server, the ones tests don't look into are only told
+/// apart by their type.
+#[derive(Debug)]
+pub(crate) enum ServerPacket {
+    Data(Block),
+    Totals(Block),
+    Extremes(Block),
+    Progress(Progress),
+    ProfileInfo(ProfileInfo),
+    Log(Block),
+    Other(u64)
+}
+
+pub(crate) struct TestClient {
+    stream: Box<dyn Transport>,
+    buffer: BytesMut,
+    server: Option<JoinHandle<Result<()>>>,
+    // The revision sent in `Hello`, then the negotiated one.
+    revision: u64
+}
+
+impl TestClient {
+    /// Runs `session` on a `tokio::io::duplex` pipe.
+    pub(crate) fn connect(session: Arc<dyn ClickHouseSession>) -> TestClient {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(ClickHouseServer::run_on_transport(session, server));
+        TestClient::new(client, Some(server))
+    }
+
+    /// `server` is the task serving the other end of `stream`, if it's known.
+    pub(crate) fn new<T: Transport>(
+        stream: T,
+        server: Option<JoinHandle<Result<()>>>
+    ) -> TestClient {
+        TestClient {
+            stream: Box::new(stream),
+            buffer: BytesMut::new(),
+            server,
+            revision: CLIENT_REVISION
+        }
+    }
+
+    /// Speaks the given revision of the protocol instead of `CLIENT_REVISION`.
+    pub(crate) fn with_revision(mut self, revision: u64) -> TestClient {
+        self.revision = revision;
+        self
+    }
+
+    /// The revision negotiated in the handshake.
+    pub(crate) fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub(crate) async fn send(&mut self, encoder: Encoder) {
+        self.stream
+            .write_all(encoder.get_buffer_ref())
+            .await
+            .unwrap();
+    }
+
+    /// Sends `Hello` without a password, returns the name of the server.
+    pub(crate) async fn hello(&mut self, user: &str) -> Result<String> {
+        self.hello_with_password(user, "").await
+    }
+
+    pub(crate) async fn hello_with_password(
+        &mut self,
+        user: &str,
+        password: &str
+    ) -> Result<String> {
+        let mut encoder = Encoder::new();
+        encoder.uvarint(CLIENT_HELLO);
+        encoder.string("test");
+        encoder.uvarint(1);
+        encoder.uvarint(1);
+        encoder.uvarint(self.revision);
+        encoder.string("default");
+        encoder.string(user);
+        encoder.string(password);
+        self.send(encoder).await;
+
+        match self.read(|r| r.read_uvarint()).await? {
+            SERVER_HELLO => {}
+            SERVER_EXCEPTION => return Err(Error::Server(self.read(read_exception).await?)),
+            packet => panic!("unexpected packet {}", packet)
+        }
+
+        let client_revision = self.revision;
+        let (name, revision) = self
+            .read(|r| {
+                let name = r.read_string()?;
+                r.read_uvarint()?;
+                r.read_uvarint()?;
+                let revision = r.read_uvarint()?.min(client_revision);
+                // timezone, display name and patch
+                r.read_string()?;
+                r.read_string()?;
+                r.read_uvarint()?;
+                if revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_PASSWORD_COMPLEXITY_RULES {
+                    for _ in 0..r.read_uvarint()? {
+                        r.read_string()?;
+                        r.read_string()?;
+                    }
+                }
+                if revision >= DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET_V2 {
+                    r.read_scalar::<u64>()?;
+                }
+                Ok((name, revision))
+            })
+            .await?;
+        self.revision = revision;
+
+        if revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_ADDENDUM {
+            let mut encoder = Encoder::new();
+            // quota key
+            encoder.string("");
+            self.send(encoder).await;
+        }
+        Ok(name)
+    }
+
+    /// Sends an uncompressed query without client info.
+    pub(crate) async fn query(&mut self, query: &str) {
+        self.query_with(query, &[], &[], &[]).await
+    }
+
+    /// Sends a query with settings, followed by scalars and the blocks of external tables.
+    /// Settings are only sent as strings, by clients of the revisions which support it.
+    pub(crate) async fn query_with(
+        &mut self,
+        query: &str,
+        settings: &[(&str, &str)],
+        scalars: &[(&str, Block)],
+        tables: &[(&str, Block)]
+    ) {
+        let mut encoder = Encoder::new();
+        encoder.uvarint(CLIENT_QUERY);
+        encoder.string("");
+        encoder.write(0_u8);
+        if !settings.is_empty() {
+            assert!(self.revision >= DBMS_MIN_REVISION_WITH_SETTINGS_SERIALIZED_AS_STRINGS);
+        }
+        for (name, value) in settings {
+            encoder.string(name);
+            // flags
+            encoder.uvarint(0);
+            encoder.string(value);
+        }
+        // end of settings
+        encoder.string("");
+        if self.revision >= DBMS_MIN_REVISION_WITH_INTERSERVER_SECRET {
+            encoder.string("");
+        }
+        // stage: complete
+        encoder.uvarint(2);
+        // compression: disabled
+        encoder.uvarint(0);
+        encoder.string(query);
+        if self.revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_PARAMETERS {
+            // end of parameters
+            encoder.string("");
+        }
+
+        for (name, block) in scalars {
+            self.data(&mut encoder, CLIENT_SCALAR, name, block);
+        }
+        for (table, block) in tables {
+            self.data(&mut encoder, CLIENT_DATA, table, block);
+        }
+        // the end of the external tables
+        self.data(&mut encoder, CLIENT_DATA, "", &Block::new());
+        self.send(encoder).await;
+    }
+
+    /// Sends a block of the data of an INSERT, an empty one ends it.
+    pub(crate) async fn send_data(&mut self, block: &Block) {
+        let mut encoder = Encoder::new();
+        self.data(&mut encoder, CLIENT_DATA, "", block);
+        self.send(encoder).await;
+    }
+
+    // Encodes an uncompressed `Data` or `Scalar` packet.
+    fn data(&self, encoder: &mut Encoder, packet: u64, name: &str, block: &Block) {
+        encoder.uvarint(packet);
+        encoder.string(name);
+        block.write(encoder, None, self.revision).unwrap();
+    }
+
+    /// Asks the status of `tables`, the replication delay is left out.
+    pub(crate) async fn tables_status(
+        &mut self,
+        tables: &[QualifiedTableName]
+    ) -> Result<Vec<(QualifiedTableName, bool)>> {
+        let mut encoder = Encoder::new();
+        encoder.uvarint(CLIENT_TABLES_STATUS_REQUEST);
+        encoder.uvarint(tables.len() as u64);
+        for table in tables {
+            encoder.string(&table.database);
+            encoder.string(&table.table);
+        }
+        self.send(encoder).await;
+
+        match self.read(|r| r.read_uvarint()).await? {
+            SERVER_TABLES_STATUS_RESPONSE => {}
+            SERVER_EXCEPTION => return Err(Error::Server(self.read(read_exception).await?)),
+            packet => panic!("unexpected packet {}", packet)
+        }
+
+        let revision = self.revision;
+        self.read(|r| {
+            let mut statuses = Vec::new();
+            for _ in 0..r.read_uvarint()? {
+                let name = QualifiedTableName {
+                    database: r.read_string()?,
+                    table: r.read_string()?
+                };
+                let is_replicated = r.read_scalar::<u8>()? != 0;
+                if is_replicated {
+                    r.read_uvarint()?;
+                    if revision >= DBMS_MIN_REVISION_WITH_TABLE_READ_ONLY_CHECK {
+                        r.read_uvarint()?;
+                    }
+                }
+                statuses.push((name, is_replicated));
+            }
+            Ok(statuses)
+        })
+        .await
+    }
+
+    /// Reads the next packet, an `Exception` is returned as an error.
+    pub(crate) async fn read_packet(&mut self) -> Result<ServerPacket> {
+        let packet = self.read(|r| r.read_uvarint()).await?;
+        match packet {
+            SERVER_DATA | SERVER_TOTALS | SERVER_EXTREMES | SERVER_LOG => {
+                self.read(|r| r.skip_string()).await?;
+                let mut decoder = BlockDecoder::new(Tz::UTC, false, self.revision);
+                let block = loop {
+                    if let Some(block) = decoder.decode(&mut self.buffer)? {
+                        break block;
+                    }
+                    self.fill().await?;
+                };
+                Ok(match packet {
+                    SERVER_DATA => ServerPacket::Data(block),
+                    SERVER_TOTALS => ServerPacket::Totals(block),
+                    SERVER_EXTREMES => ServerPacket::Extremes(block),
+                    _ => ServerPacket::Log(block)
+                })
+            }
+            SERVER_PROGRESS => {
+                let revision = self.revision;
+                let progress = self.read(|r| read_progress(r, revision)).await?;
+                Ok(ServerPacket::Progress(progress))
+            }
+            SERVER_PROFILE_INFO => {
+                let revision = self.revision;
+                let info = self.read(|r| read_profile_info(r, revision)).await?;
+                Ok(ServerPacket::ProfileInfo(info))
+            }
+            SERVER_EXCEPTION => Err(Error::Server(self.read(read_exception).await?)),
+            _ => Ok(ServerPacket::Other(packet))
+        }
+    }
+
+    /// Closes the client side and waits for the server to finish.
+    pub(crate) async fn close(self) -> Result<()> {
+        drop(self.stream);
+        match self.server {
+            Some(server) => server.await.unwrap(),
+            None => Ok(())
+        }
+    }
+
+    // Parses a value from the received bytes, waits for more of them until it's complete.
+    async fn read<T, F>(&mut self, mut parse: F) -> Result<T>
+    where F: FnMut(&mut Cursor<&[u8]>) -> Result<T> {
+        loop {
+            let mut cursor = Cursor::new(&self.buffer[..]);
+            match parse(&mut cursor) {
+                Ok(value) => {
+                    let len = cursor.position() as usize;
+                    self.buffer.advance(len);
+                    return Ok(value);
+                }
+                Err(err) if err.is_would_block() => self.fill().await?,
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    async fn fill(&mut self) -> Result<()> {
+        if self.stream.read_buf(&mut self.buffer).await? == 0 {
+            return Err("the server closed the connection".into());
+        }
+        Ok(())
+    }
+}
+
+fn read_progress(reader: &mut Cursor<&[u8]>, revision: u64) -> Result<Progress> {
+    let mut progress = Progress {
+        rows: reader.read_uvarint()?,
+        bytes: reader.read_uvarint()?,
+        total_rows: reader.read_uvarint()?,
+        ..Progress::default()
+    };
+    if revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS {
+        progress.total_bytes = reader.read_uvarint()?;
+    }
+    if revision >= DBMS_MIN_REVISION_WITH_CLIENT_WRITE_INFO {
+        progress.written_rows = reader.read_uvarint()?;
+        progress.written_bytes = reader.read_uvarint()?;
+    }
+    if revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS {
+        progress.elapsed_ns = reader.read_uvarint()?;
+    }
+    Ok(progress)
+}
+
+fn read_profile_info(reader: &mut Cursor<&[u8]>, revision: u64) -> Result<ProfileInfo> {
+    let mut info = ProfileInfo {
+        rows: reader.read_uvarint()?,
+        blocks: reader.read_uvarint()?,
+        bytes: reader.read_uvarint()?,
+        applied_limit: reader.read_scalar::<u8>()? != 0,
+        rows_before_limit: reader.read_uvarint()?,
+        calculated_rows_before_limit: reader.read_scalar::<u8>()? != 0,
+        ..ProfileInfo::default()
+    };
+    if revision >= DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION {
+        info.applied_aggregation = reader.read_scalar::<u8>()? != 0;
+        info.rows_before_aggregation = reader.read_uvarint()?;
+    }
+    Ok(info)
+}
+
+fn read_exception(reader: &mut Cursor<&[u8]>) -> Result<ServerError> {
+    let code = reader.read_scalar::<u32>()?;
+    let name = reader.read_string()?;
+    let message = reader.read_string()?;
+    let stack_trace = reader.read_string()?;
+    // only the outermost exception is kept
+    if reader.read_scalar::<u8>()? != 0 {
+        read_exception(reader)?;
+    }
+    Ok(ServerError {
+        code,
+        name,
+        message,
+        stack_trace
+    })
+}
@@ -0,0 +1,106 @@
+use tokio::net::TcpStream;
+
+use crate::errors::ConnectionError;
+use crate::errors::Result;
+use crate::stream::Stream;
+
+/// Wraps accepted sockets in TLS, for the secure native protocol (`--secure` of
+/// `clickhouse-client`, port 9440 by default).
+///
+/// ```rust,no_run
+/// # use clickhouse_srv::tls::TlsAcceptor;
+/// let cert = std::fs::read("server.crt").unwrap();
+/// let key = std::fs::read("server.key").unwrap();
+/// let acceptor = TlsAcceptor::from_pem(&cert, &key).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct TlsAcceptor(tokio_native_tls::TlsAcceptor);
+
+impl TlsAcceptor {
+    /// Uses the identity of a DER-formatted PKCS #12 archive.
+    pub fn from_pkcs12(der: &[u8], password: &str) -> Result<TlsAcceptor> {
+        let identity =
+            native_tls::Identity::from_pkcs12(der, password).map_err(ConnectionError::TlsError)?;
+        TlsAcceptor::new(identity)
+    }
+
+    /// Uses a PEM-formatted certificate chain and its PKCS #8 private key.
+    pub fn from_pem(cert: &[u8], key: &[u8]) -> Result<TlsAcceptor> {
+        let identity =
+            native_tls::Identity::from_pkcs8(cert, key).map_err(ConnectionError::TlsError)?;
+        TlsAcceptor::new(identity)
+    }
+
+    fn new(identity: native_tls::Identity) -> Result<TlsAcceptor> {
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(ConnectionError::TlsError)?;
+        Ok(TlsAcceptor(acceptor.into()))
+    }
+
+    /// Performs the TLS handshake on an accepted socket.
+    pub async fn accept(&self, stream: TcpStream) -> Result<Stream> {
+        let stream = self
+            .0
+            .accept(stream)
+            .await
+            .map_err(ConnectionError::TlsError)?;
+        Ok(stream.into())
+    }
+}
+
+impl From<native_tls::TlsAcceptor> for TlsAcceptor {
+    fn from(acceptor: native_tls::TlsAcceptor) -> Self {
+        TlsAcceptor(acceptor.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::io::AsyncReadExt;
+    use tokio::io::AsyncWriteExt;
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    // A self-signed certificate for `localhost`.
+    const CERT: &[u8] = include_bytes!("../tests/certs/server.crt");
+    const KEY: &[u8] = include_bytes!("../tests/certs/server.key");
+
+    #[tokio::test]
+    async fn test_accept() {
+        let acceptor = TlsAcceptor::from_pem(CERT, KEY).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut stream = acceptor.accept(socket).await.unwrap();
+            assert!(matches!(stream, Stream::Secure(_)));
+            assert!(stream.peer_addr().is_ok());
+
+            let mut buf = [0_u8; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+            stream.flush().await.unwrap();
+        });
+
+        let connector = native_tls::TlsConnector::builder()
+            .danger_accept_invalid_certs(true)
+            .build()
+            .unwrap();
+        let connector = tokio_native_tls::TlsConnector::from(connector);
+        let socket = TcpStream::connect(addr).await.unwrap();
+        let mut stream = connector.connect("localhost", socket).await.unwrap();
+
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0_u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        server.await.unwrap();
+    }
+
+    #[test]
+    fn test_invalid_identity() {
+        assert!(TlsAcceptor::from_pem(b"foo", b"bar").is_err());
+        assert!(TlsAcceptor::from_pkcs12(b"foo", "").is_err());
+    }
+}
@@ -4,10 +4,14 @@ use std::mem;
 use std::os::raw::c_char;
 use std::os::raw::c_int;
 
+use byteorder::ByteOrder;
 use byteorder::LittleEndian;
 use byteorder::WriteBytesExt;
+use bytes::BytesMut;
 use clickhouse_rs_cityhash_sys::city_hash_128;
 use clickhouse_rs_cityhash_sys::UInt128;
+use lz4::liblz4::LZ4_compressBound;
+use lz4::liblz4::LZ4_compress_default;
 use lz4::liblz4::LZ4_decompress_safe;
 
 use crate::binary::ReadEx;
@@ -16,6 +20,51 @@ use crate::errors::Result;
 
 const DBMS_MAX_COMPRESSED_SIZE: u32 = 0x4000_0000; // 1GB
 
+// method byte + compressed size + decompressed size
+const HEADER_SIZE: usize = 9;
+
+const CHECKSUM_SIZE: usize = 16;
+
+const ZSTD_LEVEL: i32 = 1;
+
+/// The codec of compressed blocks, see `network_compression_method`.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum CompressionMethod {
+    None,
+    #[default]
+    LZ4,
+    ZSTD
+}
+
+impl CompressionMethod {
+    fn byte(self) -> u8 {
+        match self {
+            CompressionMethod::None => 0x02,
+            CompressionMethod::LZ4 => 0x82,
+            CompressionMethod::ZSTD => 0x90
+        }
+    }
+
+    fn from_byte(method: u8) -> Option<Self> {
+        match method {
+            0x02 => Some(CompressionMethod::None),
+            0x82 => Some(CompressionMethod::LZ4),
+            0x90 => Some(CompressionMethod::ZSTD),
+            _ => None
+        }
+    }
+
+    /// Parses the value of the `network_compression_method` setting.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(CompressionMethod::None),
+            "lz4" => Some(CompressionMethod::LZ4),
+            "zstd" => Some(CompressionMethod::ZSTD),
+            _ => None
+        }
+    }
+}
+
 pub(crate) struct CompressedReader<'a, R> {
     reader: &'a mut R,
     cursor: io::Cursor<Vec<u8>>
@@ -66,11 +115,14 @@ where R: ReadEx {
         hi: reader.read_scalar()?
     };
 
-    let method: u8 = reader.read_scalar()?;
-    if method != 0x82 {
-        let message: String = format!("unsupported compression method {}", method);
-        return Err(raise_error(message));
-    }
+    let method_byte: u8 = reader.read_scalar()?;
+    let method = match CompressionMethod::from_byte(method_byte) {
+        Some(method) => method,
+        None => {
+            let message: String = format!("unsupported compression method {}", method_byte);
+            return Err(raise_error(message));
+        }
+    };
 
     let compressed: u32 = reader.read_scalar()?;
     let original: u32 = reader.read_scalar()?;
@@ -79,36 +131,129 @@ where R: ReadEx {
         return Err(raise_error("compressed data too big".to_string()));
     }
 
+    // the checksum can be forged, the size must be checked before allocating
+    if original > DBMS_MAX_COMPRESSED_SIZE {
+        return Err(raise_error("decompressed data too big".to_string()));
+    }
+
+    if (compressed as usize) < HEADER_SIZE {
+        return Err(raise_error("compressed data too small".to_string()));
+    }
+
     buffer.resize(compressed as usize, 0_u8);
     {
         let mut cursor = io::Cursor::new(&mut buffer);
-        cursor.write_u8(0x82)?;
+        cursor.write_u8(method_byte)?;
         cursor.write_u32::<LittleEndian>(compressed)?;
         cursor.write_u32::<LittleEndian>(original)?;
     }
-    reader.read_bytes(&mut buffer[9..])?;
+    reader.read_bytes(&mut buffer[HEADER_SIZE..])?;
 
     if h != city_hash_128(&buffer) {
         return Err(raise_error("data was corrupted".to_string()));
     }
 
-    let data = vec![0_u8; original as usize];
-    let status = unsafe {
-        LZ4_decompress_safe(
-            (buffer.as_mut_ptr() as *const c_char).add(9),
-            data.as_ptr() as *mut c_char,
-            (compressed - 9) as c_int,
-            original as c_int
-        )
+    let payload = &buffer[HEADER_SIZE..];
+    let data = match method {
+        CompressionMethod::None => payload.to_vec(),
+        CompressionMethod::LZ4 => {
+            let mut data = vec![0_u8; original as usize];
+            let status = unsafe {
+                LZ4_decompress_safe(
+                    payload.as_ptr() as *const c_char,
+                    data.as_mut_ptr() as *mut c_char,
+                    payload.len() as c_int,
+                    original as c_int
+                )
+            };
+
+            if status < 0 {
+                return Err(raise_error("can't decompress data".to_string()));
+            }
+            // the rest of the buffer would be taken for data
+            if status as u32 != original {
+                return Err(raise_error("decompressed size mismatch".to_string()));
+            }
+            data
+        }
+        CompressionMethod::ZSTD => zstd::block::decompress(payload, original as usize)
+            .map_err(|e| raise_error(format!("can't decompress data: {}", e)))?
     };
 
-    if status < 0 {
-        return Err(raise_error("can't decompress data".to_string()));
+    if data.len() != original as usize {
+        return Err(raise_error("decompressed size mismatch".to_string()));
     }
 
     Ok(data)
 }
 
+/// Takes a compressed frame out of `buffer` and decompresses it. Returns `None` until the
+/// whole frame has been received, its size is known from the header.
+pub(crate) fn take_frame(buffer: &mut BytesMut) -> Result<Option<Vec<u8>>> {
+    if buffer.len() < CHECKSUM_SIZE + HEADER_SIZE {
+        return Ok(None);
+    }
+
+    let compressed = LittleEndian::read_u32(&buffer[CHECKSUM_SIZE + 1..]);
+    if compressed > DBMS_MAX_COMPRESSED_SIZE {
+        return Err(raise_error("compressed data too big".to_string()));
+    }
+
+    let frame_len = CHECKSUM_SIZE + compressed as usize;
+    if buffer.len() < frame_len {
+        // make room for the rest of the frame at once
+        buffer.reserve(frame_len - buffer.len());
+        return Ok(None);
+    }
+
+    let frame = buffer.split_to(frame_len);
+    decompress_buffer(&mut &frame[..], Vec::new()).map(Some)
+}
+
+/// Compresses `data` into a frame: checksum, method byte, sizes and the compressed data.
+pub(crate) fn compress_buffer(data: &[u8], method: CompressionMethod) -> Result<Vec<u8>> {
+    let mut buf = vec![0_u8; HEADER_SIZE];
+    match method {
+        CompressionMethod::None => buf.extend_from_slice(data),
+        CompressionMethod::LZ4 => {
+            let size;
+            unsafe {
+                buf.resize(
+                    HEADER_SIZE + LZ4_compressBound(data.len() as i32) as usize,
+                    0_u8
+                );
+                size = LZ4_compress_default(
+                    data.as_ptr() as *const c_char,
+                    (buf.as_mut_ptr() as *mut c_char).add(HEADER_SIZE),
+                    data.len() as i32,
+                    (buf.len() - HEADER_SIZE) as i32
+                );
+            }
+            buf.resize(HEADER_SIZE + size as usize, 0_u8);
+        }
+        CompressionMethod::ZSTD => {
+            let compressed = zstd::block::compress(data, ZSTD_LEVEL)
+                .map_err(|e| raise_error(format!("can't compress data: {}", e)))?;
+            buf.extend_from_slice(&compressed);
+        }
+    }
+
+    let buf_len = buf.len() as u32;
+    {
+        let mut cursor = io::Cursor::new(&mut buf);
+        cursor.write_u8(method.byte()).unwrap();
+        cursor.write_u32::<LittleEndian>(buf_len).unwrap();
+        cursor.write_u32::<LittleEndian>(data.len() as u32).unwrap();
+    }
+
+    let hash = city_hash_128(&buf);
+    let mut frame = Vec::with_capacity(16 + buf.len());
+    frame.write_u64::<LittleEndian>(hash.lo).unwrap();
+    frame.write_u64::<LittleEndian>(hash.hi).unwrap();
+    frame.extend_from_slice(&buf);
+    Ok(frame)
+}
+
 fn raise_error(message: String) -> Error {
     message.into()
 }
@@ -135,4 +280,54 @@ mod test {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_compress_and_decompress() {
+        let data: Vec<u8> = (0..1000_u32).map(|i| (i % 7) as u8).collect();
+
+        for method in &[
+            CompressionMethod::None,
+            CompressionMethod::LZ4,
+            CompressionMethod::ZSTD
+        ] {
+            let frame = compress_buffer(&data, *method).unwrap();
+            assert_eq!(frame[16], method.byte());
+
+            let mut cursor = io::Cursor::new(&frame[..]);
+            let actual = decompress_buffer(&mut cursor, Vec::new()).unwrap();
+            assert_eq!(actual, data);
+        }
+    }
+
+    // Replaces the decompressed size in the header of `frame`, with a valid checksum.
+    fn set_original_size(frame: &mut Vec<u8>, original: u32) {
+        LittleEndian::write_u32(&mut frame[CHECKSUM_SIZE + 5..], original);
+        let hash = city_hash_128(&frame[CHECKSUM_SIZE..]);
+        LittleEndian::write_u64(&mut frame[..8], hash.lo);
+        LittleEndian::write_u64(&mut frame[8..16], hash.hi);
+    }
+
+    #[test]
+    fn test_decompress_wrong_size() {
+        let data: Vec<u8> = (0..1000_u32).map(|i| (i % 7) as u8).collect();
+
+        for original in &[1200, DBMS_MAX_COMPRESSED_SIZE + 1] {
+            let mut frame = compress_buffer(&data, CompressionMethod::LZ4).unwrap();
+            set_original_size(&mut frame, *original);
+
+            let mut cursor = io::Cursor::new(&frame[..]);
+            assert!(decompress_buffer(&mut cursor, Vec::new()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decompress_corrupted() {
+        let data = vec![1_u8, 2, 3];
+        let mut frame = compress_buffer(&data, CompressionMethod::None).unwrap();
+        let last = frame.len() - 1;
+        frame[last] = 4;
+
+        let mut cursor = io::Cursor::new(&frame[..]);
+        assert!(decompress_buffer(&mut cursor, Vec::new()).is_err());
+    }
 }
@@ -0,0 +1,305 @@
+use std::io::Cursor;
+use std::mem;
+
+use bytes::Buf;
+use bytes::BytesMut;
+use chrono_tz::Tz;
+
+use super::compressed;
+use super::Block;
+use super::BlockInfo;
+use crate::binary::ReadEx;
+use crate::errors::Result;
+use crate::protocols::DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION;
+use crate::types::column::factory::SkipProgress;
+use crate::types::column::Column;
+use crate::types::column::ColumnData;
+use crate::types::column::LowCardinalityColumnData;
+
+/// Decodes the block of a `Data` packet while its bytes are being received.
+///
+/// Compressed blocks are only decompressed once their frames are complete, and every
+/// frame is decompressed once. Columns are decoded as soon as all of their bytes are
+/// there, and the bytes of decoded columns are dropped. Whether the column which is still
+/// incomplete has been received is checked again when more data arrives, from where the
+/// previous check stopped.
+pub(crate) struct BlockDecoder {
+    tz: Tz,
+    compress: bool,
+    revision: u64,
+
+    // Decompressed bytes which are not decoded yet.
+    data: BytesMut,
+    // The number of columns and rows, once the block header was decoded.
+    header: Option<(usize, usize)>,
+    // How far the data of the next column was checked.
+    progress: SkipProgress,
+    block: Block
+}
+
+impl BlockDecoder {
+    /// Decodes a block in the Native format of the given protocol revision.
+    pub(crate) fn new(tz: Tz, compress: bool, revision: u64) -> Self {
+        Self {
+            tz,
+            compress,
+            revision,
+            data: BytesMut::new(),
+            header: None,
+            progress: SkipProgress::default(),
+            block: Block::new()
+        }
+    }
+
+    /// Consumes the bytes of the block from `buffer`, returns the block once all of its
+    /// columns are decoded. Bytes which are not part of the block are left in `buffer`.
+    pub(crate) fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<Block>> {
+        if !self.compress {
+            return self.decode_columns(buffer);
+        }
+
+        // The last frame of a block can't be told apart from the others, so decoding is
+        // tried after each one: the next bytes may already belong to another packet.
+        while let Some(frame) = compressed::take_frame(buffer)? {
+            let mut data = mem::take(&mut self.data);
+            data.extend_from_slice(&frame);
+
+            let block = self.decode_columns(&mut data)?;
+            self.data = data;
+
+            if block.is_some() {
+                if !self.data.is_empty() {
+                    return Err("unexpected data after the end of the block".into());
+                }
+                return Ok(block);
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn decode_columns(&mut self, data: &mut BytesMut) -> Result<Option<Block>> {
+        loop {
+            let mut cursor = Cursor::new(&data[..]);
+
+            let res = match self.header {
+                None => self.read_header(&mut cursor),
+                Some((num_columns, num_rows)) if self.block.column_count() < num_columns => {
+                    if !is_column_received(&data[..], num_rows, self.revision, &mut self.progress) {
+                        return Ok(None);
+                    }
+                    self.progress = SkipProgress::default();
+                    Column::read(&mut cursor, num_rows, self.tz, self.revision)
+                        .map(|column| self.block.append_column(column))
+                }
+                Some(_) => return Ok(Some(mem::replace(&mut self.block, Block::new())))
+            };
+
+            match res {
+                Ok(()) => {
+                    let len = cursor.position() as usize;
+                    data.advance(len);
+                }
+                // The rest of the block hasn't been received yet.
+                Err(err) if err.is_would_block() => return Ok(None),
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    fn read_header(&mut self, reader: &mut Cursor<&[u8]>) -> Result<()> {
+        let info = BlockInfo::read(reader)?;
+        let num_columns = reader.read_uvarint()? as usize;
+        let num_rows = reader.read_uvarint()? as usize;
+
+        self.block.info = info;
+        self.header = Some((num_columns, num_rows));
+        Ok(())
+    }
+}
+
+/// Checks cheaply whether all the data of a column has been received, so that a column
+/// isn't decoded over and over while it arrives. Types which aren't known here are
+/// considered as received, decoding them tells.
+fn is_column_received(
+    data: &[u8],
+    rows: usize,
+    revision: u64,
+    progress: &mut SkipProgress
+) -> bool {
+    let mut reader = Cursor::new(data);
+    let type_name = match reader.skip_string().and_then(|_| reader.read_string()) {
+        Ok(type_name) => type_name,
+        Err(_) => return false
+    };
+    if revision >= DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION
+        && reader.read_scalar::<u8>().is_err()
+    {
+        return false;
+    }
+    if rows > 0 && LowCardinalityColumnData::skip_prefix(&mut reader, &type_name).is_none() {
+        return false;
+    }
+
+    <dyn ColumnData>::skip_data(&mut reader, &type_name, rows, progress).is_some()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::binary::Encoder;
+    use crate::protocols::DBMS_TCP_PROTOCOL_VERSION;
+    use crate::types::CompressionMethod;
+    use crate::types::Decimal;
+
+    fn encode(block: &Block, compression: Option<CompressionMethod>, revision: u64) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, compression, revision).unwrap();
+        encoder.get_buffer()
+    }
+
+    #[test]
+    fn test_decode_in_pieces() {
+        let block = Block::new()
+            .column("id", (0..10_000_u32).collect::<Vec<_>>())
+            .column(
+                "name",
+                (0..10_000).map(|i| format!("n{}", i)).collect::<Vec<_>>()
+            );
+
+        let cases = [
+            (None, 0),
+            (Some(CompressionMethod::LZ4), 0),
+            (None, DBMS_TCP_PROTOCOL_VERSION),
+            (Some(CompressionMethod::LZ4), DBMS_TCP_PROTOCOL_VERSION)
+        ];
+        for (compression, revision) in cases.iter() {
+            let mut bytes = encode(&block, *compression, *revision);
+            // the start of the next packet
+            bytes.push(4);
+
+            let mut decoder = BlockDecoder::new(Tz::UTC, compression.is_some(), *revision);
+            let mut buffer = BytesMut::new();
+            let mut decoded = None;
+
+            for chunk in bytes.chunks(1000) {
+                buffer.extend_from_slice(chunk);
+                if decoded.is_none() {
+                    decoded = decoder.decode(&mut buffer).unwrap();
+                }
+            }
+
+            assert_eq!(decoded.unwrap(), block);
+            assert_eq!(&buffer[..], &[4]);
+        }
+    }
+
+    #[test]
+    fn test_custom_serialization() {
+        let block = Block::new().column("id", vec![1_u8, 2]);
+        let mut bytes = encode(&block, None, DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION);
+        // the values follow the flag of a custom serialization
+        let flag = bytes.len() - 3;
+        assert_eq!(bytes[flag..], [0, 1, 2]);
+
+        let mut buffer = BytesMut::from(&bytes[..]);
+        let mut decoder =
+            BlockDecoder::new(Tz::UTC, false, DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION);
+        assert_eq!(decoder.decode(&mut buffer).unwrap().unwrap(), block);
+
+        // columns with a custom serialization can't be read
+        bytes[flag] = 1;
+        let mut buffer = BytesMut::from(&bytes[..]);
+        let mut decoder =
+            BlockDecoder::new(Tz::UTC, false, DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION);
+        assert!(decoder.decode(&mut buffer).is_err());
+    }
+
+    #[test]
+    fn test_composite_column_received() {
+        let block = Block::new()
+            .column("n", vec![Some(1_u32), None])
+            .column("a", vec![vec![1_u32, 2], vec![3]])
+            .column("s", vec!["foo", "bar"])
+            .low_cardinality("s")
+            .unwrap()
+            .column("t", vec![(1_u8, "x".to_string()), (2, "yz".to_string())])
+            .column("m", vec![
+                HashMap::from([("a".to_string(), 1_u8)]),
+                HashMap::new(),
+            ])
+            .column("d", vec![Decimal::of(1.5, 2), Decimal::of(-2, 2)]);
+
+        for column in block.columns() {
+            let mut encoder = Encoder::new();
+            encoder.string(column.name());
+            encoder.string(column.sql_type().to_string());
+            let header = encoder.get_buffer_ref().len();
+            LowCardinalityColumnData::save_prefix(&mut encoder, &column.sql_type().to_string());
+            column.data.save(&mut encoder, 0, column.len());
+            let bytes = encoder.get_buffer();
+
+            let mut progress = SkipProgress::default();
+            for len in header..bytes.len() {
+                assert!(
+                    !is_column_received(&bytes[..len], 2, 0, &mut progress),
+                    "{}",
+                    column.name()
+                );
+            }
+            // resumes where the checks of the incomplete data stopped
+            assert!(
+                is_column_received(&bytes, 2, 0, &mut progress),
+                "{}",
+                column.name()
+            );
+            assert!(
+                is_column_received(&bytes, 2, 0, &mut SkipProgress::default()),
+                "{}",
+                column.name()
+            );
+        }
+
+        for (type_name, value_size) in &[
+            ("FixedString(3)", 3),
+            ("DateTime64(3, 'UTC')", 8),
+            ("Enum16('a' = 1, 'b' = 2)", 2),
+            ("Decimal256(2)", 32)
+        ] {
+            let mut encoder = Encoder::new();
+            encoder.string("x");
+            encoder.string(type_name);
+            let mut bytes = encoder.get_buffer();
+            bytes.resize(bytes.len() + 2 * value_size, 0);
+
+            assert!(
+                is_column_received(&bytes, 2, 0, &mut SkipProgress::default()),
+                "{}",
+                type_name
+            );
+            assert!(
+                !is_column_received(
+                    &bytes[..bytes.len() - 1],
+                    2,
+                    0,
+                    &mut SkipProgress::default()
+                ),
+                "{}",
+                type_name
+            );
+        }
+
+        // decoding tells whether the data of unknown types is complete
+        let mut encoder = Encoder::new();
+        encoder.string("x");
+        encoder.string("Nothing");
+        assert!(is_column_received(
+            &encoder.get_buffer(),
+            2,
+            0,
+            &mut SkipProgress::default()
+        ));
+    }
+}
@@ -1,23 +1,18 @@
 use std::cmp;
 use std::default::Default;
 use std::fmt;
-use std::io::Cursor;
 use std::io::Read;
 use std::marker::PhantomData;
-use std::os::raw::c_char;
 
-use byteorder::LittleEndian;
-use byteorder::WriteBytesExt;
 use chrono_tz::Tz;
-use clickhouse_rs_cityhash_sys::city_hash_128;
-use lz4::liblz4::LZ4_compressBound;
-use lz4::liblz4::LZ4_compress_default;
 
 pub use self::block_info::BlockInfo;
 pub use self::builder::RCons;
 pub use self::builder::RNil;
 pub use self::builder::RowBuilder;
 use self::chunk_iterator::ChunkIterator;
+pub use self::compressed::CompressionMethod;
+pub(crate) use self::decoder::BlockDecoder;
 pub(crate) use self::row::BlockRef;
 pub use self::row::Row;
 pub use self::row::Rows;
@@ -41,6 +36,7 @@ mod block_info;
 mod builder;
 mod chunk_iterator;
 mod compressed;
+mod decoder;
 mod row;
 
 const INSERT_BLOCK_SIZE: usize = 1_048_576;
@@ -160,17 +156,18 @@ impl Block {
         }
     }
 
-    pub(crate) fn load<R>(reader: &mut R, tz: Tz, compress: bool) -> Result<Self>
+    /// Reads a block in the Native format of the given protocol revision.
+    pub(crate) fn load<R>(reader: &mut R, tz: Tz, compress: bool, revision: u64) -> Result<Self>
     where R: Read + ReadEx {
         if compress {
             let mut cr = compressed::make(reader);
-            Self::raw_load(&mut cr, tz)
+            Self::raw_load(&mut cr, tz, revision)
         } else {
-            Self::raw_load(reader, tz)
+            Self::raw_load(reader, tz, revision)
         }
     }
 
-    fn raw_load<R>(reader: &mut R, tz: Tz) -> Result<Block<Simple>>
+    fn raw_load<R>(reader: &mut R, tz: Tz, revision: u64) -> Result<Block<Simple>>
     where R: ReadEx {
         let mut block = Block::new();
         block.info = BlockInfo::read(reader)?;
@@ -179,7 +176,7 @@ impl Block {
         let num_rows = reader.read_uvarint()?;
 
         for _ in 0..num_columns {
-            let column = Column::read(reader, num_rows as usize, tz)?;
+            let column = Column::read(reader, num_rows as usize, tz, revision)?;
             block.append_column(column);
         }
 
@@ -243,6 +240,17 @@ impl<K: ColumnType> Block<K> {
         self
     }
 
+    /// Converts a column to `LowCardinality` of its type, e.g. for a client which
+    /// inserts into a `LowCardinality` column.
+    pub fn low_cardinality<I>(mut self, col: I) -> Result<Self>
+    where I: ColumnIdx + Copy {
+        let index = col.get_index(self.columns())?;
+        let column = self.columns[index].clone();
+        let sql_type = SqlType::LowCardinality(column.sql_type().into());
+        self.columns[index] = column.cast_to(sql_type)?;
+        Ok(self)
+    }
+
     /// Returns true if the block contains no elements.
     pub fn is_empty(&self) -> bool {
         self.columns.is_empty()
@@ -323,62 +331,83 @@ impl<K: ColumnType> Block<K> {
         })
     }
 
-    pub(crate) fn write(&self, encoder: &mut Encoder, compress: bool) {
-        if compress {
+    /// Replaces the `LowCardinality` columns by columns of their type, for the clients
+    /// which don't support it.
+    pub(crate) fn remove_low_cardinality(&self) -> Result<Self> {
+        let mut block = self.clone();
+        for column in block.columns.iter_mut() {
+            if let SqlType::LowCardinality(inner_type) = column.sql_type() {
+                *column = column.clone().cast_to(inner_type.clone())?;
+            }
+        }
+        Ok(block)
+    }
+
+    /// Writes the block in the Native format of the given protocol revision, wrapped
+    /// into a compressed frame if a method is given.
+    pub(crate) fn write(
+        &self,
+        encoder: &mut Encoder,
+        method: Option<CompressionMethod>,
+        revision: u64
+    ) -> Result<()> {
+        if let Some(method) = method {
             let mut tmp_encoder = Encoder::new();
-            self.write(&mut tmp_encoder, false);
+            self.write(&mut tmp_encoder, None, revision)?;
             let tmp = tmp_encoder.get_buffer();
 
-            let mut buf = Vec::new();
-            let size;
-            unsafe {
-                buf.resize(9 + LZ4_compressBound(tmp.len() as i32) as usize, 0_u8);
-                size = LZ4_compress_default(
-                    tmp.as_ptr() as *const c_char,
-                    (buf.as_mut_ptr() as *mut c_char).add(9),
-                    tmp.len() as i32,
-                    buf.len() as i32
-                );
-            }
-            buf.resize(9 + size as usize, 0_u8);
-
-            let buf_len = buf.len() as u32;
-            {
-                let mut cursor = Cursor::new(&mut buf);
-                cursor.write_u8(0x82).unwrap();
-                cursor.write_u32::<LittleEndian>(buf_len).unwrap();
-                cursor.write_u32::<LittleEndian>(tmp.len() as u32).unwrap();
-            }
-
-            let hash = city_hash_128(&buf);
-            encoder.write(hash.lo);
-            encoder.write(hash.hi);
-            encoder.write_bytes(buf.as_ref());
+            encoder.write_bytes(&compressed::compress_buffer(&tmp, method)?);
         } else {
             self.info.write(encoder);
             encoder.uvarint(self.column_count() as u64);
             encoder.uvarint(self.row_count() as u64);
 
             for column in &self.columns {
-                column.write(encoder);
+                column.write(encoder, revision);
             }
         }
+        Ok(())
     }
 
-    pub(crate) fn send_client_data(&self, encoder: &mut Encoder, compress: bool) {
+    pub(crate) fn send_client_data(
+        &self,
+        encoder: &mut Encoder,
+        compression: Option<CompressionMethod>,
+        revision: u64
+    ) -> Result<()> {
         encoder.uvarint(protocols::CLIENT_DATA);
         encoder.string(""); // temporary table
         for chunk in self.chunks(INSERT_BLOCK_SIZE) {
-            chunk.write(encoder, compress);
+            chunk.write(encoder, compression, revision)?;
         }
+        Ok(())
     }
 
-    pub(crate) fn send_server_data(&self, encoder: &mut Encoder, compress: bool) {
+    pub(crate) fn send_server_data(
+        &self,
+        encoder: &mut Encoder,
+        compression: Option<CompressionMethod>,
+        revision: u64
+    ) -> Result<()> {
         encoder.uvarint(protocols::SERVER_DATA);
         encoder.string(""); // temporary table
         for chunk in self.chunks(INSERT_BLOCK_SIZE) {
-            chunk.write(encoder, compress);
+            chunk.write(encoder, compression, revision)?;
         }
+        Ok(())
+    }
+
+    /// Writes the whole block as a single `SERVER_TOTALS` or `SERVER_EXTREMES` packet.
+    pub(crate) fn send_server_packet(
+        &self,
+        encoder: &mut Encoder,
+        packet: u64,
+        compression: Option<CompressionMethod>,
+        revision: u64
+    ) -> Result<()> {
+        encoder.uvarint(packet);
+        encoder.string(""); // temporary table
+        self.write(encoder, compression, revision)
     }
 
     pub(crate) fn chunks(&self, n: usize) -> ChunkIterator<K> {
@@ -451,13 +480,19 @@ fn text_cells<K: ColumnType>(data: &Column<K>) -> Vec<String> {
 
 #[cfg(test)]
 mod test {
+    use std::io::Cursor;
+
     use super::*;
+    use crate::types::I256;
+    use crate::types::U256;
 
     #[test]
     fn test_write_default() {
         let expected = [1_u8, 0, 2, 255, 255, 255, 255, 0, 0, 0];
         let mut encoder = Encoder::new();
-        Block::<Simple>::default().write(&mut encoder, false);
+        Block::<Simple>::default()
+            .write(&mut encoder, None, 0)
+            .unwrap();
         assert_eq!(encoder.get_buffer_ref(), &expected)
     }
 
@@ -472,7 +507,9 @@ mod test {
         let block = Block::<Simple>::new().column("s", vec!["abc"]);
 
         let mut encoder = Encoder::new();
-        block.write(&mut encoder, true);
+        block
+            .write(&mut encoder, Some(CompressionMethod::LZ4), 0)
+            .unwrap();
 
         let actual = encoder.get_buffer();
         assert_eq!(actual, expected);
@@ -489,16 +526,33 @@ mod test {
         ];
 
         let mut cursor = Cursor::new(&source[..]);
-        let actual = Block::load(&mut cursor, Tz::UTC, true).unwrap();
+        let actual = Block::load(&mut cursor, Tz::UTC, true, 0).unwrap();
 
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_send_server_packet() {
+        let block = Block::<Simple>::new().column("s", vec!["abc"]);
+
+        let mut expected = Encoder::new();
+        block.write(&mut expected, None, 0).unwrap();
+
+        let mut encoder = Encoder::new();
+        block
+            .send_server_packet(&mut encoder, protocols::SERVER_TOTALS, None, 0)
+            .unwrap();
+
+        let actual = encoder.get_buffer();
+        assert_eq!(&actual[..2], &[7_u8, 0]);
+        assert_eq!(&actual[2..], expected.get_buffer_ref());
+    }
+
     #[test]
     fn test_read_empty_block() {
         let source = [1, 0, 2, 255, 255, 255, 255, 0, 0, 0];
         let mut cursor = Cursor::new(&source[..]);
-        match Block::<Simple>::load(&mut cursor, Tz::Zulu, false) {
+        match Block::<Simple>::load(&mut cursor, Tz::Zulu, false, 0) {
             Ok(block) => assert!(block.is_empty()),
             Err(_) => unreachable!()
         }
@@ -598,11 +652,40 @@ mod test {
         let block = Block::<Simple>::new().column("y", vec![Some(1_u8), None]);
 
         let mut encoder = Encoder::new();
-        block.write(&mut encoder, false);
+        block.write(&mut encoder, None, 0).unwrap();
+
+        let mut reader = Cursor::new(encoder.get_buffer_ref());
+        let rblock = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
+
+        assert_eq!(block, rblock);
+    }
+
+    #[test]
+    fn test_write_and_read_wide_integers() {
+        let block = Block::<Simple>::new()
+            .column("i128", vec![i128::MIN, -1, i128::MAX])
+            .column("u128", vec![0, 1, u128::MAX])
+            .column("i256", vec![
+                I256::from(-1),
+                I256::from(0),
+                I256::from(i128::MAX),
+            ])
+            .column("u256", vec![
+                U256::from(0),
+                U256::from(1),
+                U256::from(u128::MAX),
+            ]);
+
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
 
         let mut reader = Cursor::new(encoder.get_buffer_ref());
-        let rblock = Block::load(&mut reader, Tz::Zulu, false).unwrap();
+        let rblock = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
 
         assert_eq!(block, rblock);
+        assert_eq!(rblock.get::<i128, _>(0, "i128").unwrap(), i128::MIN);
+        assert_eq!(rblock.get::<u128, _>(2, "u128").unwrap(), u128::MAX);
+        assert_eq!(rblock.get::<I256, _>(0, "i256").unwrap().to_string(), "-1");
+        assert_eq!(rblock.columns()[3].sql_type(), SqlType::UInt256);
     }
 }
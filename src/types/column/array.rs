@@ -144,10 +144,10 @@ mod test {
             ]]);
 
         let mut encoder = Encoder::new();
-        block.write(&mut encoder, false);
+        block.write(&mut encoder, None, 0).unwrap();
 
         let mut reader = Cursor::new(encoder.get_buffer_ref());
-        let rblock = Block::load(&mut reader, Tz::Zulu, false).unwrap();
+        let rblock = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
 
         assert_eq!(block, rblock);
     }
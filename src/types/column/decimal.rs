@@ -7,13 +7,11 @@ use crate::binary::ReadEx;
 use crate::errors::Result;
 use crate::types::column::column_data::BoxColumnData;
 use crate::types::column::column_data::ColumnData;
-use crate::types::column::list::List;
 use crate::types::column::nullable::NullableColumnData;
 use crate::types::column::BoxColumnWrapper;
 use crate::types::column::ColumnFrom;
 use crate::types::column::ColumnWrapper;
 use crate::types::column::Either;
-use crate::types::column::VectorColumnData;
 use crate::types::decimal::Decimal;
 use crate::types::decimal::NoBits;
 use crate::types::from_sql::FromSql;
@@ -22,6 +20,7 @@ use crate::types::ColumnType;
 use crate::types::SqlType;
 use crate::types::Value;
 use crate::types::ValueRef;
+use crate::types::I256;
 
 pub(crate) struct DecimalColumnData {
     pub(crate) inner: Box<dyn ColumnData + Send + Sync>,
@@ -53,12 +52,9 @@ impl DecimalColumnData {
         size: usize,
         tz: Tz
     ) -> Result<Self> {
-        let type_name = match nobits {
-            NoBits::N32 => "Int32",
-            NoBits::N64 => "Int64"
-        };
+        let type_name = nobits.inner_type().to_string();
         let inner =
-            <dyn ColumnData>::load_data::<BoxColumnWrapper, _>(reader, type_name, size, tz)?;
+            <dyn ColumnData>::load_data::<BoxColumnWrapper, _>(reader, &type_name, size, tz)?;
 
         Ok(DecimalColumnData {
             inner,
@@ -67,34 +63,62 @@ impl DecimalColumnData {
             nobits
         })
     }
+
+    pub(crate) fn with_capacity(precision: u8, scale: u8, capacity: usize) -> Self {
+        let nobits = NoBits::from_precision(precision).unwrap();
+        let inner =
+            <dyn ColumnData>::from_type::<BoxColumnWrapper>(nobits.inner_type(), Tz::UTC, capacity)
+                .unwrap();
+
+        DecimalColumnData {
+            inner,
+            precision,
+            scale,
+            nobits
+        }
+    }
+}
+
+/// The precision and the scale of a column of the given decimals, which all have to
+/// be of the same scale.
+fn column_params<'a>(source: impl Iterator<Item = &'a Decimal>) -> (u8, u8) {
+    let mut precision = 18;
+    let mut opt_scale = None;
+    for s in source {
+        precision = std::cmp::max(precision, s.precision);
+        if let Some(old_scale) = opt_scale {
+            if old_scale != s.scale {
+                panic!("scale != scale")
+            }
+        } else {
+            opt_scale = Some(s.scale);
+        }
+    }
+    (precision, opt_scale.unwrap_or(4))
+}
+
+/// Converts a decimal to the precision and the scale of another column, fails when it
+/// doesn't fit.
+pub(crate) fn cast_decimal(decimal: Decimal, precision: u8, scale: u8) -> Result<Decimal> {
+    let nobits = match NoBits::from_precision(precision) {
+        Some(nobits) => nobits,
+        None => return Err(format!("Invalid precision {}.", precision).into())
+    };
+    Decimal {
+        precision,
+        nobits,
+        ..decimal
+    }
+    .set_scale(scale)
 }
 
 impl ColumnFrom for Vec<Decimal> {
     fn column_from<W: ColumnWrapper>(source: Self) -> W::Wrapper {
-        let mut data = List::<i64>::with_capacity(source.len());
-        let mut precision = 18;
-        let mut opt_scale = None;
+        let (precision, scale) = column_params(source.iter());
+        let mut column = DecimalColumnData::with_capacity(precision, scale, source.len());
         for s in source {
-            precision = std::cmp::max(precision, s.precision);
-            if let Some(old_scale) = opt_scale {
-                if old_scale != s.scale {
-                    panic!("scale != scale")
-                }
-            } else {
-                opt_scale = Some(s.scale);
-            }
-
-            data.push(s.internal());
+            column.push(Value::Decimal(s));
         }
-        let scale = opt_scale.unwrap_or(4);
-        let inner = Box::new(VectorColumnData { data });
-
-        let column = DecimalColumnData {
-            inner,
-            precision,
-            scale,
-            nobits: NoBits::N64
-        };
 
         W::wrap(column)
     }
@@ -102,37 +126,18 @@ impl ColumnFrom for Vec<Decimal> {
 
 impl ColumnFrom for Vec<Option<Decimal>> {
     fn column_from<W: ColumnWrapper>(source: Self) -> W::Wrapper {
+        let (precision, scale) = column_params(source.iter().flatten());
         let mut nulls: Vec<u8> = Vec::with_capacity(source.len());
-        let mut data = List::<i64>::with_capacity(source.len());
-        let mut precision = 18;
-        let mut opt_scale = None;
+        let mut inner = DecimalColumnData::with_capacity(precision, scale, source.len());
         for os in source {
             if let Some(s) = os {
-                precision = std::cmp::max(precision, s.precision);
-                if let Some(old_scale) = opt_scale {
-                    if old_scale != s.scale {
-                        panic!("scale != scale")
-                    }
-                } else {
-                    opt_scale = Some(s.scale);
-                }
-
-                data.push(s.internal());
+                inner.push(Value::Decimal(s));
                 nulls.push(0);
             } else {
-                data.push(0);
+                inner.push(Value::default(SqlType::Decimal(precision, scale)));
                 nulls.push(1);
             }
         }
-        let scale = opt_scale.unwrap_or(4);
-        let inner = Box::new(VectorColumnData { data });
-
-        let inner = DecimalColumnData {
-            inner,
-            precision,
-            scale,
-            nobits: NoBits::N64
-        };
 
         W::wrap(NullableColumnData {
             nulls,
@@ -165,6 +170,14 @@ impl ColumnData for DecimalColumnData {
                     let internal: i64 = decimal.internal();
                     self.inner.push(internal.into())
                 }
+                NoBits::N128 => {
+                    let internal: i128 = decimal.internal();
+                    self.inner.push(internal.into())
+                }
+                NoBits::N256 => {
+                    let internal: I256 = decimal.internal();
+                    self.inner.push(internal.into())
+                }
             }
         } else {
             panic!("value should be decimal ({:?})", value);
@@ -172,13 +185,15 @@ impl ColumnData for DecimalColumnData {
     }
 
     fn at(&self, index: usize) -> ValueRef {
-        let underlying: i64 = match self.nobits {
-            NoBits::N32 => i64::from(i32::from(self.inner.at(index))),
-            NoBits::N64 => i64::from(self.inner.at(index))
+        let underlying = match self.nobits {
+            NoBits::N32 => I256::from(i128::from(i32::from(self.inner.at(index)))),
+            NoBits::N64 => I256::from(i128::from(i64::from(self.inner.at(index)))),
+            NoBits::N128 => I256::from(i128::from(self.inner.at(index))),
+            NoBits::N256 => I256::from(self.inner.at(index))
         };
 
         ValueRef::Decimal(Decimal {
-            underlying,
+            underlying: underlying.into(),
             precision: self.precision,
             scale: self.scale,
             nobits: self.nobits
@@ -219,6 +234,14 @@ impl<K: ColumnType> ColumnData for DecimalAdapter<K> {
                         let internal: i64 = decimal.internal();
                         encoder.write(internal);
                     }
+                    NoBits::N128 => {
+                        let internal: i128 = decimal.internal();
+                        encoder.write(internal);
+                    }
+                    NoBits::N256 => {
+                        let internal: I256 = decimal.internal();
+                        encoder.write(internal);
+                    }
                 }
             } else {
                 panic!("should be decimal");
@@ -236,9 +259,8 @@ impl<K: ColumnType> ColumnData for DecimalAdapter<K> {
 
     fn at(&self, index: usize) -> ValueRef {
         if let ValueRef::Decimal(decimal) = self.column.at(index) {
-            let mut d = decimal.set_scale(self.scale);
-            d.precision = self.precision;
-            d.nobits = self.nobits;
+            // checked when the column is cast
+            let d = cast_decimal(decimal, self.precision, self.scale).unwrap();
             ValueRef::Decimal(d)
         } else {
             panic!("should be decimal");
@@ -270,13 +292,23 @@ impl<K: ColumnType> ColumnData for NullableDecimalAdapter<K> {
         encoder.write_bytes(nulls.as_ref());
 
         for value in values {
-            let underlying = if let Some(v) = value { v.underlying } else { 0 };
+            let underlying = if let Some(v) = value {
+                v.underlying.get()
+            } else {
+                I256::default()
+            };
 
             match self.nobits {
                 NoBits::N32 => {
-                    encoder.write(underlying as i32);
+                    encoder.write(underlying.as_i128() as i32);
                 }
                 NoBits::N64 => {
+                    encoder.write(underlying.as_i128() as i64);
+                }
+                NoBits::N128 => {
+                    encoder.write(underlying.as_i128());
+                }
+                NoBits::N256 => {
                     encoder.write(underlying);
                 }
             }
@@ -295,10 +327,9 @@ impl<K: ColumnType> ColumnData for NullableDecimalAdapter<K> {
         let value: Option<Decimal> = Option::from_sql(self.column.at(index)).unwrap();
         match value {
             None => ValueRef::Nullable(Either::Left(self.sql_type().into())),
-            Some(mut v) => {
-                v = v.set_scale(self.scale);
-                v.precision = self.precision;
-                v.nobits = self.nobits;
+            Some(v) => {
+                // checked when the column is cast
+                let v = cast_decimal(v, self.precision, self.scale).unwrap();
                 let inner = ValueRef::Decimal(v);
                 ValueRef::Nullable(Either::Right(Box::new(inner)))
             }
@@ -309,3 +340,59 @@ impl<K: ColumnType> ColumnData for NullableDecimalAdapter<K> {
         unimplemented!()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::types::Block;
+
+    fn write_and_load(block: &Block) -> Result<Block> {
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
+        let mut reader = Cursor::new(encoder.get_buffer());
+        Block::load(&mut reader, Tz::UTC, false, 0)
+    }
+
+    #[test]
+    fn test_wide_decimals() {
+        let big = 12_345_678_901_234_567_890_123_456_789_i128;
+        let block = Block::new()
+            .column("d128", vec![
+                Decimal::with_precision(I256::from(big), 38, 10).unwrap(),
+                Decimal::with_precision(I256::from(-1), 38, 10).unwrap(),
+            ])
+            .column("d256", vec![
+                Some(Decimal::with_precision(I256::from(-big), 50, 10).unwrap()),
+                None,
+            ]);
+
+        let actual = write_and_load(&block).unwrap();
+        assert_eq!(actual, block);
+        assert_eq!(actual.columns()[0].sql_type(), SqlType::Decimal(38, 10));
+
+        let d: Decimal = actual.get(0, "d128").unwrap();
+        assert_eq!(d.to_string(), "1234567890123456789.0123456789");
+        let d: Option<Decimal> = actual.get(0, "d256").unwrap();
+        assert_eq!(d.unwrap().internal::<i128>(), -big);
+    }
+
+    #[test]
+    fn test_load_decimal256() {
+        let value = I256::from(-12_345_678_901_234_567_890_123_456_789_i128)
+            .checked_mul(I256::from(10_i128.pow(38)))
+            .unwrap();
+        let block = Block::new().column("d", vec![Decimal::with_precision(value, 76, 40).unwrap()]);
+
+        let actual = write_and_load(&block).unwrap();
+        assert_eq!(actual, block);
+
+        let d: Decimal = actual.get(0, "d").unwrap();
+        assert_eq!(d.internal::<I256>(), value);
+        assert_eq!(
+            d.to_string(),
+            "-123456789012345678901234567.8900000000000000000000000000000000000000"
+        );
+    }
+}
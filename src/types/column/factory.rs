@@ -1,3 +1,5 @@
+use std::io::Cursor;
+
 use chrono_tz::Tz;
 use combine::any;
 use combine::error::StringStreamError;
@@ -27,15 +29,20 @@ use crate::types::column::ip::Ipv4;
 use crate::types::column::ip::Ipv6;
 use crate::types::column::ip::Uuid;
 use crate::types::column::list::List;
+use crate::types::column::low_cardinality::LowCardinalityColumnData;
+use crate::types::column::map::MapColumnData;
 use crate::types::column::nullable::NullableColumnData;
 use crate::types::column::numeric::VectorColumnData;
 use crate::types::column::string::StringColumnData;
+use crate::types::column::tuple::TupleColumnData;
 use crate::types::column::ArcColumnWrapper;
 use crate::types::column::BoxColumnWrapper;
 use crate::types::column::ColumnWrapper;
 use crate::types::decimal::NoBits;
 use crate::types::DateTimeType;
 use crate::types::SqlType;
+use crate::types::I256;
+use crate::types::U256;
 
 macro_rules! match_str {
     ($arg:ident, {
@@ -70,6 +77,10 @@ impl dyn ColumnData {
             "Int16" | "SmallInt" => W::wrap(VectorColumnData::<i16>::load(reader, size)?),
             "Int32" | "Int" | "Integer" => W::wrap(VectorColumnData::<i32>::load(reader, size)?),
             "Int64" | "BigInt" => W::wrap(VectorColumnData::<i64>::load(reader, size)?),
+            "Int128" => W::wrap(VectorColumnData::<i128>::load(reader, size)?),
+            "UInt128" => W::wrap(VectorColumnData::<u128>::load(reader, size)?),
+            "Int256" => W::wrap(VectorColumnData::<I256>::load(reader, size)?),
+            "UInt256" => W::wrap(VectorColumnData::<U256>::load(reader, size)?),
             "Float32" | "Float" => W::wrap(VectorColumnData::<f32>::load(reader, size)?),
             "Float64" | "Double" => W::wrap(VectorColumnData::<f64>::load(reader, size)?),
             "String" | "Char" | "Varchar" | "Text" | "TinyText" | "MediumText" | "LongText" | "Blob" | "TinyBlob" | "MediumBlob" | "LongBlob" => W::wrap(StringColumnData::load(reader, size)?),
@@ -81,10 +92,16 @@ impl dyn ColumnData {
             _ => {
                 if let Some(inner_type) = parse_nullable_type(type_name) {
                     W::wrap(NullableColumnData::load(reader, inner_type, size, tz)?)
+                } else if let Some(inner_type) = parse_low_cardinality_type(type_name) {
+                    W::wrap(LowCardinalityColumnData::load(reader, inner_type, size, tz)?)
                 } else if let Some(str_len) = parse_fixed_string(type_name) {
                     W::wrap(FixedStringColumnData::load(reader, size, str_len)?)
                 } else if let Some(inner_type) = parse_array_type(type_name) {
                     W::wrap(ArrayColumnData::load(reader, inner_type, size, tz)?)
+                } else if let Some(inner_types) = parse_tuple_type(type_name) {
+                    W::wrap(TupleColumnData::load(reader, &inner_types, size, tz)?)
+                } else if let Some((key_type, value_type)) = parse_map_type(type_name) {
+                    W::wrap(MapColumnData::load(reader, key_type, value_type, size, tz)?)
                 } else if let Some((precision, scale, nobits)) = parse_decimal(type_name) {
                     W::wrap(DecimalColumnData::load(
                         reader, precision, scale, nobits, size, tz,
@@ -104,6 +121,35 @@ impl dyn ColumnData {
         }))
     }
 
+    /// Skips `size` values of `type_name` without decoding them, to find out cheaply
+    /// whether all the data of a column has been received. Returns `None` when it's
+    /// incomplete, and `Some(false)` for types which aren't known here: decoding them tells.
+    ///
+    /// Strings are the only values which are skipped one by one, `progress` keeps where
+    /// that stopped, so the next call on the same data with more bytes resumes there.
+    pub(crate) fn skip_data(
+        reader: &mut Cursor<&[u8]>,
+        type_name: &str,
+        size: usize,
+        progress: &mut SkipProgress
+    ) -> Option<bool> {
+        let value_size = match_str!(type_name, {
+            "UInt8" | "Int8" | "TinyInt" => 1,
+            "UInt16" | "Int16" | "SmallInt" | "Date" => 2,
+            "UInt32" | "Int32" | "Int" | "Integer" | "Float32" | "Float" | "DateTime" | "Timestamp" | "IPv4" => 4,
+            "UInt64" | "Int64" | "BigInt" | "Float64" | "Double" => 8,
+            "Int128" | "UInt128" | "IPv6" | "UUID" => 16,
+            "Int256" | "UInt256" => 32,
+            "String" | "Char" | "Varchar" | "Text" | "TinyText" | "MediumText" | "LongText" | "Blob" | "TinyBlob" | "MediumBlob" | "LongBlob" => {
+                return progress.skip_strings(reader, size);
+            },
+            _ => {
+                return skip_composite_data(reader, type_name, size, progress);
+            }
+        });
+        skip_bytes(reader, size.checked_mul(value_size))
+    }
+
     pub(crate) fn from_type<W: ColumnWrapper>(
         sql_type: SqlType,
         timezone: Tz,
@@ -118,6 +164,10 @@ impl dyn ColumnData {
             SqlType::Int16 => W::wrap(VectorColumnData::<i16>::with_capacity(capacity)),
             SqlType::Int32 => W::wrap(VectorColumnData::<i32>::with_capacity(capacity)),
             SqlType::Int64 => W::wrap(VectorColumnData::<i64>::with_capacity(capacity)),
+            SqlType::Int128 => W::wrap(VectorColumnData::<i128>::with_capacity(capacity)),
+            SqlType::UInt128 => W::wrap(VectorColumnData::<u128>::with_capacity(capacity)),
+            SqlType::Int256 => W::wrap(VectorColumnData::<I256>::with_capacity(capacity)),
+            SqlType::UInt256 => W::wrap(VectorColumnData::<U256>::with_capacity(capacity)),
             SqlType::String => W::wrap(StringColumnData::with_capacity(capacity)),
             SqlType::FixedString(len) => {
                 W::wrap(FixedStringColumnData::with_capacity(capacity, len))
@@ -152,22 +202,46 @@ impl dyn ColumnData {
                 )?,
                 offsets: List::with_capacity(capacity)
             }),
-            SqlType::Decimal(precision, scale) => {
-                let nobits = NoBits::from_precision(precision).unwrap();
-
-                let inner_type = match nobits {
-                    NoBits::N32 => SqlType::Int32,
-                    NoBits::N64 => SqlType::Int64
+            SqlType::LowCardinality(inner_type) => {
+                let (key_type, nullable) = match inner_type {
+                    SqlType::Nullable(key_type) => (*key_type, true),
+                    _ => (inner_type, false)
                 };
-
-                W::wrap(DecimalColumnData {
-                    inner: <dyn ColumnData>::from_type::<BoxColumnWrapper>(
-                        inner_type, timezone, capacity
+                W::wrap(LowCardinalityColumnData::new(
+                    <dyn ColumnData>::from_type::<ArcColumnWrapper>(
+                        key_type.clone(),
+                        timezone,
+                        capacity
                     )?,
-                    precision,
-                    scale,
-                    nobits
-                })
+                    nullable
+                ))
+            }
+            SqlType::Tuple(inner_types) => {
+                let mut inner = Vec::with_capacity(inner_types.len());
+                for inner_type in inner_types {
+                    inner.push(<dyn ColumnData>::from_type::<ArcColumnWrapper>(
+                        inner_type.clone(),
+                        timezone,
+                        capacity
+                    )?);
+                }
+                W::wrap(TupleColumnData { inner })
+            }
+            SqlType::Map(key_type, value_type) => W::wrap(MapColumnData {
+                keys: <dyn ColumnData>::from_type::<ArcColumnWrapper>(
+                    key_type.clone(),
+                    timezone,
+                    capacity
+                )?,
+                values: <dyn ColumnData>::from_type::<ArcColumnWrapper>(
+                    value_type.clone(),
+                    timezone,
+                    capacity
+                )?,
+                offsets: List::with_capacity(capacity)
+            }),
+            SqlType::Decimal(precision, scale) => {
+                W::wrap(DecimalColumnData::with_capacity(precision, scale, capacity))
             }
             SqlType::Enum8(enum_values) => W::wrap(Enum8ColumnData {
                 enum_values,
@@ -189,24 +263,151 @@ impl dyn ColumnData {
     }
 }
 
-fn parse_fixed_string(source: &str) -> Option<usize> {
-    if !source.starts_with("FixedString") {
-        return None;
+/// Where the strings of a column being received were skipped to, see
+/// `ColumnData::skip_data`.
+#[derive(Default)]
+pub(crate) struct SkipProgress {
+    // The position of the first string of a sequence, with the number of strings skipped
+    // and the position after them.
+    strings: Vec<(usize, usize, usize)>
+}
+
+impl SkipProgress {
+    fn skip_strings(&mut self, reader: &mut Cursor<&[u8]>, size: usize) -> Option<bool> {
+        if size == 0 {
+            return Some(true);
+        }
+
+        let start = reader.position() as usize;
+        let index = match self.strings.iter().position(|&(s, _, _)| s == start) {
+            Some(index) => index,
+            None => {
+                self.strings.push((start, 0, start));
+                self.strings.len() - 1
+            }
+        };
+
+        let (_, mut skipped, position) = self.strings[index];
+        reader.set_position(position as u64);
+        while skipped < size {
+            let skipped_string = match reader.read_uvarint() {
+                Ok(len) => skip_bytes(reader, Some(len as usize)),
+                Err(_) => None
+            };
+            match skipped_string {
+                Some(true) => skipped += 1,
+                Some(false) => return Some(false),
+                None => return None
+            }
+            self.strings[index] = (start, skipped, reader.position() as usize);
+        }
+        Some(true)
     }
+}
 
-    let inner_size = &source[12..source.len() - 1];
-    match inner_size.parse::<usize>() {
-        Err(_) => None,
-        Ok(value) => Some(value)
+fn skip_composite_data(
+    reader: &mut Cursor<&[u8]>,
+    type_name: &str,
+    size: usize,
+    progress: &mut SkipProgress
+) -> Option<bool> {
+    // the nested data is skipped the same way, returns unless it's known
+    macro_rules! skip {
+        ($skipped:expr) => {
+            if !$skipped? {
+                return Some(false);
+            }
+        };
+    }
+
+    if let Some(inner_type) = parse_nullable_type(type_name) {
+        skip!(skip_bytes(reader, Some(size)));
+        <dyn ColumnData>::skip_data(reader, inner_type, size, progress)
+    } else if let Some(inner_type) = parse_low_cardinality_type(type_name) {
+        LowCardinalityColumnData::skip(reader, inner_type, size, progress)
+    } else if let Some(str_len) = parse_fixed_string(type_name) {
+        skip_bytes(reader, size.checked_mul(str_len))
+    } else if let Some(inner_type) = parse_array_type(type_name) {
+        let inner_size = skip_offsets(reader, size)?;
+        <dyn ColumnData>::skip_data(reader, inner_type, inner_size, progress)
+    } else if let Some(inner_types) = parse_tuple_type(type_name) {
+        for inner_type in inner_types {
+            skip!(<dyn ColumnData>::skip_data(
+                reader, inner_type, size, progress
+            ));
+        }
+        Some(true)
+    } else if let Some((key_type, value_type)) = parse_map_type(type_name) {
+        let inner_size = skip_offsets(reader, size)?;
+        skip!(<dyn ColumnData>::skip_data(
+            reader, key_type, inner_size, progress
+        ));
+        <dyn ColumnData>::skip_data(reader, value_type, inner_size, progress)
+    } else if let Some((_, _, nobits)) = parse_decimal(type_name) {
+        let inner_type = nobits.inner_type().to_string();
+        <dyn ColumnData>::skip_data(reader, &inner_type, size, progress)
+    } else if parse_enum8(type_name).is_some() {
+        skip_bytes(reader, Some(size))
+    } else if parse_enum16(type_name).is_some() {
+        skip_bytes(reader, size.checked_mul(2))
+    } else if parse_date_time64(type_name).is_some() {
+        skip_bytes(reader, size.checked_mul(8))
+    } else {
+        Some(false)
+    }
+}
+
+/// Counts the `LowCardinality` in `type_name`, nested ones included: the data of a column
+/// starts with the keys version of each of them, in the order of the type.
+pub(crate) fn low_cardinality_count(type_name: &str) -> usize {
+    if parse_low_cardinality_type(type_name).is_some() {
+        1
+    } else if let Some(inner_type) =
+        parse_nullable_type(type_name).or_else(|| parse_array_type(type_name))
+    {
+        low_cardinality_count(inner_type)
+    } else if let Some(inner_types) = parse_tuple_type(type_name) {
+        inner_types.into_iter().map(low_cardinality_count).sum()
+    } else if let Some((key_type, value_type)) = parse_map_type(type_name) {
+        low_cardinality_count(key_type) + low_cardinality_count(value_type)
+    } else {
+        0
     }
 }
 
-fn parse_nullable_type(source: &str) -> Option<&str> {
-    if !source.starts_with("Nullable") {
+/// Skips the offsets of an `Array` or a `Map`, returns the number of nested values.
+fn skip_offsets(reader: &mut Cursor<&[u8]>, size: usize) -> Option<usize> {
+    if size == 0 {
+        return Some(0);
+    }
+    // all but the last offset
+    let len = (size - 1).checked_mul(8)?;
+    let position = (reader.position() as usize).checked_add(len)?;
+    reader.set_position(position as u64);
+    let inner_size: u64 = reader.read_scalar().ok()?;
+    Some(inner_size as usize)
+}
+
+/// Skips `len` bytes, `len` is `None` when it overflowed: the size isn't known then.
+pub(crate) fn skip_bytes(reader: &mut Cursor<&[u8]>, len: Option<usize>) -> Option<bool> {
+    let end = match len.and_then(|len| (reader.position() as usize).checked_add(len)) {
+        Some(end) => end,
+        None => return Some(false)
+    };
+    if end > reader.get_ref().len() {
         return None;
     }
+    reader.set_position(end as u64);
+    Some(true)
+}
 
-    let inner_type = &source[9..source.len() - 1];
+fn parse_fixed_string(source: &str) -> Option<usize> {
+    let inner_size = source.strip_prefix("FixedString(")?.strip_suffix(')')?;
+    inner_size.parse::<usize>().ok()
+}
+
+pub(crate) fn parse_nullable_type(source: &str) -> Option<&str> {
+    let inner_type = source.strip_prefix("Nullable(")?.strip_suffix(')')?;
 
     if inner_type.starts_with("Nullable") {
         return None;
@@ -215,13 +416,83 @@ fn parse_nullable_type(source: &str) -> Option<&str> {
     Some(inner_type)
 }
 
+fn parse_low_cardinality_type(source: &str) -> Option<&str> {
+    source.strip_prefix("LowCardinality(")?.strip_suffix(')')
+}
+
 fn parse_array_type(source: &str) -> Option<&str> {
-    if !source.starts_with("Array") {
+    source.strip_prefix("Array(")?.strip_suffix(')')
+}
+
+fn parse_tuple_type(source: &str) -> Option<Vec<&str>> {
+    if !source.starts_with("Tuple(") || !source.ends_with(')') {
         return None;
     }
 
-    let inner_type = &source[6..source.len() - 1];
-    Some(inner_type)
+    let items = split_type_list(&source[6..source.len() - 1])?;
+    // named tuples, e.g. `Tuple(a UInt8, b String)`: the names don't matter on the wire
+    let inner_types = items
+        .into_iter()
+        .map(|item| match item.split_once(' ') {
+            Some((name, inner_type))
+                if !name.contains('(') && inner_type.starts_with(char::is_alphabetic) =>
+            {
+                inner_type
+            }
+            _ => item
+        })
+        .collect();
+    Some(inner_types)
+}
+
+fn parse_map_type(source: &str) -> Option<(&str, &str)> {
+    if !source.starts_with("Map(") || !source.ends_with(')') {
+        return None;
+    }
+
+    match split_type_list(&source[4..source.len() - 1])?.as_slice() {
+        [key_type, value_type] => Some((key_type, value_type)),
+        _ => None
+    }
+}
+
+/// Splits the comma separated types of a `Tuple` or a `Map`, commas inside the types
+/// themselves are skipped.
+fn split_type_list(source: &str) -> Option<Vec<&str>> {
+    let mut items = Vec::new();
+    let mut depth = 0_usize;
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+
+    for (idx, c) in source.char_indices() {
+        if quoted {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '\'' => quoted = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match c {
+            '\'' => quoted = true,
+            '(' => depth += 1,
+            ')' => depth = depth.checked_sub(1)?,
+            ',' if depth == 0 => {
+                items.push(source[start..idx].trim());
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+
+    items.push(source[start..].trim());
+    if depth != 0 || quoted || items.iter().any(|item| item.is_empty()) {
+        return None;
+    }
+    Some(items)
 }
 
 fn parse_decimal(source: &str) -> Option<(u8, u8, NoBits)> {
@@ -249,6 +520,12 @@ fn parse_decimal(source: &str) -> Option<(u8, u8, NoBits)> {
                 b"Decimal64" => {
                     nobits = Some(NoBits::N64);
                 }
+                b"Decimal128" => {
+                    nobits = Some(NoBits::N128);
+                }
+                b"Decimal256" => {
+                    nobits = Some(NoBits::N256);
+                }
                 _ => return None
             }
             params_indexes.0 = Some(idx);
@@ -295,7 +572,9 @@ fn parse_decimal(source: &str) -> Option<(u8, u8, NoBits)> {
         (None, Some(scale), Some(bits)) => {
             let precision = match bits {
                 NoBits::N32 => 9,
-                NoBits::N64 => 18
+                NoBits::N64 => 18,
+                NoBits::N128 => 38,
+                NoBits::N256 => 76
             };
             Some((precision, scale, bits))
         }
@@ -416,23 +695,109 @@ fn get_timezone(timezone: &Option<String>, tz: Tz) -> Result<Tz> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::binary::Encoder;
+
+    #[test]
+    fn test_skip_progress() {
+        let mut encoder = Encoder::new();
+        for i in 0..100 {
+            encoder.string(format!("value {}", i));
+        }
+        let mut bytes = encoder.get_buffer();
+        let half = bytes.len() / 2;
+
+        let mut progress = SkipProgress::default();
+        let mut reader = Cursor::new(&bytes[..half]);
+        assert_eq!(
+            <dyn ColumnData>::skip_data(&mut reader, "String", 100, &mut progress),
+            None
+        );
+        let (_, skipped, position) = progress.strings[0];
+        assert!(skipped > 0 && position <= half);
+
+        // the strings which were skipped aren't read again
+        for byte in &mut bytes[..position] {
+            *byte = 0xff;
+        }
+        let mut reader = Cursor::new(&bytes[..]);
+        assert_eq!(
+            <dyn ColumnData>::skip_data(&mut reader, "String", 100, &mut progress),
+            Some(true)
+        );
+        assert_eq!(reader.position() as usize, bytes.len());
+    }
 
     #[test]
     fn test_parse_decimal() {
         assert_eq!(parse_decimal("Decimal(9, 4)"), Some((9, 4, NoBits::N32)));
         assert_eq!(parse_decimal("Decimal(10, 4)"), Some((10, 4, NoBits::N64)));
-        assert_eq!(parse_decimal("Decimal(20, 4)"), None);
+        assert_eq!(parse_decimal("Decimal(20, 4)"), Some((20, 4, NoBits::N128)));
+        assert_eq!(parse_decimal("Decimal(40, 4)"), Some((40, 4, NoBits::N256)));
+        assert_eq!(parse_decimal("Decimal(77, 4)"), None);
         assert_eq!(parse_decimal("Decimal(2000, 4)"), None);
         assert_eq!(parse_decimal("Decimal(3, 4)"), None);
         assert_eq!(parse_decimal("Decimal(20, -4)"), None);
         assert_eq!(parse_decimal("Decimal(0)"), None);
         assert_eq!(parse_decimal("Decimal(1, 2, 3)"), None);
         assert_eq!(parse_decimal("Decimal64(9)"), Some((18, 9, NoBits::N64)));
+        assert_eq!(parse_decimal("Decimal128(9)"), Some((38, 9, NoBits::N128)));
+        assert_eq!(parse_decimal("Decimal256(9)"), Some((76, 9, NoBits::N256)));
     }
 
     #[test]
     fn test_parse_array_type() {
         assert_eq!(parse_array_type("Array(UInt8)"), Some("UInt8"));
+        assert_eq!(parse_array_type("Array"), None);
+        assert_eq!(parse_array_type("Array("), None);
+    }
+
+    #[test]
+    fn test_parse_low_cardinality_type() {
+        assert_eq!(
+            parse_low_cardinality_type("LowCardinality(Nullable(String))"),
+            Some("Nullable(String)")
+        );
+        assert_eq!(parse_low_cardinality_type("LowCardinality"), None);
+        assert_eq!(parse_low_cardinality_type("LowCardinality("), None);
+        assert_eq!(parse_low_cardinality_type("LowCardinalityX"), None);
+    }
+
+    #[test]
+    fn test_low_cardinality_count() {
+        assert_eq!(low_cardinality_count("String"), 0);
+        assert_eq!(low_cardinality_count("LowCardinality(Nullable(String))"), 1);
+        assert_eq!(low_cardinality_count("Array(LowCardinality(String))"), 1);
+        assert_eq!(
+            low_cardinality_count("Tuple(LowCardinality(String), UInt8, LowCardinality(String))"),
+            2
+        );
+        assert_eq!(
+            low_cardinality_count("Map(LowCardinality(String), Array(LowCardinality(String)))"),
+            2
+        );
+    }
+
+    #[test]
+    fn test_parse_tuple_type() {
+        assert_eq!(
+            parse_tuple_type("Tuple(UInt8, Decimal(9, 2), Enum8('a,)' = 1))"),
+            Some(vec!["UInt8", "Decimal(9, 2)", "Enum8('a,)' = 1)"])
+        );
+        assert_eq!(
+            parse_tuple_type("Tuple(a UInt8, b Nullable(String))"),
+            Some(vec!["UInt8", "Nullable(String)"])
+        );
+        assert_eq!(parse_tuple_type("Tuple(UInt8,)"), None);
+        assert_eq!(parse_tuple_type("UInt8"), None);
+    }
+
+    #[test]
+    fn test_parse_map_type() {
+        assert_eq!(
+            parse_map_type("Map(String, Array(UInt8))"),
+            Some(("String", "Array(UInt8)"))
+        );
+        assert_eq!(parse_map_type("Map(String)"), None);
     }
 
     #[test]
@@ -440,6 +805,8 @@ mod test {
         assert_eq!(parse_nullable_type("Nullable(Int8)"), Some("Int8"));
         assert_eq!(parse_nullable_type("Int8"), None);
         assert_eq!(parse_nullable_type("Nullable(Nullable(Int8))"), None);
+        assert_eq!(parse_nullable_type("Nullable"), None);
+        assert_eq!(parse_nullable_type("Nullable("), None);
     }
 
     #[test]
@@ -447,6 +814,8 @@ mod test {
         assert_eq!(parse_fixed_string("FixedString(8)"), Some(8_usize));
         assert_eq!(parse_fixed_string("FixedString(zz)"), None);
         assert_eq!(parse_fixed_string("Int8"), None);
+        assert_eq!(parse_fixed_string("FixedString"), None);
+        assert_eq!(parse_fixed_string("FixedString("), None);
     }
 
     #[test]
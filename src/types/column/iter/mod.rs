@@ -25,6 +25,7 @@ use crate::types::Complex;
 use crate::types::Decimal;
 use crate::types::Simple;
 use crate::types::SqlType;
+use crate::types::I256;
 
 macro_rules! simple_num_iterable {
     ( $($t:ty: $k:ident),* ) => {
@@ -264,16 +265,13 @@ impl FusedIterator for StringIterator<'_> {}
 
 impl<'a> DecimalIterator<'a> {
     #[inline(always)]
-    unsafe fn next_unchecked_<T>(&mut self) -> Decimal
-    where
-        T: Copy + Sized,
-        i64: From<T>
-    {
+    unsafe fn next_unchecked_<T>(&mut self, f: fn(T) -> I256) -> Decimal
+    where T: Copy + Sized {
         let current_value = *(self.ptr as *const T);
         self.ptr = (self.ptr as *const T).offset(1) as *const u8;
 
         Decimal {
-            underlying: current_value.into(),
+            underlying: f(current_value).into(),
             nobits: self.nobits,
             precision: self.precision,
             scale: self.scale
@@ -283,8 +281,10 @@ impl<'a> DecimalIterator<'a> {
     #[inline(always)]
     unsafe fn next_unchecked(&mut self) -> Decimal {
         match self.nobits {
-            NoBits::N32 => self.next_unchecked_::<i32>(),
-            NoBits::N64 => self.next_unchecked_::<i64>()
+            NoBits::N32 => self.next_unchecked_::<i32>(|v| I256::from(i128::from(v))),
+            NoBits::N64 => self.next_unchecked_::<i64>(|v| I256::from(i128::from(v))),
+            NoBits::N128 => self.next_unchecked_::<i128>(I256::from),
+            NoBits::N256 => self.next_unchecked_::<I256>(std::convert::identity)
         }
     }
 
@@ -293,7 +293,9 @@ impl<'a> DecimalIterator<'a> {
         unsafe {
             match self.nobits {
                 NoBits::N32 => self.ptr = (self.ptr as *const i32).add(n) as *const u8,
-                NoBits::N64 => self.ptr = (self.ptr as *const i64).add(n) as *const u8
+                NoBits::N64 => self.ptr = (self.ptr as *const i64).add(n) as *const u8,
+                NoBits::N128 => self.ptr = (self.ptr as *const i128).add(n) as *const u8,
+                NoBits::N256 => self.ptr = (self.ptr as *const I256).add(n) as *const u8
             }
         }
     }
@@ -304,7 +306,9 @@ impl<'a> ExactSizeIterator for DecimalIterator<'a> {
     fn len(&self) -> usize {
         let size = match self.nobits {
             NoBits::N32 => mem::size_of::<i32>(),
-            NoBits::N64 => mem::size_of::<i64>()
+            NoBits::N64 => mem::size_of::<i64>(),
+            NoBits::N128 => mem::size_of::<i128>(),
+            NoBits::N256 => mem::size_of::<I256>()
         };
         (self.end as usize - self.ptr as usize) / size
     }
@@ -735,7 +739,9 @@ impl<'a> Iterable<'a, Simple> for Decimal {
         let end = unsafe {
             match nobits {
                 NoBits::N32 => (ptr as *const u32).add(size) as *const u8,
-                NoBits::N64 => (ptr as *const u64).add(size) as *const u8
+                NoBits::N64 => (ptr as *const u64).add(size) as *const u8,
+                NoBits::N128 => (ptr as *const u128).add(size) as *const u8,
+                NoBits::N256 => (ptr as *const I256).add(size) as *const u8
             }
         };
 
@@ -0,0 +1,490 @@
+use std::collections::HashMap;
+use std::io::Cursor;
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+
+use crate::binary::Encoder;
+use crate::binary::ReadEx;
+use crate::errors::Result;
+use crate::types::column::column_data::ArcColumnData;
+use crate::types::column::column_data::BoxColumnData;
+use crate::types::column::factory::low_cardinality_count;
+use crate::types::column::factory::parse_nullable_type;
+use crate::types::column::factory::skip_bytes;
+use crate::types::column::factory::SkipProgress;
+use crate::types::column::ArcColumnWrapper;
+use crate::types::column::ColumnData;
+use crate::types::column::Either;
+use crate::types::SqlType;
+use crate::types::Value;
+use crate::types::ValueRef;
+
+// `SharedDictionariesWithAdditionalKeys`, the only version of the keys serialization.
+const KEYS_SERIALIZATION_VERSION: u64 = 1;
+
+const INDEX_TYPE_MASK: u64 = 0xff;
+const NEED_GLOBAL_DICTIONARY_BIT: u64 = 1 << 8;
+const HAS_ADDITIONAL_KEYS_BIT: u64 = 1 << 9;
+
+/// A `LowCardinality` column: the rows are indices into a dictionary of keys.
+///
+/// Only the keys sent along with each block (additional keys) are supported, which is
+/// what clients use in the native protocol.
+///
+/// The keys version isn't part of the data: it comes before the data of the whole
+/// column, also when the `LowCardinality` is nested in an `Array`, a `Tuple` or a `Map`,
+/// see `load_prefix`.
+pub(crate) struct LowCardinalityColumnData {
+    // For `LowCardinality(Nullable(T))` the dictionary has type `T` and its first key
+    // stands for NULL.
+    pub(crate) dictionary: ArcColumnData,
+    pub(crate) indices: Vec<u64>,
+    pub(crate) nullable: bool
+}
+
+impl LowCardinalityColumnData {
+    pub(crate) fn load<R: ReadEx>(
+        reader: &mut R,
+        type_name: &str,
+        size: usize,
+        tz: Tz
+    ) -> Result<Self> {
+        let (nullable, key_type) = match parse_nullable_type(type_name) {
+            Some(inner_type) => (true, inner_type),
+            None => (false, type_name)
+        };
+
+        // Nothing is sent for no rows, e.g. empty arrays.
+        if size == 0 {
+            let dictionary =
+                <dyn ColumnData>::load_data::<ArcColumnWrapper, _>(reader, key_type, 0, tz)?;
+            return Ok(Self {
+                dictionary,
+                indices: Vec::new(),
+                nullable
+            });
+        }
+
+        let index_type: u64 = reader.read_scalar()?;
+        if index_type & NEED_GLOBAL_DICTIONARY_BIT != 0 {
+            return Err("LowCardinality global dictionaries are not supported.".into());
+        }
+        if index_type & HAS_ADDITIONAL_KEYS_BIT == 0 {
+            return Err("LowCardinality column has no keys.".into());
+        }
+
+        let num_keys: u64 = reader.read_scalar()?;
+        let dictionary = <dyn ColumnData>::load_data::<ArcColumnWrapper, _>(
+            reader,
+            key_type,
+            num_keys as usize,
+            tz
+        )?;
+
+        let num_indices: u64 = reader.read_scalar()?;
+        if num_indices as usize != size {
+            let message = format!(
+                "LowCardinality column has {} indices, expected {}.",
+                num_indices, size
+            );
+            return Err(message.into());
+        }
+
+        let width = index_width(index_type & INDEX_TYPE_MASK)?;
+        let mut buffer = vec![0_u8; width * size];
+        reader.read_bytes(&mut buffer)?;
+
+        let indices: Vec<u64> = buffer
+            .chunks(width)
+            .map(|chunk| {
+                let mut index = [0_u8; 8];
+                index[..width].copy_from_slice(chunk);
+                u64::from_le_bytes(index)
+            })
+            .collect();
+
+        if indices.iter().any(|&index| index >= num_keys) {
+            return Err("LowCardinality index is out of the dictionary.".into());
+        }
+
+        Ok(Self {
+            dictionary,
+            indices,
+            nullable
+        })
+    }
+
+    /// Skips the data which `load` reads, see `ColumnData::skip_data`.
+    pub(crate) fn skip(
+        reader: &mut Cursor<&[u8]>,
+        type_name: &str,
+        size: usize,
+        progress: &mut SkipProgress
+    ) -> Option<bool> {
+        let key_type = parse_nullable_type(type_name).unwrap_or(type_name);
+        if size == 0 {
+            return <dyn ColumnData>::skip_data(reader, key_type, 0, progress);
+        }
+
+        let index_type: u64 = reader.read_scalar().ok()?;
+        let num_keys: u64 = reader.read_scalar().ok()?;
+        if !<dyn ColumnData>::skip_data(reader, key_type, num_keys as usize, progress)? {
+            return Some(false);
+        }
+
+        let num_indices: u64 = reader.read_scalar().ok()?;
+        let width = match index_width(index_type & INDEX_TYPE_MASK) {
+            Ok(width) => width,
+            Err(_) => return Some(false)
+        };
+        let len = (num_indices as usize).checked_mul(width);
+        skip_bytes(reader, len)
+    }
+
+    /// Reads the prefix of the data of a column of `type_name`: the keys version of each
+    /// `LowCardinality` in it, nested ones included. It isn't sent when there are no rows.
+    pub(crate) fn load_prefix<R: ReadEx>(reader: &mut R, type_name: &str) -> Result<()> {
+        for _ in 0..low_cardinality_count(type_name) {
+            let version: u64 = reader.read_scalar()?;
+            if version != KEYS_SERIALIZATION_VERSION {
+                let message = format!("Invalid version for LowCardinality keys: {}.", version);
+                return Err(message.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Skips the prefix which `load_prefix` reads.
+    pub(crate) fn skip_prefix(reader: &mut Cursor<&[u8]>, type_name: &str) -> Option<bool> {
+        skip_bytes(reader, low_cardinality_count(type_name).checked_mul(8))
+    }
+
+    /// Writes the prefix which `load_prefix` reads.
+    pub(crate) fn save_prefix(encoder: &mut Encoder, type_name: &str) {
+        for _ in 0..low_cardinality_count(type_name) {
+            encoder.write(KEYS_SERIALIZATION_VERSION);
+        }
+    }
+
+    /// Creates an empty column, `dictionary` must be empty too.
+    pub(crate) fn new(dictionary: ArcColumnData, nullable: bool) -> Self {
+        let mut column = Self {
+            dictionary,
+            indices: Vec::new(),
+            nullable
+        };
+
+        if nullable {
+            let default_value = Value::default(column.dictionary.sql_type());
+            column.push_key(default_value);
+        }
+        column
+    }
+
+    fn push_key(&mut self, value: Value) -> u64 {
+        let dictionary: &mut dyn ColumnData = Arc::get_mut(&mut self.dictionary).unwrap();
+        dictionary.push(value);
+        (dictionary.len() - 1) as u64
+    }
+
+    fn encode_key(&self, index: usize) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        self.dictionary.save(&mut encoder, index, index + 1);
+        encoder.get_buffer()
+    }
+}
+
+fn index_width(index_type: u64) -> Result<usize> {
+    match index_type {
+        0 => Ok(1),
+        1 => Ok(2),
+        2 => Ok(4),
+        3 => Ok(8),
+        _ => {
+            let message = format!("Invalid type for LowCardinality index: {}.", index_type);
+            Err(message.into())
+        }
+    }
+}
+
+impl ColumnData for LowCardinalityColumnData {
+    fn sql_type(&self) -> SqlType {
+        let key_type = self.dictionary.sql_type();
+        if self.nullable {
+            SqlType::LowCardinality(SqlType::Nullable(key_type.into()).into())
+        } else {
+            SqlType::LowCardinality(key_type.into())
+        }
+    }
+
+    fn save(&self, encoder: &mut Encoder, start: usize, end: usize) {
+        if start == end {
+            return;
+        }
+
+        // Only the keys used by the rows are sent, each distinct value once.
+        let mut keys = Encoder::new();
+        let mut positions: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut indices = Vec::with_capacity(end - start);
+
+        if self.nullable {
+            keys.write_bytes(&self.encode_key(0));
+        }
+        let mut num_keys = self.nullable as u64;
+
+        for &index in &self.indices[start..end] {
+            if self.nullable && index == 0 {
+                indices.push(0);
+                continue;
+            }
+
+            let key = self.encode_key(index as usize);
+            let position = *positions.entry(key).or_insert_with_key(|key| {
+                keys.write_bytes(key);
+                num_keys += 1;
+                num_keys - 1
+            });
+            indices.push(position);
+        }
+
+        let index_type = match num_keys {
+            0..=0xff => 0,
+            0x100..=0xffff => 1,
+            0x1_0000..=0xffff_ffff => 2,
+            _ => 3
+        };
+
+        encoder.write(index_type | HAS_ADDITIONAL_KEYS_BIT);
+        encoder.write(num_keys);
+        encoder.write_bytes(keys.get_buffer_ref());
+
+        encoder.write(indices.len() as u64);
+        for index in indices {
+            match index_type {
+                0 => encoder.write(index as u8),
+                1 => encoder.write(index as u16),
+                2 => encoder.write(index as u32),
+                _ => encoder.write(index)
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    fn push(&mut self, value: Value) {
+        let index = match value {
+            Value::Nullable(Either::Left(_)) if self.nullable => 0,
+            Value::Nullable(Either::Right(inner)) if self.nullable => self.push_key(*inner),
+            value => self.push_key(value)
+        };
+        self.indices.push(index);
+    }
+
+    fn at(&self, index: usize) -> ValueRef<'_> {
+        let key = self.indices[index] as usize;
+        if !self.nullable {
+            return self.dictionary.at(key);
+        }
+
+        if key == 0 {
+            let sql_type = self.dictionary.sql_type();
+            ValueRef::Nullable(Either::Left(sql_type.into()))
+        } else {
+            let inner_value = self.dictionary.at(key);
+            ValueRef::Nullable(Either::Right(Box::new(inner_value)))
+        }
+    }
+
+    fn clone_instance(&self) -> BoxColumnData {
+        Box::new(Self {
+            dictionary: self.dictionary.clone(),
+            indices: self.indices.clone(),
+            nullable: self.nullable
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::types::block::BlockInfo;
+    use crate::types::Block;
+
+    fn write_and_load(block: &Block) -> Block {
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
+        let mut reader = Cursor::new(encoder.get_buffer());
+        Block::load(&mut reader, Tz::UTC, false, 0).unwrap()
+    }
+
+    /// Loads a column of `type_name` from the `data` which ClickHouse sends for it and
+    /// checks that it's written back the same.
+    fn load_and_write(type_name: &str, rows: u64, data: &[u8]) -> Block {
+        let mut encoder = Encoder::new();
+        BlockInfo::default().write(&mut encoder);
+        encoder.uvarint(1);
+        encoder.uvarint(rows);
+        encoder.string("c");
+        encoder.string(type_name);
+        encoder.write_bytes(data);
+        let bytes = encoder.get_buffer();
+
+        let mut reader = Cursor::new(&bytes[..]);
+        let block = Block::load(&mut reader, Tz::UTC, false, 0).unwrap();
+        assert_eq!(reader.position() as usize, bytes.len(), "{}", type_name);
+
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
+        assert_eq!(encoder.get_buffer(), bytes, "{}", type_name);
+        block
+    }
+
+    /// The data of a `LowCardinality(String)` with the keys `keys`, without the prefix.
+    fn string_keys(keys: &[&str], indices: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&HAS_ADDITIONAL_KEYS_BIT.to_le_bytes());
+        data.extend_from_slice(&(keys.len() as u64).to_le_bytes());
+        for key in keys {
+            data.push(key.len() as u8);
+            data.extend_from_slice(key.as_bytes());
+        }
+        data.extend_from_slice(&(indices.len() as u64).to_le_bytes());
+        data.extend_from_slice(indices);
+        data
+    }
+
+    #[test]
+    fn test_save() {
+        let block = Block::new()
+            .column("s", vec!["a", "b", "a", "a"])
+            .low_cardinality("s")
+            .unwrap();
+
+        let mut encoder = Encoder::new();
+        let column = &block.columns()[0];
+        column.data.save(&mut encoder, 0, column.len());
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&HAS_ADDITIONAL_KEYS_BIT.to_le_bytes());
+        expected.extend_from_slice(&2_u64.to_le_bytes());
+        expected.extend_from_slice(&[1, b'a', 1, b'b']);
+        expected.extend_from_slice(&4_u64.to_le_bytes());
+        expected.extend_from_slice(&[0, 1, 0, 0]);
+
+        assert_eq!(encoder.get_buffer(), expected);
+    }
+
+    #[test]
+    fn test_write_and_load() {
+        let block = Block::new()
+            .column("s", vec!["foo", "bar", "foo"])
+            .column("n", vec![Some("x"), None, Some("y")])
+            .low_cardinality("s")
+            .unwrap()
+            .low_cardinality("n")
+            .unwrap();
+
+        let actual = write_and_load(&block);
+
+        assert_eq!(
+            actual.columns()[0].sql_type(),
+            SqlType::LowCardinality(SqlType::String.into())
+        );
+        assert_eq!(
+            actual.columns()[1].sql_type(),
+            SqlType::LowCardinality(SqlType::Nullable(SqlType::String.into()).into())
+        );
+        assert_eq!(actual, block);
+        assert_eq!(actual.get::<String, _>(2, "s").unwrap(), "foo");
+        assert_eq!(actual.get::<Option<String>, _>(1, "n").unwrap(), None);
+    }
+
+    #[test]
+    fn test_load_nested() {
+        let version = KEYS_SERIALIZATION_VERSION.to_le_bytes();
+
+        // the prefix comes before the offsets, the empty array has no data
+        let mut data = version.to_vec();
+        for offset in &[2_u64, 2, 3] {
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        data.extend_from_slice(&string_keys(&["x", "y"], &[0, 1, 0]));
+        let block = load_and_write("Array(LowCardinality(String))", 3, &data);
+        assert_eq!(block.get::<Vec<String>, _>(0, "c").unwrap(), vec!["x", "y"]);
+        assert_eq!(
+            block.get::<Vec<String>, _>(1, "c").unwrap(),
+            Vec::<String>::new()
+        );
+        assert_eq!(block.get::<Vec<String>, _>(2, "c").unwrap(), vec!["x"]);
+
+        let mut data = version.to_vec();
+        data.extend_from_slice(&0_u64.to_le_bytes());
+        let block = load_and_write("Array(LowCardinality(String))", 1, &data);
+        assert_eq!(
+            block.get::<Vec<String>, _>(0, "c").unwrap(),
+            Vec::<String>::new()
+        );
+
+        // the prefixes of the items come before the data of the first item
+        let mut data = version.to_vec();
+        data.extend_from_slice(&version);
+        data.extend_from_slice(&string_keys(&["a", "b"], &[0, 1]));
+        data.extend_from_slice(&[7, 8]);
+        data.extend_from_slice(&string_keys(&["c"], &[0, 0]));
+        let block = load_and_write(
+            "Tuple(LowCardinality(String), UInt8, LowCardinality(String))",
+            2,
+            &data
+        );
+        assert_eq!(
+            block.get::<(String, u8, String), _>(1, "c").unwrap(),
+            ("b".to_string(), 8, "c".to_string())
+        );
+
+        let mut data = version.to_vec();
+        data.extend_from_slice(&version);
+        data.extend_from_slice(&2_u64.to_le_bytes());
+        data.extend_from_slice(&string_keys(&["k", "l"], &[0, 1]));
+        data.extend_from_slice(&string_keys(&["v"], &[0, 0]));
+        let block = load_and_write(
+            "Map(LowCardinality(String), LowCardinality(String))",
+            1,
+            &data
+        );
+        let map = block.get::<HashMap<String, String>, _>(0, "c").unwrap();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["l"], "v");
+    }
+
+    #[test]
+    fn test_load_empty() {
+        let block = Block::new()
+            .column("s", Vec::<String>::new())
+            .low_cardinality("s")
+            .unwrap();
+
+        let actual = write_and_load(&block);
+        assert_eq!(actual.row_count(), 0);
+        assert_eq!(
+            actual.columns()[0].sql_type(),
+            SqlType::LowCardinality(SqlType::String.into())
+        );
+    }
+
+    #[test]
+    fn test_remove_low_cardinality() {
+        let block = Block::new()
+            .column("s", vec!["foo", "bar"])
+            .low_cardinality("s")
+            .unwrap();
+
+        let actual = block.remove_low_cardinality().unwrap();
+        assert_eq!(actual.columns()[0].sql_type(), SqlType::String);
+        assert_eq!(actual.get::<String, _>(1, "s").unwrap(), "bar");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::convert;
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+
+use crate::binary::Encoder;
+use crate::binary::ReadEx;
+use crate::errors::Result;
+use crate::types::column::column_data::ArcColumnData;
+use crate::types::column::column_data::BoxColumnData;
+use crate::types::column::list::List;
+use crate::types::column::ArcColumnWrapper;
+use crate::types::column::ColumnData;
+use crate::types::column::ColumnFrom;
+use crate::types::column::ColumnWrapper;
+use crate::types::HasSqlType;
+use crate::types::SqlType;
+use crate::types::Value;
+use crate::types::ValueRef;
+
+/// A `Map` column, sent the same way as `Array(Tuple(K, V))`: the offsets, then the
+/// keys and the values of all the rows.
+pub(crate) struct MapColumnData {
+    pub(crate) keys: ArcColumnData,
+    pub(crate) values: ArcColumnData,
+    pub(crate) offsets: List<u64>
+}
+
+impl MapColumnData {
+    pub(crate) fn load<R: ReadEx>(
+        reader: &mut R,
+        key_type: &str,
+        value_type: &str,
+        rows: usize,
+        tz: Tz
+    ) -> Result<Self> {
+        let mut offsets = List::with_capacity(rows);
+        offsets.resize(rows, 0_u64);
+        reader.read_bytes(offsets.as_mut())?;
+
+        let size = match rows {
+            0 => 0,
+            _ => offsets.at(rows - 1) as usize
+        };
+        let keys = <dyn ColumnData>::load_data::<ArcColumnWrapper, _>(reader, key_type, size, tz)?;
+        let values =
+            <dyn ColumnData>::load_data::<ArcColumnWrapper, _>(reader, value_type, size, tz)?;
+
+        Ok(Self {
+            keys,
+            values,
+            offsets
+        })
+    }
+
+    fn with_capacity<K: HasSqlType, V: HasSqlType>(capacity: usize) -> Self {
+        let keys =
+            <dyn ColumnData>::from_type::<ArcColumnWrapper>(K::get_sql_type(), Tz::UTC, capacity);
+        let values =
+            <dyn ColumnData>::from_type::<ArcColumnWrapper>(V::get_sql_type(), Tz::UTC, capacity);
+
+        Self {
+            keys: keys.unwrap(),
+            values: values.unwrap(),
+            offsets: List::with_capacity(capacity)
+        }
+    }
+
+    fn offset(&self, index: usize) -> usize {
+        match index {
+            0 => 0,
+            _ => self.offsets.at(index - 1) as usize
+        }
+    }
+}
+
+impl<K, V, S> ColumnFrom for Vec<HashMap<K, V, S>>
+where
+    Value: convert::From<K> + convert::From<V>,
+    K: HasSqlType,
+    V: HasSqlType
+{
+    fn column_from<W: ColumnWrapper>(source: Self) -> W::Wrapper {
+        let mut data = MapColumnData::with_capacity::<K, V>(source.len());
+        for value in source {
+            data.push(value.into());
+        }
+        W::wrap(data)
+    }
+}
+
+impl<K, V> ColumnFrom for Vec<BTreeMap<K, V>>
+where
+    Value: convert::From<K> + convert::From<V>,
+    K: HasSqlType,
+    V: HasSqlType
+{
+    fn column_from<W: ColumnWrapper>(source: Self) -> W::Wrapper {
+        let mut data = MapColumnData::with_capacity::<K, V>(source.len());
+        for value in source {
+            data.push(value.into());
+        }
+        W::wrap(data)
+    }
+}
+
+impl ColumnData for MapColumnData {
+    fn sql_type(&self) -> SqlType {
+        SqlType::Map(self.keys.sql_type().into(), self.values.sql_type().into())
+    }
+
+    fn save(&self, encoder: &mut Encoder, start: usize, end: usize) {
+        let first = self.offset(start);
+        let last = self.offset(end);
+
+        for i in start..end {
+            encoder.write(self.offsets.at(i) - first as u64);
+        }
+
+        self.keys.save(encoder, first, last);
+        self.values.save(encoder, first, last);
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    fn push(&mut self, value: Value) {
+        if let Value::Map(_, _, vs) = value {
+            let prev = self.offset(self.offsets.len());
+            self.offsets.push((prev + vs.len()) as u64);
+
+            let keys = Arc::get_mut(&mut self.keys).unwrap();
+            let values = Arc::get_mut(&mut self.values).unwrap();
+            for (k, v) in vs.iter() {
+                keys.push(k.clone());
+                values.push(v.clone());
+            }
+        } else {
+            panic!("value should be a map")
+        }
+    }
+
+    fn at(&self, index: usize) -> ValueRef<'_> {
+        let start = self.offset(index);
+        let end = self.offset(index + 1);
+
+        let vs = (start..end)
+            .map(|i| (self.keys.at(i), self.values.at(i)))
+            .collect();
+        ValueRef::Map(
+            self.keys.sql_type().into(),
+            self.values.sql_type().into(),
+            Arc::new(vs)
+        )
+    }
+
+    fn clone_instance(&self) -> BoxColumnData {
+        Box::new(Self {
+            keys: self.keys.clone(),
+            values: self.values.clone(),
+            offsets: self.offsets.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+    use std::iter::FromIterator;
+
+    use super::*;
+    use crate::types::Block;
+
+    fn map<M>(items: &[(&'static str, u32)]) -> M
+    where M: FromIterator<(&'static str, u32)> {
+        items.iter().cloned().collect()
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let block = Block::new().column("m", vec![
+            map::<HashMap<_, _>>(&[("a", 1), ("b", 2)]),
+            map(&[]),
+            map(&[("c", 3)]),
+        ]);
+
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
+
+        let mut reader = Cursor::new(encoder.get_buffer_ref());
+        let rblock = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
+
+        assert_eq!(
+            rblock.columns()[0].sql_type(),
+            SqlType::Map(&SqlType::String, &SqlType::UInt32)
+        );
+        assert_eq!(block, rblock);
+
+        let first: BTreeMap<String, u32> = rblock.get(0, "m").unwrap();
+        assert_eq!(first.into_iter().collect::<Vec<_>>(), vec![
+            ("a".to_string(), 1),
+            ("b".to_string(), 2)
+        ]);
+        let second: HashMap<String, u32> = rblock.get(1, "m").unwrap();
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn test_save_range() {
+        let block = Block::new().column("m", vec![
+            map::<BTreeMap<_, _>>(&[("a", 1)]),
+            map(&[("b", 2), ("c", 3)]),
+            map(&[("d", 4)]),
+        ]);
+
+        let mut encoder = Encoder::new();
+        block.columns()[0].data.save(&mut encoder, 1, 2);
+
+        let mut expected = Vec::new();
+        expected.extend_from_slice(&2_u64.to_le_bytes());
+        expected.extend_from_slice(&[1, b'b', 1, b'c']);
+        expected.extend_from_slice(&2_u32.to_le_bytes());
+        expected.extend_from_slice(&3_u32.to_le_bytes());
+        assert_eq!(encoder.get_buffer(), expected);
+    }
+}
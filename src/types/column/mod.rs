@@ -10,6 +10,7 @@ use chrono_tz::Tz;
 use self::chunk::ChunkColumnData;
 pub(crate) use self::column_data::ColumnData;
 pub use self::concat::ConcatColumnData;
+pub(crate) use self::low_cardinality::LowCardinalityColumnData;
 pub use self::numeric::VectorColumnData;
 pub(crate) use self::string_pool::StringPool;
 use crate::binary::Encoder;
@@ -17,7 +18,9 @@ use crate::binary::ReadEx;
 use crate::errors::Error;
 use crate::errors::FromSqlError;
 use crate::errors::Result;
+use crate::protocols::DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION;
 use crate::types::column::column_data::ArcColumnData;
+use crate::types::column::decimal::cast_decimal;
 use crate::types::column::decimal::DecimalAdapter;
 use crate::types::column::decimal::NullableDecimalAdapter;
 use crate::types::column::enums::Enum16Adapter;
@@ -45,15 +48,18 @@ mod date;
 pub(crate) mod datetime64;
 mod decimal;
 mod enums;
-mod factory;
+pub(crate) mod factory;
 pub(crate) mod fixed_string;
 mod ip;
 pub(crate) mod iter;
 mod list;
+mod low_cardinality;
+mod map;
 mod nullable;
 mod numeric;
 mod string;
 mod string_pool;
+mod tuple;
 
 /// Represents Clickhouse Column
 pub struct Column<K: ColumnType> {
@@ -192,9 +198,25 @@ impl<K: ColumnType> Column<K> {
 }
 
 impl<K: ColumnType> Column<K> {
-    pub(crate) fn read<R: ReadEx>(reader: &mut R, size: usize, tz: Tz) -> Result<Column<K>> {
+    pub(crate) fn read<R: ReadEx>(
+        reader: &mut R,
+        size: usize,
+        tz: Tz,
+        revision: u64
+    ) -> Result<Column<K>> {
         let name = reader.read_string()?;
         let type_name = reader.read_string()?;
+        if revision >= DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION {
+            let has_custom: u8 = reader.read_scalar()?;
+            if has_custom != 0 {
+                return Err(
+                    format!("custom serialization of column {} is not supported", name).into()
+                );
+            }
+        }
+        if size > 0 {
+            LowCardinalityColumnData::load_prefix(reader, &type_name)?;
+        }
         let data =
             <dyn ColumnData>::load_data::<ArcColumnWrapper, _>(reader, &type_name, size, tz)?;
         let column = Self {
@@ -220,10 +242,18 @@ impl<K: ColumnType> Column<K> {
         self.data.at(index)
     }
 
-    pub(crate) fn write(&self, encoder: &mut Encoder) {
+    pub(crate) fn write(&self, encoder: &mut Encoder, revision: u64) {
+        let type_name = self.data.sql_type().to_string();
         encoder.string(&self.name);
-        encoder.string(self.data.sql_type().to_string().as_ref());
+        encoder.string(type_name.as_ref());
+        if revision >= DBMS_MIN_REVISION_WITH_CUSTOM_SERIALIZATION {
+            // no custom serialization
+            encoder.write(0_u8);
+        }
         let len = self.data.len();
+        if len > 0 {
+            LowCardinalityColumnData::save_prefix(encoder, &type_name);
+        }
         self.data.save(encoder, 0, len);
     }
 
@@ -290,6 +320,12 @@ impl<K: ColumnType> Column<K> {
                 string_column.cast_to(SqlType::FixedString(n))
             }
             (SqlType::Decimal(dst_p, dst_s), SqlType::Decimal(_, _)) => {
+                // the adapter rescales the values, they have to fit in the new type
+                for i in 0..self.len() {
+                    if let ValueRef::Decimal(decimal) = self.at(i) {
+                        cast_decimal(decimal, dst_p, dst_s)?;
+                    }
+                }
                 let name = self.name().to_owned();
                 let nobits = NoBits::from_precision(dst_p).unwrap();
                 let adapter = DecimalAdapter {
@@ -364,6 +400,13 @@ impl<K: ColumnType> Column<K> {
                 SqlType::Nullable(SqlType::Decimal(dst_p, dst_s)),
                 SqlType::Nullable(SqlType::Decimal(_, _))
             ) => {
+                for i in 0..self.len() {
+                    if let ValueRef::Nullable(Either::Right(value)) = self.at(i) {
+                        if let ValueRef::Decimal(decimal) = *value {
+                            cast_decimal(decimal, *dst_p, *dst_s)?;
+                        }
+                    }
+                }
                 let name = self.name().to_owned();
                 let nobits = NoBits::from_precision(*dst_p).unwrap();
                 let adapter = NullableDecimalAdapter {
@@ -425,6 +468,12 @@ impl<K: ColumnType> Column<K> {
                     _marker: marker::PhantomData
                 })
             }
+            (SqlType::LowCardinality(inner_type), _) if *inner_type == src_type => {
+                self.copy_to(dst_type)
+            }
+            (_, SqlType::LowCardinality(inner_type)) if *inner_type == dst_type => {
+                self.copy_to(dst_type)
+            }
             _ => {
                 if let Some(data) = self.data.cast_to(&self.data, &dst_type) {
                     let name = self.name().to_owned();
@@ -443,6 +492,24 @@ impl<K: ColumnType> Column<K> {
         }
     }
 
+    // Creates a column of the given type with the values of this column.
+    fn copy_to(&self, dst_type: SqlType) -> Result<Self> {
+        let mut data =
+            <dyn ColumnData>::from_type::<ArcColumnWrapper>(dst_type, Tz::UTC, self.len())?;
+        {
+            let data: &mut dyn ColumnData = Arc::get_mut(&mut data).unwrap();
+            for i in 0..self.len() {
+                data.push(self.at(i).into());
+            }
+        }
+
+        Ok(Column {
+            name: self.name.clone(),
+            data,
+            _marker: marker::PhantomData
+        })
+    }
+
     pub(crate) fn push(&mut self, value: Value) {
         loop {
             match Arc::get_mut(&mut self.data) {
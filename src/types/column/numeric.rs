@@ -208,6 +208,10 @@ where T: StatBuffer
             Value::Int16(x) => ValueRef::Int16(x),
             Value::Int32(x) => ValueRef::Int32(x),
             Value::Int64(x) => ValueRef::Int64(x),
+            Value::Int128(x) => ValueRef::Int128(x),
+            Value::UInt128(x) => ValueRef::UInt128(x),
+            Value::Int256(x) => ValueRef::Int256(x),
+            Value::UInt256(x) => ValueRef::UInt256(x),
 
             Value::Float32(x) => ValueRef::Float32(x),
             Value::Float64(x) => ValueRef::Float64(x),
@@ -0,0 +1,176 @@
+use std::convert;
+use std::sync::Arc;
+
+use chrono_tz::Tz;
+
+use crate::binary::Encoder;
+use crate::binary::ReadEx;
+use crate::errors::Result;
+use crate::types::column::column_data::ArcColumnData;
+use crate::types::column::column_data::BoxColumnData;
+use crate::types::column::ArcColumnWrapper;
+use crate::types::column::ColumnData;
+use crate::types::column::ColumnFrom;
+use crate::types::column::ColumnWrapper;
+use crate::types::HasSqlType;
+use crate::types::SqlType;
+use crate::types::Value;
+use crate::types::ValueRef;
+
+/// A `Tuple` column, its items are sent one column after another.
+pub(crate) struct TupleColumnData {
+    pub(crate) inner: Vec<ArcColumnData>
+}
+
+impl TupleColumnData {
+    pub(crate) fn load<R: ReadEx>(
+        reader: &mut R,
+        type_names: &[&str],
+        size: usize,
+        tz: Tz
+    ) -> Result<Self> {
+        let mut inner = Vec::with_capacity(type_names.len());
+        for type_name in type_names {
+            inner.push(<dyn ColumnData>::load_data::<ArcColumnWrapper, _>(
+                reader, type_name, size, tz
+            )?);
+        }
+        Ok(Self { inner })
+    }
+}
+
+macro_rules! column_from_tuple {
+    ( $( ( $( $t:ident ),* ) ),* ) => {
+        $(
+            impl<$( $t ),*> ColumnFrom for Vec<($( $t, )*)>
+            where
+                $( Value: convert::From<$t>, $t: HasSqlType ),*
+            {
+                fn column_from<W: ColumnWrapper>(source: Self) -> W::Wrapper {
+                    let inner = vec![$(
+                        <dyn ColumnData>::from_type::<ArcColumnWrapper>(
+                            $t::get_sql_type(),
+                            Tz::UTC,
+                            source.len()
+                        )
+                        .unwrap()
+                    ),*];
+
+                    let mut data = TupleColumnData { inner };
+                    for value in source {
+                        data.push(value.into());
+                    }
+                    W::wrap(data)
+                }
+            }
+        )*
+    };
+}
+
+column_from_tuple! {
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+}
+
+impl ColumnData for TupleColumnData {
+    fn sql_type(&self) -> SqlType {
+        let inner_types = self
+            .inner
+            .iter()
+            .map(|column| column.sql_type().into())
+            .collect();
+        SqlType::Tuple(inner_types)
+    }
+
+    fn save(&self, encoder: &mut Encoder, start: usize, end: usize) {
+        for column in &self.inner {
+            column.save(encoder, start, end);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.inner[0].len()
+    }
+
+    fn push(&mut self, value: Value) {
+        if let Value::Tuple(vs) = value {
+            if vs.len() != self.inner.len() {
+                panic!(
+                    "tuple should have {} items, not {}",
+                    self.inner.len(),
+                    vs.len()
+                );
+            }
+
+            for (column, v) in self.inner.iter_mut().zip(vs.iter()) {
+                Arc::get_mut(column).unwrap().push(v.clone());
+            }
+        } else {
+            panic!("value should be a tuple")
+        }
+    }
+
+    fn at(&self, index: usize) -> ValueRef<'_> {
+        let vs = self.inner.iter().map(|column| column.at(index)).collect();
+        ValueRef::Tuple(Arc::new(vs))
+    }
+
+    fn clone_instance(&self) -> BoxColumnData {
+        Box::new(Self {
+            inner: self.inner.clone()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::types::block::BlockInfo;
+    use crate::types::Block;
+
+    #[test]
+    fn test_write_and_read() {
+        let block = Block::new().column("t", vec![(1_u8, "foo"), (2, "bar"), (3, "baz")]);
+
+        let mut encoder = Encoder::new();
+        block.write(&mut encoder, None, 0).unwrap();
+
+        let mut reader = Cursor::new(encoder.get_buffer_ref());
+        let rblock = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
+
+        assert_eq!(
+            rblock.columns()[0].sql_type(),
+            SqlType::Tuple(vec![&SqlType::UInt8, &SqlType::String])
+        );
+        assert_eq!(block, rblock);
+        assert_eq!(
+            rblock.get::<(u8, String), _>(1, "t").unwrap(),
+            (2, "bar".to_string())
+        );
+    }
+
+    #[test]
+    fn test_load_named_tuple() {
+        let mut encoder = Encoder::new();
+        BlockInfo::default().write(&mut encoder);
+        encoder.uvarint(1);
+        encoder.uvarint(1);
+        encoder.string("t");
+        encoder.string("Tuple(a UInt8, b Nullable(String))");
+        encoder.write(7_u8);
+        encoder.write(0_u8);
+        encoder.string("x");
+
+        let mut reader = Cursor::new(encoder.get_buffer_ref());
+        let block = Block::load(&mut reader, Tz::Zulu, false, 0).unwrap();
+
+        let (a, b) = block.get::<(u8, Option<&str>), _>(0, "t").unwrap();
+        assert_eq!(a, 7);
+        assert_eq!(b, Some("x"));
+    }
+}
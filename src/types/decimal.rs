@@ -1,55 +1,92 @@
 use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::fmt;
 
-static FACTORS10: &[i64] = &[
-    1,
-    10,
-    100,
-    1000,
-    10000,
-    100_000,
-    1_000_000,
-    10_000_000,
-    100_000_000,
-    1_000_000_000,
-    10_000_000_000,
-    100_000_000_000,
-    1_000_000_000_000,
-    10_000_000_000_000,
-    100_000_000_000_000,
-    1_000_000_000_000_000,
-    10_000_000_000_000_000,
-    100_000_000_000_000_000,
-    1_000_000_000_000_000_000
-];
+use lazy_static::lazy_static;
+
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::types::SqlType;
+use crate::types::I256;
+
+const MAX_PRECISION: u8 = 76;
+
+lazy_static! {
+    // 10^0 to 10^76, the largest precision of a `Decimal256`.
+    static ref FACTORS10: Vec<I256> = {
+        let mut factors = vec![I256::from(1)];
+        while factors.len() <= MAX_PRECISION as usize {
+            let factor = factors[factors.len() - 1].checked_mul(I256::from(10)).unwrap();
+            factors.push(factor);
+        }
+        factors
+    };
+}
 
 pub trait Base {
     fn scale(self, scale: i64) -> i64;
+
+    /// Multiplies by 10^`scale` in 256 bits, for the decimals of a precision greater
+    /// than 18. Returns `None` on overflow.
+    fn scale_wide(self, scale: u8) -> Option<I256>
+    where Self: Sized {
+        let factor = i128::try_from(*FACTORS10.get(scale as usize)?).ok()?;
+        let factor = i64::try_from(factor).ok()?;
+        Some(I256::from(i128::from(self.scale(factor))))
+    }
 }
 
 pub trait InternalResult {
-    fn get(underlying: i64) -> Self;
+    fn get(underlying: I256) -> Self;
 }
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
 pub(crate) enum NoBits {
     N32,
-    N64
+    N64,
+    N128,
+    N256
 }
 
 /// Provides arbitrary-precision floating point decimal.
 #[derive(Clone)]
 pub struct Decimal {
-    pub(crate) underlying: i64,
-    pub(crate) nobits: NoBits, // its domain is {32, 64}
+    pub(crate) underlying: Underlying,
+    pub(crate) nobits: NoBits, // its domain is {32, 64, 128, 256}
     pub(crate) precision: u8,
     pub(crate) scale: u8
 }
 
+// The value of a decimal. Values which don't fit in an i64 are boxed, so that `Value`
+// stays small.
+#[derive(Clone)]
+pub(crate) enum Underlying {
+    Narrow(i64),
+    Wide(Box<I256>)
+}
+
+impl Underlying {
+    pub(crate) fn get(&self) -> I256 {
+        match self {
+            Underlying::Narrow(value) => I256::from(i128::from(*value)),
+            Underlying::Wide(value) => **value
+        }
+    }
+}
+
+impl From<I256> for Underlying {
+    fn from(value: I256) -> Self {
+        match i128::try_from(value).map(i64::try_from) {
+            Ok(Ok(value)) => Underlying::Narrow(value),
+            _ => Underlying::Wide(Box::new(value))
+        }
+    }
+}
+
 impl Default for Decimal {
     fn default() -> Self {
         Decimal {
-            underlying: 0,
+            underlying: Underlying::Narrow(0),
             precision: 9,
             scale: 4,
             nobits: NoBits::N32
@@ -58,40 +95,68 @@ impl Default for Decimal {
 }
 
 macro_rules! base_for {
-    ( $( $t:ty: $cast:expr ),* ) => {
+    ( $( $t:ty: $cast:expr, $wide:expr ),* ) => {
         $(
             impl Base for $t {
                 fn scale(self, scale: i64) -> i64 {
                     $cast(self * (scale as $t)) as i64
                 }
+
+                fn scale_wide(self, scale: u8) -> Option<I256> {
+                    let wide: fn($t, u8) -> Option<I256> = $wide;
+                    wide(self, scale)
+                }
             }
         )*
     };
 }
 
 base_for! {
-    f32: std::convert::identity,
-    f64: std::convert::identity,
-    i8: i64::from,
-    i16: i64::from,
-    i32: i64::from,
-    i64: std::convert::identity,
-    u8: i64::from,
-    u16: i64::from,
-    u32: i64::from,
-    u64 : std::convert::identity
+    f32: std::convert::identity, |source, scale| scale_float(f64::from(source), scale),
+    f64: std::convert::identity, scale_float,
+    i8: i64::from, scale_integer,
+    i16: i64::from, scale_integer,
+    i32: i64::from, scale_integer,
+    i64: std::convert::identity, scale_integer,
+    i128: std::convert::identity, scale_integer,
+    u8: i64::from, scale_integer,
+    u16: i64::from, scale_integer,
+    u32: i64::from, scale_integer,
+    u64 : std::convert::identity, scale_integer
+}
+
+fn scale_integer<T: Into<i128>>(source: T, scale: u8) -> Option<I256> {
+    rescale(I256::from(source.into()), scale)
+}
+
+fn scale_float(source: f64, scale: u8) -> Option<I256> {
+    I256::from_f64(source * 10_f64.powi(i32::from(scale)))
 }
 
 impl InternalResult for i32 {
     #[inline(always)]
-    fn get(underlying: i64) -> Self {
-        underlying as Self
+    fn get(underlying: I256) -> Self {
+        underlying.as_i128() as Self
     }
 }
 
 impl InternalResult for i64 {
     #[inline(always)]
-    fn get(underlying: i64) -> Self {
+    fn get(underlying: I256) -> Self {
+        underlying.as_i128() as Self
+    }
+}
+
+impl InternalResult for i128 {
+    #[inline(always)]
+    fn get(underlying: I256) -> Self {
+        underlying.as_i128()
+    }
+}
+
+impl InternalResult for I256 {
+    #[inline(always)]
+    fn get(underlying: I256) -> Self {
         underlying
     }
 }
@@ -102,32 +167,46 @@ impl NoBits {
             Some(NoBits::N32)
         } else if precision <= 18 {
             Some(NoBits::N64)
+        } else if precision <= 38 {
+            Some(NoBits::N128)
+        } else if precision <= 76 {
+            Some(NoBits::N256)
         } else {
             None
         }
     }
+
+    /// The type of the integers the values are sent as.
+    pub(crate) fn inner_type(self) -> SqlType {
+        match self {
+            NoBits::N32 => SqlType::Int32,
+            NoBits::N64 => SqlType::Int64,
+            NoBits::N128 => SqlType::Int128,
+            NoBits::N256 => SqlType::Int256
+        }
+    }
 }
 
 impl PartialEq for Decimal {
     fn eq(&self, other: &Self) -> bool {
+        let (a, b) = (self.underlying.get(), other.underlying.get());
+        // a value which overflows once rescaled can't be equal to the other one
         match self.scale.cmp(&other.scale) {
-            Ordering::Less => {
-                let delta = other.scale() - self.scale();
-                let underlying = self.underlying * FACTORS10[delta];
-                other.underlying == underlying
-            }
-            Ordering::Equal => self.underlying == other.underlying,
-            Ordering::Greater => {
-                let delta = self.scale() - other.scale();
-                let underlying = other.underlying * FACTORS10[delta];
-                self.underlying == underlying
-            }
+            Ordering::Less => rescale(a, other.scale - self.scale) == Some(b),
+            Ordering::Equal => a == b,
+            Ordering::Greater => rescale(b, self.scale - other.scale) == Some(a)
         }
     }
 }
 
+/// Multiplies `value` by 10^`exp`, `None` on overflow.
+fn rescale(value: I256, exp: u8) -> Option<I256> {
+    value.checked_mul(*FACTORS10.get(exp as usize)?)
+}
+
 fn decimal2str(decimal: &Decimal) -> String {
-    let mut r = format!("{}", decimal.underlying);
+    let value = decimal.underlying.get();
+    let mut r = value.unsigned_abs().to_string();
     while r.len() < decimal.scale() {
         r.insert(0, '0');
     }
@@ -136,35 +215,38 @@ fn decimal2str(decimal: &Decimal) -> String {
     if r.starts_with('.') {
         r.insert(0, '0');
     }
+    if value.is_negative() {
+        r.insert(0, '-');
+    }
     r
 }
 
 impl fmt::Display for Decimal {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", decimal2str(self))
     }
 }
 
 impl fmt::Debug for Decimal {
-    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(f, "{}", decimal2str(self))
     }
 }
 
 impl From<Decimal> for f32 {
     fn from(value: Decimal) -> Self {
-        value.underlying as f32 / FACTORS10[value.scale()] as f32
+        f64::from(value) as f32
     }
 }
 
 impl From<Decimal> for f64 {
     fn from(value: Decimal) -> Self {
-        value.underlying as f64 / FACTORS10[value.scale()] as f64
+        value.underlying.get().as_f64() / 10_f64.powi(i32::from(value.scale))
     }
 }
 
 impl Decimal {
-    /// Method of creating a Decimal.
+    /// Method of creating a Decimal of precision 18, see `with_precision` for the others.
     pub fn new(underlying: i64, scale: u8) -> Decimal {
         let precision = 18;
         if scale > precision {
@@ -172,33 +254,70 @@ impl Decimal {
         }
 
         Decimal {
-            underlying,
+            underlying: Underlying::Narrow(underlying),
             precision,
             scale,
             nobits: NoBits::N64
         }
     }
 
+    /// Creates a decimal of the given precision, up to 76 digits. Fails when the scale is
+    /// greater than the precision, or when the value has more digits.
+    pub fn with_precision(underlying: I256, precision: u8, scale: u8) -> Result<Decimal> {
+        let nobits = match NoBits::from_precision(precision) {
+            Some(nobits) if scale <= precision => nobits,
+            _ => {
+                let message = format!("Invalid precision {} or scale {}.", precision, scale);
+                return Err(message.into());
+            }
+        };
+
+        Decimal {
+            underlying: Underlying::Narrow(0),
+            precision,
+            scale,
+            nobits
+        }
+        .with_underlying(underlying)
+    }
+
+    /// Creates a decimal of precision 18 from a number which is scaled, see
+    /// `of_precision` for the others.
     pub fn of<B: Base>(source: B, scale: u8) -> Decimal {
         let precision = 18;
         if scale > precision {
             panic!("scale can't be greater than 18");
         }
 
-        let underlying = source.scale(FACTORS10[scale as usize]);
-        if underlying > FACTORS10[precision as usize] as i64 {
-            panic!("{} > {}", underlying, FACTORS10[precision as usize]);
+        let underlying = source.scale(10_i64.pow(u32::from(scale)));
+        let max = 10_i64.pow(u32::from(precision));
+        if underlying > max {
+            panic!("{} > {}", underlying, max);
         }
 
         Decimal {
-            underlying,
+            underlying: Underlying::Narrow(underlying),
             precision,
             scale,
             nobits: NoBits::N64
         }
     }
 
-    /// Get the internal representation of decimal as [`i32`] or [`i64`].
+    /// Creates a decimal of the given precision, up to 76 digits, from a number which is
+    /// scaled, e.g. `Decimal::of_precision(1.5, 40, 2)` is 1.50 as a `Decimal(40, 2)`.
+    /// Fails like `with_precision`, or when the scaled number overflows.
+    pub fn of_precision<B: Base>(source: B, precision: u8, scale: u8) -> Result<Decimal> {
+        match source.scale_wide(scale) {
+            Some(underlying) => Decimal::with_precision(underlying, precision, scale),
+            None => {
+                let message = format!("Value doesn't fit in Decimal({}, {}).", precision, scale);
+                Err(message.into())
+            }
+        }
+    }
+
+    /// Get the internal representation of decimal as [`i32`], [`i64`], [`i128`] or
+    /// [`I256`].
     ///
     /// example:
     /// ```rust
@@ -221,7 +340,7 @@ impl Decimal {
     /// ```
     #[inline(always)]
     pub fn internal<I: InternalResult>(&self) -> I {
-        InternalResult::get(self.underlying)
+        InternalResult::get(self.underlying.get())
     }
 
     /// Determines how many decimal digits fraction can have.
@@ -229,25 +348,50 @@ impl Decimal {
         self.scale as usize
     }
 
-    pub(crate) fn set_scale(self, scale: u8) -> Self {
+    /// Rescales the value, fails when it doesn't fit in the precision anymore.
+    pub(crate) fn set_scale(self, scale: u8) -> Result<Self> {
+        if scale > self.precision {
+            let message = format!("Invalid scale {} for precision {}.", scale, self.precision);
+            return Err(message.into());
+        }
+
+        let value = self.underlying.get();
         let underlying = match scale.cmp(&self.scale) {
-            Ordering::Less => {
-                let delta = self.scale() - scale as usize;
-                self.underlying / FACTORS10[delta]
-            }
-            Ordering::Equal => return self,
-            Ordering::Greater => {
-                let delta = scale as usize - self.scale();
-                self.underlying * FACTORS10[delta]
-            }
+            Ordering::Less => FACTORS10
+                .get((self.scale - scale) as usize)
+                .and_then(|factor| value.checked_div(*factor)),
+            Ordering::Equal => Some(value),
+            Ordering::Greater => rescale(value, scale - self.scale)
         };
 
-        Decimal {
-            underlying,
+        let decimal = Decimal {
+            underlying: Underlying::Narrow(0),
             precision: self.precision,
             scale,
             nobits: self.nobits
+        };
+        match underlying {
+            Some(underlying) => decimal.with_underlying(underlying),
+            None => Err(decimal.overflow(value, self.scale))
+        }
+    }
+
+    // Sets the value, fails when it has more digits than the precision.
+    fn with_underlying(mut self, underlying: I256) -> Result<Self> {
+        let max = FACTORS10[self.precision as usize];
+        if underlying.unsigned_abs() >= max.unsigned_abs() {
+            return Err(self.overflow(underlying, self.scale));
         }
+        self.underlying = underlying.into();
+        Ok(self)
+    }
+
+    fn overflow(&self, underlying: I256, scale: u8) -> Error {
+        let message = format!(
+            "Value {} with scale {} doesn't fit in Decimal({}, {}).",
+            underlying, scale, self.precision, self.scale
+        );
+        message.into()
     }
 }
 
@@ -310,22 +454,86 @@ mod test {
         assert!((value - 2.0_f64).abs() < std::f64::EPSILON);
     }
 
+    #[test]
+    fn test_wide_from_f64() {
+        let value: f64 = Decimal::with_precision(I256::from(1), 76, 40)
+            .unwrap()
+            .into();
+        assert!((value - 1e-40_f64).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_with_precision() {
+        assert!(Decimal::with_precision(I256::from(1), 77, 0).is_err());
+        assert!(Decimal::with_precision(I256::from(1), 10, 11).is_err());
+        assert!(Decimal::with_precision(I256::from(999), 3, 0).is_ok());
+        assert!(Decimal::with_precision(I256::from(-1000), 3, 0).is_err());
+    }
+
+    #[test]
+    fn test_of_precision() {
+        let d = Decimal::of_precision(1.5, 40, 2).unwrap();
+        assert_eq!(d.to_string(), "1.50");
+        assert_eq!(d, Decimal::of(1.5, 2));
+
+        let big = Decimal::of_precision(u64::MAX, 76, 50).unwrap();
+        let expected = I256::from(i128::from(u64::MAX)).checked_mul(FACTORS10[50]);
+        assert_eq!(Some(big.internal::<I256>()), expected);
+
+        assert!(Decimal::of_precision(1, 76, 76).is_err());
+        assert!(Decimal::of_precision(1e80, 76, 0).is_err());
+        assert!(Decimal::of_precision(1, 10, 11).is_err());
+
+        // other sources are scaled in 64 bits
+        struct Cents(i64);
+        impl Base for Cents {
+            fn scale(self, scale: i64) -> i64 {
+                self.0 * scale
+            }
+        }
+        let cents = Decimal::of_precision(Cents(3), 20, 2).unwrap();
+        assert_eq!(cents.internal::<i128>(), 300);
+        assert!(Decimal::of_precision(Cents(3), 20, 19).is_err());
+    }
+
+    #[test]
+    fn test_wide_eq() {
+        let max = FACTORS10[76].checked_div(I256::from(10)).unwrap();
+        let a = Decimal::with_precision(max, 76, 0).unwrap();
+        let b = Decimal::with_precision(I256::from(1), 76, 76).unwrap();
+        assert_ne!(a, b);
+        assert_ne!(b, a);
+
+        let c = Decimal::with_precision(FACTORS10[40], 76, 40).unwrap();
+        assert_eq!(c, Decimal::with_precision(I256::from(1), 76, 0).unwrap());
+    }
+
     #[test]
     fn set_scale1() {
         let a = Decimal::of(12, 3);
-        let b = a.set_scale(2);
+        let b = a.set_scale(2).unwrap();
 
         assert_eq!(2, b.scale);
-        assert_eq!(1200, b.underlying);
+        assert_eq!(I256::from(1200), b.underlying.get());
     }
 
     #[test]
     fn set_scale2() {
         let a = Decimal::of(12, 3);
-        let b = a.set_scale(4);
+        let b = a.set_scale(4).unwrap();
 
         assert_eq!(4, b.scale);
-        assert_eq!(120_000, b.underlying);
+        assert_eq!(I256::from(120_000), b.underlying.get());
+    }
+
+    #[test]
+    fn set_scale_overflow() {
+        let a = Decimal::of(12, 3);
+        assert!(a.clone().set_scale(17).is_err());
+        assert!(a.set_scale(19).is_err());
+
+        let b = Decimal::with_precision(I256::from(1), 76, 0).unwrap();
+        assert!(b.set_scale(76).is_err());
     }
 
     #[test]
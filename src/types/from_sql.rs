@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::net::Ipv4Addr;
 use std::net::Ipv6Addr;
 
@@ -16,6 +19,8 @@ use crate::types::Enum16;
 use crate::types::Enum8;
 use crate::types::SqlType;
 use crate::types::ValueRef;
+use crate::types::I256;
+use crate::types::U256;
 
 pub type FromSqlResult<T> = Result<T>;
 
@@ -157,12 +162,12 @@ impl<'a> FromSql<'a> for uuid::Uuid {
 }
 
 macro_rules! from_sql_vec_impl {
-    ( $( $t:ty: $k:pat => $f:expr ),* ) => {
+    ( $( $t:ty: $( $k:pat )|+ => $f:expr ),* ) => {
         $(
             impl<'a> FromSql<'a> for Vec<$t> {
                 fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
                     match value {
-                        ValueRef::Array($k, vs) => {
+                        $( ValueRef::Array($k, vs) )|+ => {
                             let f: fn(ValueRef<'a>) -> FromSqlResult<$t> = $f;
                             let mut result = Vec::with_capacity(vs.len());
                             for v in vs.iter() {
@@ -186,10 +191,10 @@ macro_rules! from_sql_vec_impl {
 }
 
 from_sql_vec_impl! {
-    &'a str: SqlType::String => |v| v.as_str(),
-    String: SqlType::String => |v| v.as_string(),
-    &'a [u8]: SqlType::String => |v| v.as_bytes(),
-    Vec<u8>: SqlType::String => |v| v.as_bytes().map(<[u8]>::to_vec),
+    &'a str: SqlType::String | SqlType::LowCardinality(SqlType::String) => |v| v.as_str(),
+    String: SqlType::String | SqlType::LowCardinality(SqlType::String) => |v| v.as_string(),
+    &'a [u8]: SqlType::String | SqlType::LowCardinality(SqlType::String) => |v| v.as_bytes(),
+    Vec<u8>: SqlType::String | SqlType::LowCardinality(SqlType::String) => |v| v.as_bytes().map(<[u8]>::to_vec),
     Date<Tz>: SqlType::Date => |z| Ok(z.into()),
     DateTime<Tz>: SqlType::DateTime(_) => |z| Ok(z.into())
 }
@@ -244,6 +249,9 @@ from_sql_vec_impl! {
     u32: UInt32,
     u64: UInt64,
 
+    i128: Int128,
+    u128: UInt128,
+
     f32: Float32,
     f64: Float64
 }
@@ -271,6 +279,86 @@ where T: FromSql<'a>
     }
 }
 
+macro_rules! from_sql_tuple_impl {
+    ( $( ( $( $t:ident ),* ) ),* ) => {
+        $(
+            impl<'a, $( $t ),*> FromSql<'a> for ($( $t, )*)
+            where
+                $( $t: FromSql<'a> ),*
+            {
+                fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
+                    let len = [$( stringify!($t) ),*].len();
+                    match value {
+                        ValueRef::Tuple(vs) if vs.len() == len => {
+                            let mut items = vs.iter().cloned();
+                            Ok(($( $t::from_sql(items.next().unwrap())?, )*))
+                        }
+                        _ => {
+                            let from = SqlType::from(value.clone()).to_string();
+                            Err(Error::FromSql(FromSqlError::InvalidType {
+                                src: from,
+                                dst: format!("{}-tuple", len).into(),
+                            }))
+                        }
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_sql_tuple_impl! {
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+}
+
+impl<'a, K, V> FromSql<'a> for HashMap<K, V>
+where
+    K: FromSql<'a> + Eq + Hash,
+    V: FromSql<'a>
+{
+    fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Map(_, _, vs) => vs
+                .iter()
+                .map(|(k, v)| Ok((K::from_sql(k.clone())?, V::from_sql(v.clone())?)))
+                .collect(),
+            _ => {
+                let from = SqlType::from(value.clone()).to_string();
+                Err(Error::FromSql(FromSqlError::InvalidType {
+                    src: from,
+                    dst: "HashMap".into()
+                }))
+            }
+        }
+    }
+}
+
+impl<'a, K, V> FromSql<'a> for BTreeMap<K, V>
+where
+    K: FromSql<'a> + Ord,
+    V: FromSql<'a>
+{
+    fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Map(_, _, vs) => vs
+                .iter()
+                .map(|(k, v)| Ok((K::from_sql(k.clone())?, V::from_sql(v.clone())?)))
+                .collect(),
+            _ => {
+                let from = SqlType::from(value.clone()).to_string();
+                Err(Error::FromSql(FromSqlError::InvalidType {
+                    src: from,
+                    dst: "BTreeMap".into()
+                }))
+            }
+        }
+    }
+}
+
 impl<'a> FromSql<'a> for Date<Tz> {
     fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
         match value {
@@ -322,12 +410,48 @@ from_sql_impl! {
     i32: Int32,
     i64: Int64,
 
+    i128: Int128,
+    u128: UInt128,
+
     f32: Float32,
     f64: Float64
 }
 
+impl<'a> FromSql<'a> for I256 {
+    fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Int256(v) => Ok(*v),
+            _ => {
+                let from = SqlType::from(value.clone()).to_string();
+                Err(Error::FromSql(FromSqlError::InvalidType {
+                    src: from,
+                    dst: "I256".into()
+                }))
+            }
+        }
+    }
+}
+
+impl<'a> FromSql<'a> for U256 {
+    fn from_sql(value: ValueRef<'a>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::UInt256(v) => Ok(*v),
+            _ => {
+                let from = SqlType::from(value.clone()).to_string();
+                Err(Error::FromSql(FromSqlError::InvalidType {
+                    src: from,
+                    dst: "U256".into()
+                }))
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
     use chrono::prelude::*;
     use chrono_tz::Tz;
 
@@ -356,6 +480,28 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_tuple() {
+        let v = ValueRef::Tuple(Arc::new(vec![ValueRef::from(1_u8), ValueRef::from("a")]));
+        assert_eq!(<(u8, &str)>::from_sql(v.clone()).unwrap(), (1, "a"));
+        assert!(<(u8, &str, u8)>::from_sql(v).is_err());
+    }
+
+    #[test]
+    fn test_map() {
+        let v = ValueRef::Map(
+            SqlType::String.into(),
+            SqlType::UInt32.into(),
+            Arc::new(vec![
+                (ValueRef::from("a"), ValueRef::from(1_u32)),
+                (ValueRef::from("b"), ValueRef::from(2_u32)),
+            ])
+        );
+        let actual = HashMap::<String, u32>::from_sql(v).unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!(actual["b"], 2);
+    }
+
     #[test]
     fn null_to_datetime() {
         let null_value = ValueRef::Nullable(Either::Left(
@@ -0,0 +1,223 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use crate::errors::Error;
+use crate::errors::FromSqlError;
+
+/// A signed 256-bit integer of `Int256` columns, sent as little-endian two's complement
+/// bytes. The arithmetic is the one of `ethnum`.
+///
+/// ```rust
+/// # use std::convert::TryFrom;
+/// # use clickhouse_srv::types::I256;
+/// let value = I256::from(-42_i128);
+/// assert_eq!(value.to_string(), "-42");
+/// assert_eq!(i128::try_from(value).unwrap(), -42);
+/// ```
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct I256(pub(crate) ethnum::I256);
+
+/// An unsigned 256-bit integer of `UInt256` columns, as little-endian bytes.
+#[derive(Copy, Clone, Default, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[repr(transparent)]
+pub struct U256(pub(crate) ethnum::U256);
+
+impl I256 {
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        I256(ethnum::I256::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        self.0.to_le_bytes()
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_negative()
+    }
+
+    pub(crate) fn unsigned_abs(self) -> U256 {
+        U256(self.0.unsigned_abs())
+    }
+
+    /// Returns `None` on overflow.
+    pub(crate) fn checked_mul(self, rhs: I256) -> Option<I256> {
+        self.0.checked_mul(rhs.0).map(I256)
+    }
+
+    /// Rounds toward zero, returns `None` when `rhs` is zero or on overflow.
+    pub(crate) fn checked_div(self, rhs: I256) -> Option<I256> {
+        self.0.checked_div(rhs.0).map(I256)
+    }
+
+    /// Truncates toward zero, returns `None` for NaN and the values out of range.
+    pub(crate) fn from_f64(value: f64) -> Option<I256> {
+        // 2^255
+        let limit = 2_f64.powi(255);
+        if value.is_nan() || value >= limit || value < -limit {
+            return None;
+        }
+        Some(I256(ethnum::AsI256::as_i256(value)))
+    }
+
+    /// The lowest 128 bits, as `as` truncates integers.
+    pub(crate) fn as_i128(self) -> i128 {
+        self.0.as_i128()
+    }
+
+    pub(crate) fn as_f64(self) -> f64 {
+        self.0.as_f64()
+    }
+}
+
+impl U256 {
+    pub fn from_le_bytes(bytes: [u8; 32]) -> Self {
+        U256(ethnum::U256::from_le_bytes(bytes))
+    }
+
+    pub fn to_le_bytes(self) -> [u8; 32] {
+        self.0.to_le_bytes()
+    }
+}
+
+impl From<i128> for I256 {
+    fn from(value: i128) -> Self {
+        I256(value.into())
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(value: u128) -> Self {
+        U256(value.into())
+    }
+}
+
+impl TryFrom<I256> for i128 {
+    type Error = Error;
+
+    fn try_from(value: I256) -> Result<Self, Self::Error> {
+        i128::try_from(value.0).map_err(|_| Error::FromSql(FromSqlError::OutOfRange))
+    }
+}
+
+impl TryFrom<U256> for u128 {
+    type Error = Error;
+
+    fn try_from(value: U256) -> Result<Self, Self::Error> {
+        u128::try_from(value.0).map_err(|_| Error::FromSql(FromSqlError::OutOfRange))
+    }
+}
+
+impl fmt::Display for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Display for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)
+    }
+}
+
+impl fmt::Debug for I256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Debug for U256 {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(I256::from(0).to_string(), "0");
+        assert_eq!(I256::from(i128::MIN).to_string(), i128::MIN.to_string());
+        assert_eq!(U256::from(u128::MAX).to_string(), u128::MAX.to_string());
+        assert_eq!(
+            U256::from_le_bytes([0xff; 32]).to_string(),
+            "115792089237316195423570985008687907853269984665640564039457584007913129639935"
+        );
+
+        let mut min = [0; 32];
+        min[31] = 0x80;
+        assert_eq!(
+            I256::from_le_bytes(min).to_string(),
+            "-57896044618658097711785492504343953926634992332820282019728792003956564819968"
+        );
+    }
+
+    #[test]
+    fn test_try_from() {
+        assert_eq!(i128::try_from(I256::from(-1)).unwrap(), -1);
+        assert_eq!(i128::try_from(I256::from(i128::MAX)).unwrap(), i128::MAX);
+        assert_eq!(u128::try_from(U256::from(7)).unwrap(), 7);
+
+        let mut big = [0; 32];
+        big[16] = 1;
+        assert!(i128::try_from(I256::from_le_bytes(big)).is_err());
+        assert!(u128::try_from(U256::from_le_bytes(big)).is_err());
+
+        // 2^127 doesn't fit in an i128 though its high half is zero
+        let mut edge = [0; 32];
+        edge[15] = 0x80;
+        assert!(i128::try_from(I256::from_le_bytes(edge)).is_err());
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let big = I256::from(i128::MAX);
+        let square = big.checked_mul(big).unwrap();
+        assert_eq!(square.checked_div(big), Some(big));
+        assert_eq!(
+            square.checked_mul(I256::from(-1)).unwrap().checked_div(big),
+            Some(I256::from(-i128::MAX))
+        );
+        assert_eq!(square.checked_mul(I256::from(4)), None);
+        assert_eq!(big.checked_div(I256::from(0)), None);
+
+        assert_eq!(
+            I256::from(-7).checked_div(I256::from(2)),
+            Some(I256::from(-3))
+        );
+        assert_eq!(
+            I256::from(-7).checked_mul(I256::from(-2)),
+            Some(I256::from(14))
+        );
+
+        let mut min = [0; 32];
+        min[31] = 0x80;
+        assert_eq!(
+            I256::from_le_bytes(min).checked_div(I256::from(1)),
+            Some(I256::from_le_bytes(min))
+        );
+        assert_eq!(I256::from_le_bytes(min).checked_div(I256::from(-1)), None);
+    }
+
+    #[test]
+    fn test_cmp() {
+        assert!(I256::from(-1) < I256::from(0));
+        assert!(I256::from(i128::MIN) < I256::from(-1));
+        assert!(I256::from(i128::MAX).checked_mul(I256::from(2)).unwrap() > I256::from(i128::MAX));
+        assert!(U256::from(u128::MAX) < U256::from_le_bytes([0xff; 32]));
+    }
+
+    #[test]
+    fn test_as_f64() {
+        assert_eq!(I256::from(-42).as_f64(), -42.0);
+        assert_eq!(I256::from(i128::MIN).as_f64(), i128::MIN as f64);
+        assert_eq!(I256::from(-5).as_i128(), -5);
+
+        assert_eq!(I256::from_f64(-42.9), Some(I256::from(-42)));
+        assert_eq!(I256::from_f64(2_f64.powi(255)), None);
+        assert_eq!(I256::from_f64(f64::NAN), None);
+        assert!(I256::from_f64(-(2_f64.powi(255))).unwrap().is_negative());
+    }
+}
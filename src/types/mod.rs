@@ -11,6 +11,8 @@ use hostname::get;
 use lazy_static::lazy_static;
 
 pub use self::block::Block;
+pub(crate) use self::block::BlockDecoder;
+pub use self::block::CompressionMethod;
 pub use self::block::RCons;
 pub use self::block::RNil;
 pub use self::block::Row;
@@ -26,16 +28,25 @@ pub use self::enums::Enum16;
 pub use self::enums::Enum8;
 pub use self::from_sql::FromSql;
 pub use self::from_sql::FromSqlResult;
+pub use self::int256::I256;
+pub use self::int256::U256;
 pub(crate) use self::marshal::Marshal;
 pub use self::options::Options;
 pub(crate) use self::options::OptionsSource;
 pub use self::query::Query;
+pub use self::settings::Setting;
+pub use self::settings::SettingValue;
+pub use self::settings::Settings;
 pub(crate) use self::stat_buffer::StatBuffer;
 pub(crate) use self::unmarshal::Unmarshal;
 pub use self::value::Value;
 pub use self::value_ref::ValueRef;
 use crate::binary::Encoder;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS;
+use crate::protocols::DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS;
 use crate::protocols::DBMS_MIN_REVISION_WITH_CLIENT_WRITE_INFO;
+use crate::protocols::DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION;
+use crate::protocols::SERVER_PROFILE_INFO;
 use crate::protocols::SERVER_PROGRESS;
 
 pub(crate) mod column;
@@ -54,13 +65,20 @@ mod query;
 
 mod decimal;
 mod enums;
+mod int256;
 mod options;
+mod settings;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct Progress {
     pub rows: u64,
     pub bytes: u64,
-    pub total_rows: u64
+    pub total_rows: u64,
+    pub total_bytes: u64,
+    pub written_rows: u64,
+    pub written_bytes: u64,
+    /// The time the query has been running for, in nanoseconds.
+    pub elapsed_ns: u64
 }
 
 impl Progress {
@@ -70,21 +88,48 @@ impl Progress {
         encoder.uvarint(self.bytes);
         encoder.uvarint(self.total_rows);
 
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS {
+            encoder.uvarint(self.total_bytes);
+        }
+
         if client_revision >= DBMS_MIN_REVISION_WITH_CLIENT_WRITE_INFO {
-            encoder.uvarint(0);
-            encoder.uvarint(0);
+            encoder.uvarint(self.written_rows);
+            encoder.uvarint(self.written_bytes);
+        }
+
+        if client_revision >= DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS {
+            encoder.uvarint(self.elapsed_ns);
         }
     }
 }
 
 #[derive(Copy, Clone, Default, Debug, PartialEq)]
-pub(crate) struct ProfileInfo {
+pub struct ProfileInfo {
     pub rows: u64,
     pub bytes: u64,
     pub blocks: u64,
     pub applied_limit: bool,
     pub rows_before_limit: u64,
-    pub calculated_rows_before_limit: bool
+    pub calculated_rows_before_limit: bool,
+    pub applied_aggregation: bool,
+    pub rows_before_aggregation: u64
+}
+
+impl ProfileInfo {
+    pub fn write(&self, encoder: &mut Encoder, client_revision: u64) {
+        encoder.uvarint(SERVER_PROFILE_INFO);
+        encoder.uvarint(self.rows);
+        encoder.uvarint(self.blocks);
+        encoder.uvarint(self.bytes);
+        encoder.write(self.applied_limit);
+        encoder.uvarint(self.rows_before_limit);
+        encoder.write(self.calculated_rows_before_limit);
+
+        if client_revision >= DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION {
+            encoder.write(self.applied_aggregation);
+            encoder.uvarint(self.rows_before_aggregation);
+        }
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -169,6 +214,10 @@ has_sql_type! {
     i16: SqlType::Int16,
     i32: SqlType::Int32,
     i64: SqlType::Int64,
+    i128: SqlType::Int128,
+    u128: SqlType::UInt128,
+    I256: SqlType::Int256,
+    U256: SqlType::UInt256,
     &str: SqlType::String,
     String: SqlType::String,
     f32: SqlType::Float32,
@@ -194,6 +243,10 @@ pub enum SqlType {
     Int16,
     Int32,
     Int64,
+    Int128,
+    UInt128,
+    Int256,
+    UInt256,
     String,
     FixedString(usize),
     Float32,
@@ -205,6 +258,9 @@ pub enum SqlType {
     Uuid,
     Nullable(&'static SqlType),
     Array(&'static SqlType),
+    LowCardinality(&'static SqlType),
+    Tuple(Vec<&'static SqlType>),
+    Map(&'static SqlType, &'static SqlType),
     Decimal(u8, u8),
     Enum8(Vec<(String, i8)>),
     Enum16(Vec<(String, i16)>)
@@ -257,6 +313,10 @@ impl SqlType {
             SqlType::Int16 => "Int16".into(),
             SqlType::Int32 => "Int32".into(),
             SqlType::Int64 => "Int64".into(),
+            SqlType::Int128 => "Int128".into(),
+            SqlType::UInt128 => "UInt128".into(),
+            SqlType::Int256 => "Int256".into(),
+            SqlType::UInt256 => "UInt256".into(),
             SqlType::String => "String".into(),
             SqlType::FixedString(str_len) => format!("FixedString({})", str_len).into(),
             SqlType::Float32 => "Float32".into(),
@@ -271,6 +331,12 @@ impl SqlType {
             SqlType::Uuid => "UUID".into(),
             SqlType::Nullable(nested) => format!("Nullable({})", &nested).into(),
             SqlType::Array(nested) => format!("Array({})", &nested).into(),
+            SqlType::LowCardinality(nested) => format!("LowCardinality({})", &nested).into(),
+            SqlType::Tuple(items) => {
+                let a: Vec<String> = items.iter().map(|item| item.to_string()).collect();
+                format!("Tuple({})", a.join(", ")).into()
+            }
+            SqlType::Map(key, value) => format!("Map({}, {})", &key, &value).into(),
             SqlType::Decimal(precision, scale) => {
                 format!("Decimal({}, {})", precision, scale).into()
             }
@@ -319,3 +385,59 @@ fn test_to_string() {
     let actual = SqlType::Nullable(&SqlType::UInt8).to_string();
     assert_eq!(expected, actual)
 }
+
+#[test]
+fn test_write_profile_info() {
+    let profile_info = ProfileInfo {
+        rows: 1,
+        bytes: 300,
+        blocks: 2,
+        applied_limit: true,
+        rows_before_limit: 10,
+        calculated_rows_before_limit: true,
+        applied_aggregation: true,
+        rows_before_aggregation: 5
+    };
+
+    let mut encoder = Encoder::new();
+    profile_info.write(&mut encoder, 54428);
+    assert_eq!(encoder.get_buffer_ref(), &[6_u8, 1, 2, 172, 2, 1, 10, 1]);
+
+    let mut encoder = Encoder::new();
+    profile_info.write(&mut encoder, DBMS_MIN_REVISION_WITH_ROWS_BEFORE_AGGREGATION);
+    assert_eq!(encoder.get_buffer_ref(), &[
+        6_u8, 1, 2, 172, 2, 1, 10, 1, 1, 5
+    ]);
+}
+
+#[test]
+fn test_write_progress() {
+    let progress = Progress {
+        rows: 1,
+        bytes: 2,
+        total_rows: 3,
+        total_bytes: 4,
+        written_rows: 5,
+        written_bytes: 6,
+        elapsed_ns: 7
+    };
+
+    let cases: [(u64, &[u8]); 4] = [
+        (54405, &[3, 1, 2, 3]),
+        (DBMS_MIN_REVISION_WITH_CLIENT_WRITE_INFO, &[
+            3, 1, 2, 3, 5, 6
+        ]),
+        (
+            DBMS_MIN_PROTOCOL_VERSION_WITH_SERVER_QUERY_TIME_IN_PROGRESS,
+            &[3, 1, 2, 3, 5, 6, 7]
+        ),
+        (DBMS_MIN_PROTOCOL_VERSION_WITH_TOTAL_BYTES_IN_PROGRESS, &[
+            3, 1, 2, 3, 4, 5, 6, 7
+        ])
+    ];
+    for (revision, expected) in cases.iter() {
+        let mut encoder = Encoder::new();
+        progress.write(&mut encoder, *revision);
+        assert_eq!(encoder.get_buffer_ref(), *expected, "revision {}", revision);
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::io::Read;
+
+use crate::binary::ReadEx;
+use crate::errors::DriverError;
+use crate::errors::Error;
+use crate::errors::Result;
+use crate::protocols::DBMS_MIN_REVISION_WITH_SETTINGS_SERIALIZED_AS_STRINGS;
+
+const SETTING_FLAG_IMPORTANT: u64 = 0x01;
+const SETTING_FLAG_CUSTOM: u64 = 0x02;
+
+/// The value of a query setting sent by the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SettingValue {
+    UInt64(u64),
+    Int64(i64),
+    Float64(f64),
+    Bool(bool),
+    String(String)
+}
+
+/// A query setting sent by the client.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Setting {
+    pub value: SettingValue,
+    /// The client asks to fail the query if the setting is not supported.
+    pub important: bool,
+    /// The setting is a user defined one (e.g. `custom_xxx`).
+    pub custom: bool
+}
+
+/// Query settings changed by the client, e.g. `max_execution_time` or `readonly`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    settings: HashMap<String, Setting>
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum SettingType {
+    UInt64,
+    Int64,
+    Float64,
+    Bool,
+    String
+}
+
+impl Settings {
+    /// Reads settings until the empty name, which marks the end of them.
+    ///
+    /// Before `DBMS_MIN_REVISION_WITH_SETTINGS_SERIALIZED_AS_STRINGS` values are sent in
+    /// a type dependent binary format, so only the settings known by this crate can be read.
+    pub(crate) fn read_from<R: Read>(reader: &mut R, client_revision: u64) -> Result<Settings> {
+        let mut settings = Settings::default();
+
+        loop {
+            let name = reader.read_string()?;
+            if name.is_empty() {
+                break;
+            }
+
+            let setting =
+                if client_revision >= DBMS_MIN_REVISION_WITH_SETTINGS_SERIALIZED_AS_STRINGS {
+                    let flags = reader.read_uvarint()?;
+                    let value = reader.read_string()?;
+                    let custom = flags & SETTING_FLAG_CUSTOM != 0;
+                    let value = match setting_type(&name) {
+                        Some(kind) if !custom => parse_typed(kind, value),
+                        _ => parse_untyped(value)
+                    };
+
+                    Setting {
+                        value,
+                        important: flags & SETTING_FLAG_IMPORTANT != 0,
+                        custom
+                    }
+                } else {
+                    let value = match setting_type(&name) {
+                        Some(SettingType::UInt64) => SettingValue::UInt64(reader.read_uvarint()?),
+                        Some(SettingType::Int64) => {
+                            let v = reader.read_uvarint()?;
+                            SettingValue::Int64((v >> 1) as i64 ^ -((v & 1) as i64))
+                        }
+                        Some(SettingType::Bool) => SettingValue::Bool(reader.read_uvarint()? != 0),
+                        Some(kind) => parse_typed(kind, reader.read_string()?),
+                        None => return Err(Error::Driver(DriverError::UnknownSetting { name }))
+                    };
+
+                    Setting {
+                        value,
+                        important: false,
+                        custom: false
+                    }
+                };
+
+            settings.settings.insert(name, setting);
+        }
+
+        Ok(settings)
+    }
+
+    /// Returns the setting with the given name, if it was sent by the client.
+    pub fn get(&self, name: &str) -> Option<&Setting> {
+        self.settings.get(name)
+    }
+
+    pub fn get_u64(&self, name: &str) -> Option<u64> {
+        match self.get(name)?.value {
+            SettingValue::UInt64(v) => Some(v),
+            SettingValue::Int64(v) if v >= 0 => Some(v as u64),
+            SettingValue::Bool(v) => Some(v as u64),
+            _ => None
+        }
+    }
+
+    pub fn get_i64(&self, name: &str) -> Option<i64> {
+        match self.get(name)?.value {
+            SettingValue::Int64(v) => Some(v),
+            SettingValue::UInt64(v) if v <= i64::MAX as u64 => Some(v as i64),
+            SettingValue::Bool(v) => Some(v as i64),
+            _ => None
+        }
+    }
+
+    pub fn get_f64(&self, name: &str) -> Option<f64> {
+        match self.get(name)?.value {
+            SettingValue::Float64(v) => Some(v),
+            SettingValue::UInt64(v) => Some(v as f64),
+            SettingValue::Int64(v) => Some(v as f64),
+            _ => None
+        }
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        match self.get(name)?.value {
+            SettingValue::Bool(v) => Some(v),
+            SettingValue::UInt64(v) => Some(v != 0),
+            SettingValue::Int64(v) => Some(v != 0),
+            _ => None
+        }
+    }
+
+    pub fn get_str(&self, name: &str) -> Option<&str> {
+        match self.get(name)?.value {
+            SettingValue::String(ref v) => Some(v.as_str()),
+            _ => None
+        }
+    }
+
+    /// Adds or replaces a setting.
+    pub fn set(&mut self, name: impl Into<String>, value: SettingValue) {
+        self.settings.insert(name.into(), Setting {
+            value,
+            important: false,
+            custom: false
+        });
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.settings.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.settings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.settings.is_empty()
+    }
+
+    pub fn iter(&self) -> hash_map::Iter<'_, String, Setting> {
+        self.settings.iter()
+    }
+}
+
+fn parse_typed(kind: SettingType, value: String) -> SettingValue {
+    let parsed = match kind {
+        SettingType::UInt64 => value.parse().ok().map(SettingValue::UInt64),
+        SettingType::Int64 => value.parse().ok().map(SettingValue::Int64),
+        SettingType::Float64 => value.parse().ok().map(SettingValue::Float64),
+        SettingType::Bool => match value.to_ascii_lowercase().as_str() {
+            "1" | "true" => Some(SettingValue::Bool(true)),
+            "0" | "false" => Some(SettingValue::Bool(false)),
+            _ => None
+        },
+        SettingType::String => return SettingValue::String(value)
+    };
+
+    // e.g. max_threads is sent as 'auto(8)'
+    parsed.unwrap_or_else(|| SettingValue::String(unquote(value)))
+}
+
+fn parse_untyped(value: String) -> SettingValue {
+    if let Ok(v) = value.parse() {
+        SettingValue::UInt64(v)
+    } else if let Ok(v) = value.parse() {
+        SettingValue::Int64(v)
+    } else if let Ok(v) = value.parse() {
+        SettingValue::Float64(v)
+    } else {
+        SettingValue::String(unquote(value))
+    }
+}
+
+fn unquote(value: String) -> String {
+    if value.len() >= 2 && value.starts_with('\'') && value.ends_with('\'') {
+        value[1..value.len() - 1].replace("\\'", "'")
+    } else {
+        value
+    }
+}
+
+fn setting_type(name: &str) -> Option<SettingType> {
+    Some(match name {
+        "min_compress_block_size"
+        | "max_compress_block_size"
+        | "max_block_size"
+        | "max_insert_block_size"
+        | "min_insert_block_size_rows"
+        | "min_insert_block_size_bytes"
+        | "max_joined_block_size_rows"
+        | "max_insert_threads"
+        | "max_final_threads"
+        | "max_threads"
+        | "max_alter_threads"
+        | "max_read_buffer_size"
+        | "max_distributed_connections"
+        | "max_query_size"
+        | "interactive_delay"
+        | "connect_timeout"
+        | "connect_timeout_with_failover_ms"
+        | "receive_timeout"
+        | "send_timeout"
+        | "tcp_keep_alive_timeout"
+        | "queue_max_wait_ms"
+        | "connection_pool_max_wait_ms"
+        | "replace_running_query_max_wait_ms"
+        | "poll_interval"
+        | "idle_connection_timeout"
+        | "distributed_connections_pool_size"
+        | "connections_with_failover_max_tries"
+        | "max_replica_delay_for_distributed_queries"
+        | "preferred_block_size_bytes"
+        | "preferred_max_column_in_block_size_bytes"
+        | "insert_quorum"
+        | "insert_quorum_timeout"
+        | "select_sequential_consistency"
+        | "table_function_remote_max_addresses"
+        | "max_parallel_replicas"
+        | "parallel_replicas_count"
+        | "parallel_replica_offset"
+        | "distributed_group_by_no_merge"
+        | "force_optimize_skip_unused_shards"
+        | "merge_tree_min_rows_for_concurrent_read"
+        | "merge_tree_min_bytes_for_concurrent_read"
+        | "merge_tree_min_rows_for_seek"
+        | "merge_tree_min_bytes_for_seek"
+        | "merge_tree_coarse_index_granularity"
+        | "merge_tree_max_rows_to_use_cache"
+        | "merge_tree_max_bytes_to_use_cache"
+        | "min_bytes_to_use_direct_io"
+        | "min_bytes_to_use_mmap_io"
+        | "mark_cache_min_lifetime"
+        | "priority"
+        | "log_queries_min_query_duration_ms"
+        | "log_queries_cut_to_length"
+        | "max_concurrent_queries_for_user"
+        | "max_rows_to_read"
+        | "max_bytes_to_read"
+        | "max_rows_to_group_by"
+        | "max_bytes_before_external_group_by"
+        | "max_rows_to_sort"
+        | "max_bytes_to_sort"
+        | "max_bytes_before_external_sort"
+        | "max_bytes_before_remerge_sort"
+        | "max_result_rows"
+        | "max_result_bytes"
+        | "max_execution_time"
+        | "min_execution_speed"
+        | "max_execution_speed"
+        | "min_execution_speed_bytes"
+        | "max_execution_speed_bytes"
+        | "timeout_before_checking_execution_speed"
+        | "max_columns_to_read"
+        | "max_temporary_columns"
+        | "max_temporary_non_const_columns"
+        | "max_subquery_depth"
+        | "max_pipeline_depth"
+        | "max_ast_depth"
+        | "max_ast_elements"
+        | "max_expanded_ast_elements"
+        | "max_parser_depth"
+        | "readonly"
+        | "max_rows_in_set"
+        | "max_bytes_in_set"
+        | "max_rows_in_join"
+        | "max_bytes_in_join"
+        | "max_rows_to_transfer"
+        | "max_bytes_to_transfer"
+        | "max_rows_in_distinct"
+        | "max_bytes_in_distinct"
+        | "max_memory_usage"
+        | "max_memory_usage_for_user"
+        | "max_untracked_memory"
+        | "memory_profiler_step"
+        | "max_network_bandwidth"
+        | "max_network_bytes"
+        | "max_network_bandwidth_for_user"
+        | "max_network_bandwidth_for_all_users"
+        | "max_partitions_per_insert_block"
+        | "max_http_get_redirects"
+        | "http_headers_progress_interval_ms"
+        | "input_format_allow_errors_num"
+        | "output_format_pretty_max_rows"
+        | "output_format_pretty_max_column_pad_width"
+        | "output_format_pretty_max_value_width"
+        | "query_profiler_real_time_period_ns"
+        | "query_profiler_cpu_time_period_ns"
+        | "mutations_sync"
+        | "replication_alter_partitions_sync"
+        | "distributed_replica_error_half_life"
+        | "distributed_replica_error_cap"
+        | "max_live_view_insert_blocks_before_refresh"
+        | "min_count_to_compile_expression"
+        | "group_by_two_level_threshold"
+        | "group_by_two_level_threshold_bytes"
+        | "aggregation_memory_efficient_merge_threads"
+        | "optimize_min_equality_disjunction_chain_length"
+        | "default_max_bytes_in_join"
+        | "max_rows_in_set_to_optimize_join"
+        | "max_hyperscan_regexp_length"
+        | "max_hyperscan_regexp_total_length"
+        | "stream_poll_timeout_ms" => SettingType::UInt64,
+
+        "network_zstd_compression_level"
+        | "http_zlib_compression_level"
+        | "os_thread_priority"
+        | "max_partitions_to_read" => SettingType::Int64,
+
+        "totals_auto_threshold"
+        | "max_streams_to_max_threads_ratio"
+        | "input_format_allow_errors_ratio"
+        | "memory_profiler_sample_probability" => SettingType::Float64,
+
+        "extremes"
+        | "use_uncompressed_cache"
+        | "replace_running_query"
+        | "optimize_move_to_prewhere"
+        | "compile_expressions"
+        | "log_queries"
+        | "log_query_threads"
+        | "log_profile_events"
+        | "log_query_settings"
+        | "insert_deduplicate"
+        | "insert_distributed_sync"
+        | "insert_null_as_default"
+        | "fsync_metadata"
+        | "join_use_nulls"
+        | "any_join_distinct_right_table_keys"
+        | "input_format_skip_unknown_fields"
+        | "input_format_with_names_use_header"
+        | "input_format_import_nested_json"
+        | "input_format_defaults_for_omitted_fields"
+        | "input_format_null_as_default"
+        | "input_format_values_interpret_expressions"
+        | "output_format_json_quote_64bit_integers"
+        | "output_format_json_quote_denormals"
+        | "output_format_write_statistics"
+        | "output_format_pretty_color"
+        | "output_format_enable_streaming"
+        | "use_index_for_in_with_subqueries"
+        | "empty_result_for_aggregation_by_empty_set"
+        | "allow_ddl"
+        | "allow_introspection_functions"
+        | "allow_nondeterministic_mutations"
+        | "allow_suspicious_low_cardinality_types"
+        | "allow_experimental_map_type"
+        | "allow_experimental_window_functions"
+        | "allow_experimental_live_view"
+        | "low_cardinality_allow_in_native_format"
+        | "low_cardinality_use_single_dictionary_for_part"
+        | "enable_optimize_predicate_expression"
+        | "enable_scalar_subquery_optimization"
+        | "enable_http_compression"
+        | "add_http_cors_header"
+        | "force_index_by_date"
+        | "force_primary_key"
+        | "skip_unavailable_shards"
+        | "optimize_skip_unused_shards"
+        | "optimize_aggregation_in_order"
+        | "transform_null_in"
+        | "partial_merge_join_optimizations"
+        | "cancel_http_readonly_queries_on_client_close"
+        | "send_progress_in_http_headers"
+        | "calculate_text_stack_trace"
+        | "async_socket_for_remote"
+        | "use_hedged_requests"
+        | "prefer_localhost_replica"
+        | "data_type_default_nullable"
+        | "cast_keep_nullable"
+        | "aggregate_functions_null_for_empty"
+        | "database_atomic_wait_for_drop_and_detach_synchronously"
+        | "distributed_aggregation_memory_efficient"
+        | "allow_hyperscan"
+        | "joined_subquery_requires_alias"
+        | "asterisk_include_materialized_columns"
+        | "asterisk_include_alias_columns" => SettingType::Bool,
+
+        "load_balancing"
+        | "totals_mode"
+        | "count_distinct_implementation"
+        | "network_compression_method"
+        | "distributed_product_mode"
+        | "read_overflow_mode"
+        | "group_by_overflow_mode"
+        | "sort_overflow_mode"
+        | "result_overflow_mode"
+        | "timeout_overflow_mode"
+        | "set_overflow_mode"
+        | "join_overflow_mode"
+        | "transfer_overflow_mode"
+        | "distinct_overflow_mode"
+        | "send_logs_level"
+        | "send_logs_source_regexp"
+        | "format_csv_delimiter"
+        | "format_csv_null_representation"
+        | "format_tsv_null_representation"
+        | "date_time_input_format"
+        | "date_time_output_format"
+        | "join_default_strictness"
+        | "join_algorithm"
+        | "format_schema"
+        | "format_template_resultset"
+        | "format_template_row"
+        | "format_template_rows_between_delimiter"
+        | "format_custom_escaping_rule"
+        | "format_custom_field_delimiter"
+        | "format_custom_row_before_delimiter"
+        | "format_custom_row_after_delimiter"
+        | "format_custom_row_between_delimiter"
+        | "format_custom_result_before_delimiter"
+        | "format_custom_result_after_delimiter"
+        | "format_regexp"
+        | "format_regexp_escaping_rule"
+        | "output_format_avro_codec"
+        | "output_format_pretty_grid_charset"
+        | "log_comment"
+        | "default_database_engine"
+        | "mysql_datatypes_support_level"
+        | "union_default_mode"
+        | "insert_deduplication_token"
+        | "session_timezone"
+        | "sql_dialect" => SettingType::String,
+
+        _ => return None
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::binary::Encoder;
+
+    #[test]
+    fn test_read_binary_settings() {
+        let mut encoder = Encoder::new();
+        encoder.string("max_block_size");
+        encoder.uvarint(65536);
+        encoder.string("extremes");
+        encoder.uvarint(1);
+        encoder.string("os_thread_priority");
+        encoder.uvarint(3); // zigzag -2
+        encoder.string("totals_auto_threshold");
+        encoder.string("0.5");
+        encoder.string("stream_poll_timeout_ms");
+        encoder.uvarint(10);
+        encoder.string("load_balancing");
+        encoder.string("random");
+        encoder.string("");
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let settings = Settings::read_from(&mut reader, 54428).unwrap();
+
+        assert_eq!(settings.len(), 6);
+        assert_eq!(settings.get_u64("stream_poll_timeout_ms"), Some(10));
+        assert_eq!(settings.get_u64("max_block_size"), Some(65536));
+        assert_eq!(settings.get_bool("extremes"), Some(true));
+        assert_eq!(settings.get_i64("os_thread_priority"), Some(-2));
+        assert_eq!(settings.get_f64("totals_auto_threshold"), Some(0.5));
+        assert_eq!(settings.get_str("load_balancing"), Some("random"));
+    }
+
+    #[test]
+    fn test_read_unknown_binary_setting() {
+        let mut encoder = Encoder::new();
+        encoder.string("some_unknown_setting");
+        encoder.uvarint(1);
+        encoder.string("");
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        match Settings::read_from(&mut reader, 54428) {
+            Err(Error::Driver(DriverError::UnknownSetting { name })) => {
+                assert_eq!(name, "some_unknown_setting")
+            }
+            _ => unreachable!()
+        }
+    }
+
+    #[test]
+    fn test_read_string_settings() {
+        let mut encoder = Encoder::new();
+        encoder.string("max_execution_time");
+        encoder.uvarint(SETTING_FLAG_IMPORTANT);
+        encoder.string("30");
+        encoder.string("readonly");
+        encoder.uvarint(0);
+        encoder.string("1");
+        encoder.string("max_threads");
+        encoder.uvarint(0);
+        encoder.string("'auto(8)'");
+        encoder.string("some_new_setting");
+        encoder.uvarint(0);
+        encoder.string("abc");
+        encoder.string("log_comment");
+        encoder.uvarint(0);
+        encoder.string("'quoted'");
+        encoder.string("custom_tenant");
+        encoder.uvarint(SETTING_FLAG_CUSTOM);
+        encoder.string("'acme'");
+        encoder.string("");
+
+        let mut reader = Cursor::new(encoder.get_buffer());
+        let settings = Settings::read_from(&mut reader, 54429).unwrap();
+
+        assert_eq!(settings.len(), 6);
+        assert_eq!(settings.get_u64("max_execution_time"), Some(30));
+        assert!(settings.get("max_execution_time").unwrap().important);
+        assert_eq!(settings.get_bool("readonly"), Some(true));
+        assert_eq!(settings.get_str("max_threads"), Some("auto(8)"));
+        assert_eq!(settings.get_str("some_new_setting"), Some("abc"));
+        assert_eq!(settings.get_str("log_comment"), Some("'quoted'"));
+
+        let custom = settings.get("custom_tenant").unwrap();
+        assert!(custom.custom);
+        assert_eq!(custom.value, SettingValue::String("acme".to_string()));
+    }
+}
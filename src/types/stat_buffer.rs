@@ -1,4 +1,6 @@
 use crate::types::SqlType;
+use crate::types::I256;
+use crate::types::U256;
 
 pub trait StatBuffer {
     type Buffer: AsMut<[u8]> + AsRef<[u8]> + Copy + Sync;
@@ -102,6 +104,54 @@ impl StatBuffer for i64 {
     }
 }
 
+impl StatBuffer for i128 {
+    type Buffer = [u8; 16];
+
+    fn buffer() -> Self::Buffer {
+        [0; 16]
+    }
+
+    fn sql_type() -> SqlType {
+        SqlType::Int128
+    }
+}
+
+impl StatBuffer for u128 {
+    type Buffer = [u8; 16];
+
+    fn buffer() -> Self::Buffer {
+        [0; 16]
+    }
+
+    fn sql_type() -> SqlType {
+        SqlType::UInt128
+    }
+}
+
+impl StatBuffer for I256 {
+    type Buffer = [u8; 32];
+
+    fn buffer() -> Self::Buffer {
+        [0; 32]
+    }
+
+    fn sql_type() -> SqlType {
+        SqlType::Int256
+    }
+}
+
+impl StatBuffer for U256 {
+    type Buffer = [u8; 32];
+
+    fn buffer() -> Self::Buffer {
+        [0; 32]
+    }
+
+    fn sql_type() -> SqlType {
+        SqlType::UInt256
+    }
+}
+
 impl StatBuffer for f32 {
     type Buffer = [u8; 4];
 
@@ -1,3 +1,6 @@
+use crate::types::I256;
+use crate::types::U256;
+
 pub trait Unmarshal<T: Copy> {
     fn unmarshal(scratch: &[u8]) -> T;
 }
@@ -70,6 +73,38 @@ impl Unmarshal<i64> for i64 {
     }
 }
 
+impl Unmarshal<i128> for i128 {
+    fn unmarshal(scratch: &[u8]) -> Self {
+        let mut buffer = [0; 16];
+        buffer.copy_from_slice(&scratch[..16]);
+        Self::from_le_bytes(buffer)
+    }
+}
+
+impl Unmarshal<u128> for u128 {
+    fn unmarshal(scratch: &[u8]) -> Self {
+        let mut buffer = [0; 16];
+        buffer.copy_from_slice(&scratch[..16]);
+        Self::from_le_bytes(buffer)
+    }
+}
+
+impl Unmarshal<I256> for I256 {
+    fn unmarshal(scratch: &[u8]) -> Self {
+        let mut buffer = [0; 32];
+        buffer.copy_from_slice(&scratch[..32]);
+        I256::from_le_bytes(buffer)
+    }
+}
+
+impl Unmarshal<U256> for U256 {
+    fn unmarshal(scratch: &[u8]) -> Self {
+        let mut buffer = [0; 32];
+        buffer.copy_from_slice(&scratch[..32]);
+        U256::from_le_bytes(buffer)
+    }
+}
+
 impl Unmarshal<f32> for f32 {
     fn unmarshal(scratch: &[u8]) -> Self {
         let bits = u32::from(scratch[0])
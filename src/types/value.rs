@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use std::collections::HashMap;
 use std::convert;
 use std::fmt;
 use std::mem;
@@ -14,12 +16,15 @@ use crate::types::column::datetime64::to_datetime;
 use crate::types::column::Either;
 use crate::types::decimal::Decimal;
 use crate::types::decimal::NoBits;
+use crate::types::decimal::Underlying;
 use crate::types::DateConverter;
 use crate::types::DateTimeType;
 use crate::types::Enum16;
 use crate::types::Enum8;
 use crate::types::HasSqlType;
 use crate::types::SqlType;
+use crate::types::I256;
+use crate::types::U256;
 
 pub(crate) type AppDateTime = DateTime<Tz>;
 pub(crate) type AppDate = Date<Tz>;
@@ -35,6 +40,10 @@ pub enum Value {
     Int16(i16),
     Int32(i32),
     Int64(i64),
+    Int128(i128),
+    UInt128(u128),
+    Int256(Box<I256>),
+    UInt256(Box<U256>),
     String(Arc<Vec<u8>>),
     Float32(f32),
     Float64(f64),
@@ -46,6 +55,8 @@ pub enum Value {
     Uuid([u8; 16]),
     Nullable(Either<&'static SqlType, Box<Value>>),
     Array(&'static SqlType, Arc<Vec<Value>>),
+    Tuple(Arc<Vec<Value>>),
+    Map(&'static SqlType, &'static SqlType, Arc<Vec<(Value, Value)>>),
     Decimal(Decimal),
     Enum8(Vec<(String, i8)>, Enum8),
     Enum16(Vec<(String, i16)>, Enum16)
@@ -62,6 +73,10 @@ impl PartialEq for Value {
             (Value::Int16(a), Value::Int16(b)) => *a == *b,
             (Value::Int32(a), Value::Int32(b)) => *a == *b,
             (Value::Int64(a), Value::Int64(b)) => *a == *b,
+            (Value::Int128(a), Value::Int128(b)) => *a == *b,
+            (Value::UInt128(a), Value::UInt128(b)) => *a == *b,
+            (Value::Int256(a), Value::Int256(b)) => *a == *b,
+            (Value::UInt256(a), Value::UInt256(b)) => *a == *b,
             (Value::String(a), Value::String(b)) => *a == *b,
             (Value::Float32(a), Value::Float32(b)) => *a == *b,
             (Value::Float64(a), Value::Float64(b)) => *a == *b,
@@ -77,6 +92,8 @@ impl PartialEq for Value {
             }
             (Value::Nullable(a), Value::Nullable(b)) => *a == *b,
             (Value::Array(ta, a), Value::Array(tb, b)) => *ta == *tb && *a == *b,
+            (Value::Tuple(a), Value::Tuple(b)) => *a == *b,
+            (Value::Map(ka, va, a), Value::Map(kb, vb, b)) => *ka == *kb && *va == *vb && *a == *b,
             (Value::Decimal(a), Value::Decimal(b)) => *a == *b,
             (Value::Enum16(values_a, val_a), Value::Enum16(values_b, val_b)) => {
                 *values_a == *values_b && *val_a == *val_b
@@ -97,6 +114,10 @@ impl Value {
             SqlType::Int16 => Value::Int16(0),
             SqlType::Int32 => Value::Int32(0),
             SqlType::Int64 => Value::Int64(0),
+            SqlType::Int128 => Value::Int128(0),
+            SqlType::UInt128 => Value::UInt128(0),
+            SqlType::Int256 => Value::Int256(Box::default()),
+            SqlType::UInt256 => Value::UInt256(Box::default()),
             SqlType::String => Value::String(Arc::new(Vec::default())),
             SqlType::FixedString(str_len) => Value::String(Arc::new(vec![0_u8; str_len])),
             SqlType::Float32 => Value::Float32(0.0),
@@ -108,11 +129,17 @@ impl Value {
             SqlType::DateTime(_) => 0_u32.to_date(Tz::Zulu).into(),
             SqlType::Nullable(inner) => Value::Nullable(Either::Left(inner)),
             SqlType::Array(inner) => Value::Array(inner, Arc::new(Vec::default())),
+            SqlType::LowCardinality(inner) => Value::default(inner.clone()),
+            SqlType::Tuple(inner) => {
+                let vs = inner.iter().map(|t| Value::default((*t).clone())).collect();
+                Value::Tuple(Arc::new(vs))
+            }
+            SqlType::Map(key, value) => Value::Map(key, value, Arc::new(Vec::default())),
             SqlType::Decimal(precision, scale) => Value::Decimal(Decimal {
-                underlying: 0,
+                underlying: Underlying::Narrow(0),
                 precision,
                 scale,
-                nobits: NoBits::N64
+                nobits: NoBits::from_precision(precision).unwrap_or(NoBits::N64)
             }),
             SqlType::Ipv4 => Value::Ipv4([0_u8; 4]),
             SqlType::Ipv6 => Value::Ipv6([0_u8; 16]),
@@ -134,6 +161,10 @@ impl fmt::Display for Value {
             Value::Int16(ref v) => fmt::Display::fmt(v, f),
             Value::Int32(ref v) => fmt::Display::fmt(v, f),
             Value::Int64(ref v) => fmt::Display::fmt(v, f),
+            Value::Int128(ref v) => fmt::Display::fmt(v, f),
+            Value::UInt128(ref v) => fmt::Display::fmt(v, f),
+            Value::Int256(ref v) => fmt::Display::fmt(v, f),
+            Value::UInt256(ref v) => fmt::Display::fmt(v, f),
             Value::String(ref v) => match str::from_utf8(v) {
                 Ok(s) => fmt::Display::fmt(s, f),
                 Err(_) => write!(f, "{:?}", v)
@@ -171,6 +202,14 @@ impl fmt::Display for Value {
                 let cells: Vec<String> = vs.iter().map(|v| format!("{}", v)).collect();
                 write!(f, "[{}]", cells.join(", "))
             }
+            Value::Tuple(vs) => {
+                let cells: Vec<String> = vs.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "({})", cells.join(", "))
+            }
+            Value::Map(_, _, vs) => {
+                let cells: Vec<String> = vs.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", cells.join(", "))
+            }
             Value::Decimal(v) => fmt::Display::fmt(v, f),
             Value::Ipv4(v) => {
                 write!(f, "{}", decode_ipv4(v))
@@ -204,6 +243,10 @@ impl convert::From<Value> for SqlType {
             Value::Int16(_) => SqlType::Int16,
             Value::Int32(_) => SqlType::Int32,
             Value::Int64(_) => SqlType::Int64,
+            Value::Int128(_) => SqlType::Int128,
+            Value::UInt128(_) => SqlType::UInt128,
+            Value::Int256(_) => SqlType::Int256,
+            Value::UInt256(_) => SqlType::UInt256,
             Value::String(_) => SqlType::String,
             Value::Float32(_) => SqlType::Float32,
             Value::Float64(_) => SqlType::Float64,
@@ -217,6 +260,11 @@ impl convert::From<Value> for SqlType {
                 }
             },
             Value::Array(t, _) => SqlType::Array(t),
+            Value::Tuple(vs) => {
+                let types = vs.iter().map(|v| SqlType::from(v.clone()).into()).collect();
+                SqlType::Tuple(types)
+            }
+            Value::Map(k, v, _) => SqlType::Map(k, v),
             Value::Decimal(v) => SqlType::Decimal(v.precision, v.scale),
             Value::Ipv4(_) => SqlType::Ipv4,
             Value::Ipv6(_) => SqlType::Ipv6,
@@ -312,6 +360,11 @@ value_from! {
     i32: Int32,
     i64: Int64,
 
+    i128: Int128,
+    u128: UInt128,
+    I256: Int256,
+    U256: UInt256,
+
     f32: Float32,
     f64: Float64,
 
@@ -325,6 +378,61 @@ impl<'a> convert::From<&'a str> for Value {
     }
 }
 
+macro_rules! value_from_tuple {
+    ( $( ( $( $t:ident : $i:tt ),* ) ),* ) => {
+        $(
+            impl<$( $t ),*> convert::From<($( $t, )*)> for Value
+            where
+                $( Value: convert::From<$t> ),*
+            {
+                fn from(v: ($( $t, )*)) -> Value {
+                    Value::Tuple(Arc::new(vec![$( v.$i.into() ),*]))
+                }
+            }
+        )*
+    };
+}
+
+value_from_tuple! {
+    (A: 0, B: 1),
+    (A: 0, B: 1, C: 2),
+    (A: 0, B: 1, C: 2, D: 3),
+    (A: 0, B: 1, C: 2, D: 3, E: 4),
+    (A: 0, B: 1, C: 2, D: 3, E: 4, F: 5)
+}
+
+impl<K, V, S> convert::From<HashMap<K, V, S>> for Value
+where
+    Value: convert::From<K> + convert::From<V>,
+    K: HasSqlType,
+    V: HasSqlType
+{
+    fn from(v: HashMap<K, V, S>) -> Value {
+        let vs = v.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+        Value::Map(
+            K::get_sql_type().into(),
+            V::get_sql_type().into(),
+            Arc::new(vs)
+        )
+    }
+}
+
+impl<K, V> convert::From<BTreeMap<K, V>> for Value
+where
+    Value: convert::From<K> + convert::From<V>,
+    K: HasSqlType,
+    V: HasSqlType
+{
+    fn from(v: BTreeMap<K, V>) -> Value {
+        let vs = v.into_iter().map(|(k, v)| (k.into(), v.into())).collect();
+        Value::Map(
+            K::get_sql_type().into(),
+            V::get_sql_type().into(),
+            Arc::new(vs)
+        )
+    }
+}
+
 impl convert::From<Value> for String {
     fn from(mut v: Value) -> Self {
         if let Value::String(ref mut x) = &mut v {
@@ -403,10 +511,32 @@ from_value! {
     i16: Int16,
     i32: Int32,
     i64: Int64,
+    i128: Int128,
+    u128: UInt128,
     f32: Float32,
     f64: Float64
 }
 
+impl convert::From<Value> for I256 {
+    fn from(v: Value) -> I256 {
+        if let Value::Int256(x) = v {
+            return *x;
+        }
+        let from = SqlType::from(v);
+        panic!("Can't convert Value::{} into {}", from, "I256")
+    }
+}
+
+impl convert::From<Value> for U256 {
+    fn from(v: Value) -> U256 {
+        if let Value::UInt256(x) = v {
+            return *x;
+        }
+        let from = SqlType::from(v);
+        panic!("Can't convert Value::{} into {}", from, "U256")
+    }
+}
+
 pub(crate) fn decode_ipv4(octets: &[u8; 4]) -> Ipv4Addr {
     let mut buffer = *octets;
     buffer.reverse();
@@ -599,6 +729,24 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_tuple_and_map() {
+        let tuple = Value::from((1_u8, "a"));
+        assert_eq!(
+            SqlType::from(tuple.clone()),
+            SqlType::Tuple(vec![&SqlType::UInt8, &SqlType::String])
+        );
+        assert_eq!(tuple.to_string(), "(1, a)");
+
+        let map: BTreeMap<&str, u32> = vec![("a", 1), ("b", 2)].into_iter().collect();
+        let map = Value::from(map);
+        assert_eq!(
+            SqlType::from(map.clone()),
+            SqlType::Map(&SqlType::String, &SqlType::UInt32)
+        );
+        assert_eq!(map.to_string(), "{a: 1, b: 2}");
+    }
+
     #[test]
     fn test_default_fixed_str() {
         for n in 0_usize..1000_usize {
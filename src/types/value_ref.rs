@@ -22,6 +22,8 @@ use crate::types::Enum16;
 use crate::types::Enum8;
 use crate::types::SqlType;
 use crate::types::Value;
+use crate::types::I256;
+use crate::types::U256;
 
 #[derive(Clone, Debug)]
 pub enum ValueRef<'a> {
@@ -33,6 +35,10 @@ pub enum ValueRef<'a> {
     Int16(i16),
     Int32(i32),
     Int64(i64),
+    Int128(i128),
+    UInt128(u128),
+    Int256(Box<I256>),
+    UInt256(Box<U256>),
     String(&'a [u8]),
     Float32(f32),
     Float64(f64),
@@ -41,6 +47,12 @@ pub enum ValueRef<'a> {
     DateTime64(i64, &'a (u32, Tz)),
     Nullable(Either<&'static SqlType, Box<ValueRef<'a>>>),
     Array(&'static SqlType, Arc<Vec<ValueRef<'a>>>),
+    Tuple(Arc<Vec<ValueRef<'a>>>),
+    Map(
+        &'static SqlType,
+        &'static SqlType,
+        Arc<Vec<(ValueRef<'a>, ValueRef<'a>)>>
+    ),
     Decimal(Decimal),
     Ipv4([u8; 4]),
     Ipv6([u8; 16]),
@@ -60,6 +72,10 @@ impl<'a> PartialEq for ValueRef<'a> {
             (ValueRef::Int16(a), ValueRef::Int16(b)) => *a == *b,
             (ValueRef::Int32(a), ValueRef::Int32(b)) => *a == *b,
             (ValueRef::Int64(a), ValueRef::Int64(b)) => *a == *b,
+            (ValueRef::Int128(a), ValueRef::Int128(b)) => *a == *b,
+            (ValueRef::UInt128(a), ValueRef::UInt128(b)) => *a == *b,
+            (ValueRef::Int256(a), ValueRef::Int256(b)) => *a == *b,
+            (ValueRef::UInt256(a), ValueRef::UInt256(b)) => *a == *b,
             (ValueRef::String(a), ValueRef::String(b)) => *a == *b,
             (ValueRef::Float32(a), ValueRef::Float32(b)) => *a == *b,
             (ValueRef::Float64(a), ValueRef::Float64(b)) => *a == *b,
@@ -75,6 +91,10 @@ impl<'a> PartialEq for ValueRef<'a> {
             }
             (ValueRef::Nullable(a), ValueRef::Nullable(b)) => *a == *b,
             (ValueRef::Array(ta, a), ValueRef::Array(tb, b)) => *ta == *tb && *a == *b,
+            (ValueRef::Tuple(a), ValueRef::Tuple(b)) => *a == *b,
+            (ValueRef::Map(ka, va, a), ValueRef::Map(kb, vb, b)) => {
+                *ka == *kb && *va == *vb && *a == *b
+            }
             (ValueRef::Decimal(a), ValueRef::Decimal(b)) => *a == *b,
             (ValueRef::Enum8(a0, a1), ValueRef::Enum8(b0, b1)) => *a1 == *b1 && *a0 == *b0,
             (ValueRef::Enum16(a0, a1), ValueRef::Enum16(b0, b1)) => *a1 == *b1 && *a0 == *b0,
@@ -103,6 +123,10 @@ impl<'a> fmt::Display for ValueRef<'a> {
             ValueRef::Int16(v) => fmt::Display::fmt(v, f),
             ValueRef::Int32(v) => fmt::Display::fmt(v, f),
             ValueRef::Int64(v) => fmt::Display::fmt(v, f),
+            ValueRef::Int128(v) => fmt::Display::fmt(v, f),
+            ValueRef::UInt128(v) => fmt::Display::fmt(v, f),
+            ValueRef::Int256(v) => fmt::Display::fmt(v, f),
+            ValueRef::UInt256(v) => fmt::Display::fmt(v, f),
             ValueRef::String(v) => match str::from_utf8(v) {
                 Ok(s) => fmt::Display::fmt(s, f),
                 Err(_) => write!(f, "{:?}", *v)
@@ -140,6 +164,14 @@ impl<'a> fmt::Display for ValueRef<'a> {
                 let cells: Vec<String> = vs.iter().map(|v| format!("{}", v)).collect();
                 write!(f, "[{}]", cells.join(", "))
             }
+            ValueRef::Tuple(vs) => {
+                let cells: Vec<String> = vs.iter().map(|v| format!("{}", v)).collect();
+                write!(f, "({})", cells.join(", "))
+            }
+            ValueRef::Map(_, _, vs) => {
+                let cells: Vec<String> = vs.iter().map(|(k, v)| format!("{}: {}", k, v)).collect();
+                write!(f, "{{{}}}", cells.join(", "))
+            }
             ValueRef::Decimal(v) => fmt::Display::fmt(v, f),
             ValueRef::Ipv4(v) => {
                 write!(f, "{}", decode_ipv4(v))
@@ -173,6 +205,10 @@ impl<'a> convert::From<ValueRef<'a>> for SqlType {
             ValueRef::Int16(_) => SqlType::Int16,
             ValueRef::Int32(_) => SqlType::Int32,
             ValueRef::Int64(_) => SqlType::Int64,
+            ValueRef::Int128(_) => SqlType::Int128,
+            ValueRef::UInt128(_) => SqlType::UInt128,
+            ValueRef::Int256(_) => SqlType::Int256,
+            ValueRef::UInt256(_) => SqlType::UInt256,
             ValueRef::String(_) => SqlType::String,
             ValueRef::Float32(_) => SqlType::Float32,
             ValueRef::Float64(_) => SqlType::Float64,
@@ -183,6 +219,11 @@ impl<'a> convert::From<ValueRef<'a>> for SqlType {
                 Either::Right(value_ref) => SqlType::Nullable(SqlType::from(*value_ref).into())
             },
             ValueRef::Array(t, _) => SqlType::Array(t),
+            ValueRef::Tuple(vs) => {
+                let types = vs.iter().map(|v| SqlType::from(v.clone()).into()).collect();
+                SqlType::Tuple(types)
+            }
+            ValueRef::Map(k, v, _) => SqlType::Map(k, v),
             ValueRef::Decimal(v) => SqlType::Decimal(v.precision, v.scale),
             ValueRef::Enum8(values, _) => SqlType::Enum8(values),
             ValueRef::Enum16(values, _) => SqlType::Enum16(values),
@@ -237,6 +278,10 @@ impl<'a> From<ValueRef<'a>> for Value {
             ValueRef::Int16(v) => Value::Int16(v),
             ValueRef::Int32(v) => Value::Int32(v),
             ValueRef::Int64(v) => Value::Int64(v),
+            ValueRef::Int128(v) => Value::Int128(v),
+            ValueRef::UInt128(v) => Value::UInt128(v),
+            ValueRef::Int256(v) => Value::Int256(v),
+            ValueRef::UInt256(v) => Value::UInt256(v),
             ValueRef::String(v) => Value::String(Arc::new(v.into())),
             ValueRef::Float32(v) => Value::Float32(v),
             ValueRef::Float64(v) => Value::Float64(v),
@@ -257,6 +302,17 @@ impl<'a> From<ValueRef<'a>> for Value {
                 }
                 Value::Array(t, Arc::new(value_list))
             }
+            ValueRef::Tuple(vs) => {
+                let value_list = vs.iter().map(|v| v.clone().into()).collect();
+                Value::Tuple(Arc::new(value_list))
+            }
+            ValueRef::Map(k, v, vs) => {
+                let value_list = vs
+                    .iter()
+                    .map(|(key, value)| (key.clone().into(), value.clone().into()))
+                    .collect();
+                Value::Map(k, v, Arc::new(value_list))
+            }
             ValueRef::Decimal(v) => Value::Decimal(v),
             ValueRef::Enum8(e_v, v) => Value::Enum8(e_v, v),
             ValueRef::Enum16(e_v, v) => Value::Enum16(e_v, v),
@@ -285,7 +341,7 @@ macro_rules! from_number {
         $(
             impl<'a> From<$t> for ValueRef<'a> {
                 fn from(v: $t) -> ValueRef<'static> {
-                    ValueRef::$k(v)
+                    ValueRef::$k(v.into())
                 }
             }
         )*
@@ -303,6 +359,11 @@ from_number! {
     i32: Int32,
     i64: Int64,
 
+    i128: Int128,
+    u128: UInt128,
+    I256: Int256,
+    U256: UInt256,
+
     f32: Float32,
     f64: Float64
 }
@@ -318,6 +379,10 @@ impl<'a> From<&'a Value> for ValueRef<'a> {
             Value::Int16(v) => ValueRef::Int16(*v),
             Value::Int32(v) => ValueRef::Int32(*v),
             Value::Int64(v) => ValueRef::Int64(*v),
+            Value::Int128(v) => ValueRef::Int128(*v),
+            Value::UInt128(v) => ValueRef::UInt128(*v),
+            Value::Int256(v) => ValueRef::Int256(v.clone()),
+            Value::UInt256(v) => ValueRef::UInt256(v.clone()),
             Value::String(v) => ValueRef::String(v),
             Value::Float32(v) => ValueRef::Float32(*v),
             Value::Float64(v) => ValueRef::Float64(*v),
@@ -339,6 +404,17 @@ impl<'a> From<&'a Value> for ValueRef<'a> {
                 }
                 ValueRef::Array(*t, Arc::new(ref_vec))
             }
+            Value::Tuple(vs) => {
+                let ref_vec = vs.iter().map(ValueRef::from).collect();
+                ValueRef::Tuple(Arc::new(ref_vec))
+            }
+            Value::Map(k, v, vs) => {
+                let ref_vec = vs
+                    .iter()
+                    .map(|(key, value)| (ValueRef::from(key), ValueRef::from(value)))
+                    .collect();
+                ValueRef::Map(k, v, Arc::new(ref_vec))
+            }
             Value::Decimal(v) => ValueRef::Decimal(v.clone()),
             Value::Enum8(values, v) => ValueRef::Enum8(values.to_vec(), *v),
             Value::Enum16(values, v) => ValueRef::Enum16(values.to_vec(), *v),
@@ -404,10 +480,33 @@ value_from! {
     i32: Int32,
     i64: Int64,
 
+    i128: Int128,
+    u128: UInt128,
+
     f32: Float32,
     f64: Float64
 }
 
+impl<'a> From<ValueRef<'a>> for I256 {
+    fn from(value: ValueRef<'a>) -> Self {
+        if let ValueRef::Int256(v) = value {
+            return *v;
+        }
+        let from = format!("{}", SqlType::from(value.clone()));
+        panic!("Can't convert ValueRef::{} into {}.", from, "I256")
+    }
+}
+
+impl<'a> From<ValueRef<'a>> for U256 {
+    fn from(value: ValueRef<'a>) -> Self {
+        if let ValueRef::UInt256(v) = value {
+            return *v;
+        }
+        let from = format!("{}", SqlType::from(value.clone()));
+        panic!("Can't convert ValueRef::{} into {}.", from, "U256")
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;